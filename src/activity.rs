@@ -0,0 +1,182 @@
+use std::{
+    collections::VecDeque,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+// older entries are dropped past this
+const LOG_CAPACITY: usize = 2000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level
+{
+    /// Chatty property changes, RSSI and advertising data.
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    /*
+     * The next minimum level shown, cycled from the log pane
+    */
+    pub fn next(self) -> Self {
+        match self {
+            Level::Debug => Level::Info,
+            Level::Info => Level::Warning,
+            Level::Warning => Level::Error,
+            Level::Error => Level::Debug,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // pad, so the levels line up with {:<5}
+        f.pad(match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warning => "WARN",
+            Level::Error => "ERROR",
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry
+{
+    pub time: SystemTime,
+    pub level: Level,
+    pub text: String,
+}
+
+/*
+ * What btui did and what BlueZ reported, written to from the UI and the background tasks alike
+*/
+#[derive(Clone, Default)]
+pub struct ActivityLog
+{
+    entries: Arc<Mutex<VecDeque<Entry>>>,
+}
+
+impl ActivityLog {
+    pub fn push(&self, level: Level, text: impl Into<String>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == LOG_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(Entry { time: SystemTime::now(), level, text: text.into() });
+    }
+
+    pub fn debug(&self, text: impl Into<String>) {
+        self.push(Level::Debug, text);
+    }
+
+    pub fn info(&self, text: impl Into<String>) {
+        self.push(Level::Info, text);
+    }
+
+    /// Entries at `level` or above, oldest first.
+    pub fn entries(&self, level: Level) -> Vec<Entry> {
+        self.entries.lock().unwrap().iter().filter(|entry| entry.level >= level).cloned().collect()
+    }
+
+    /*
+     * Writes every entry, debug ones included, one per line
+    */
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        for entry in self.entries(Level::Debug) {
+            writeln!(out, "{} {:<5} {}", timestamp(entry.time), entry.level, entry.text)?;
+        }
+        out.flush()
+    }
+}
+
+/*
+ * Where a saved log goes, e.g. ~/.cache/bluetooi/logs/btui-2024-06-10T140359Z.log
+*/
+pub fn save_path(time: SystemTime) -> Option<PathBuf>
+{
+    let mut path = dirs::cache_dir()?;
+    path.push(format!("bluetooi/logs/btui-{}.log", timestamp(time).replace(':', "")));
+    Some(path)
+}
+
+/*
+ * "14:03:59", UTC
+*/
+pub fn clock(time: SystemTime) -> String
+{
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    format!("{:02}:{:02}:{:02}", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60)
+}
+
+/*
+ * "2024-06-10T14:03:59Z"
+*/
+pub fn timestamp(time: SystemTime) -> String
+{
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (year, month, day) = civil_date(seconds / 86400);
+    format!("{:04}-{:02}-{:02}T{}Z", year, month, day, clock(time))
+}
+
+/*
+ * Days since 1970-01-01 to a calendar date, after Howard Hinnant's civil_from_days
+*/
+fn civil_date(days: u64) -> (u64, u64, u64)
+{
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn the_epoch() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(clock(UNIX_EPOCH), "00:00:00");
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(timestamp(at(1_709_210_096)), "2024-02-29T12:34:56Z");
+        assert_eq!(timestamp(at(1_709_210_096 + 86_400)), "2024-03-01T12:34:56Z");
+        // divisible by 400, a leap year
+        assert_eq!(timestamp(at(951_782_400)), "2000-02-29T00:00:00Z");
+        // divisible by 100 only, not one
+        assert_eq!(timestamp(at(4_107_542_399)), "2100-02-28T23:59:59Z");
+        assert_eq!(timestamp(at(4_107_542_400)), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn a_year_boundary() {
+        assert_eq!(timestamp(at(1_704_067_199)), "2023-12-31T23:59:59Z");
+        assert_eq!(timestamp(at(1_704_067_200)), "2024-01-01T00:00:00Z");
+        assert_eq!(clock(at(1_704_067_200)), "00:00:00");
+    }
+}
@@ -0,0 +1,181 @@
+use bluer::agent::{Agent, ReqError, ReqResult};
+use bluer::{Address, Uuid};
+use tokio::sync::{mpsc, oneshot};
+
+/*
+ * A request BlueZ made to btui's pairing agent, waiting for the user
+*/
+pub enum AgentRequest
+{
+    PinCode { device: Address, reply: oneshot::Sender<Option<String>> },
+    Passkey { device: Address, reply: oneshot::Sender<Option<u32>> },
+    DisplayPinCode { device: Address, pincode: String, cancel: oneshot::Receiver<()> },
+    DisplayPasskey { device: Address, passkey: u32, entered: u16, cancel: oneshot::Receiver<()> },
+    Confirmation { device: Address, passkey: u32, reply: oneshot::Sender<bool> },
+    Authorization { device: Address, reply: oneshot::Sender<bool> },
+    AuthorizeService { device: Address, service: Uuid, reply: oneshot::Sender<bool> },
+}
+
+impl AgentRequest {
+    pub fn device(&self) -> Address {
+        match self {
+            AgentRequest::PinCode { device, .. }
+            | AgentRequest::Passkey { device, .. }
+            | AgentRequest::DisplayPinCode { device, .. }
+            | AgentRequest::DisplayPasskey { device, .. }
+            | AgentRequest::Confirmation { device, .. }
+            | AgentRequest::Authorization { device, .. }
+            | AgentRequest::AuthorizeService { device, .. } => *device,
+        }
+    }
+
+    /*
+     * Display requests don't wait for an answer, a newer request simply replaces them
+    */
+    pub fn is_display(&self) -> bool {
+        matches!(self, AgentRequest::DisplayPinCode { .. } | AgentRequest::DisplayPasskey { .. })
+    }
+
+    /*
+     * BlueZ drops the pending call when it cancels a request, which closes our channel
+    */
+    pub fn is_cancelled(&mut self) -> bool {
+        match self {
+            AgentRequest::PinCode { reply, .. } => reply.is_closed(),
+            AgentRequest::Passkey { reply, .. } => reply.is_closed(),
+            AgentRequest::Confirmation { reply, .. }
+            | AgentRequest::Authorization { reply, .. }
+            | AgentRequest::AuthorizeService { reply, .. } => reply.is_closed(),
+            AgentRequest::DisplayPinCode { cancel, .. }
+            | AgentRequest::DisplayPasskey { cancel, .. } => !matches!(cancel.try_recv(), Err(oneshot::error::TryRecvError::Empty)),
+        }
+    }
+}
+
+/*
+ * Builds the agent registered with BlueZ, every request is forwarded to the UI through `requests`
+*/
+pub fn build_agent(requests: mpsc::UnboundedSender<AgentRequest>) -> Agent
+{
+    let pin_tx = requests.clone();
+    let passkey_tx = requests.clone();
+    let display_pin_tx = requests.clone();
+    let display_passkey_tx = requests.clone();
+    let confirmation_tx = requests.clone();
+    let authorization_tx = requests.clone();
+    let service_tx = requests;
+
+    Agent {
+        request_default: true,
+        request_pin_code: Some(Box::new(move |req| {
+            let (reply, answer) = oneshot::channel();
+            let sent = pin_tx.send(AgentRequest::PinCode { device: req.device, reply });
+            Box::pin(async move {
+                sent.map_err(|_| ReqError::Canceled)?;
+                answer.await.ok().flatten().ok_or(ReqError::Rejected)
+            })
+        })),
+        request_passkey: Some(Box::new(move |req| {
+            let (reply, answer) = oneshot::channel();
+            let sent = passkey_tx.send(AgentRequest::Passkey { device: req.device, reply });
+            Box::pin(async move {
+                sent.map_err(|_| ReqError::Canceled)?;
+                answer.await.ok().flatten().ok_or(ReqError::Rejected)
+            })
+        })),
+        display_pin_code: Some(Box::new(move |req| {
+            let sent = display_pin_tx.send(AgentRequest::DisplayPinCode { device: req.device, pincode: req.pincode, cancel: req.cancel });
+            Box::pin(async move { sent.map_err(|_| ReqError::Canceled) })
+        })),
+        display_passkey: Some(Box::new(move |req| {
+            let sent = display_passkey_tx.send(AgentRequest::DisplayPasskey { device: req.device, passkey: req.passkey, entered: req.entered, cancel: req.cancel });
+            Box::pin(async move { sent.map_err(|_| ReqError::Canceled) })
+        })),
+        request_confirmation: Some(Box::new(move |req| {
+            let (reply, answer) = oneshot::channel();
+            let sent = confirmation_tx.send(AgentRequest::Confirmation { device: req.device, passkey: req.passkey, reply });
+            Box::pin(async move { accepted(sent.is_ok(), answer).await })
+        })),
+        request_authorization: Some(Box::new(move |req| {
+            let (reply, answer) = oneshot::channel();
+            let sent = authorization_tx.send(AgentRequest::Authorization { device: req.device, reply });
+            Box::pin(async move { accepted(sent.is_ok(), answer).await })
+        })),
+        authorize_service: Some(Box::new(move |req| {
+            let (reply, answer) = oneshot::channel();
+            let sent = service_tx.send(AgentRequest::AuthorizeService { device: req.device, service: req.service, reply });
+            Box::pin(async move { accepted(sent.is_ok(), answer).await })
+        })),
+        ..Default::default()
+    }
+}
+
+async fn accepted(sent: bool, answer: oneshot::Receiver<bool>) -> ReqResult<()>
+{
+    if !sent {
+        return Err(ReqError::Canceled);
+    }
+    match answer.await {
+        Ok(true) => Ok(()),
+        _ => Err(ReqError::Rejected),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> Address {
+        "EE:00:00:00:00:01".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_answer_becomes_the_reply_to_bluez() {
+        let (reply, answer) = oneshot::channel();
+        reply.send(true).unwrap();
+        assert!(accepted(true, answer).await.is_ok());
+
+        let (reply, answer) = oneshot::channel();
+        reply.send(false).unwrap();
+        assert!(matches!(accepted(true, answer).await, Err(ReqError::Rejected)));
+
+        // the prompt went away without an answer
+        let (reply, answer) = oneshot::channel::<bool>();
+        drop(reply);
+        assert!(matches!(accepted(true, answer).await, Err(ReqError::Rejected)));
+
+        // nobody to ask
+        let (_reply, answer) = oneshot::channel();
+        assert!(matches!(accepted(false, answer).await, Err(ReqError::Canceled)));
+    }
+
+    #[test]
+    fn a_request_bluez_gave_up_on_is_cancelled() {
+        let (reply, answer) = oneshot::channel();
+        let mut request = AgentRequest::Confirmation { device: device(), passkey: 1, reply };
+        assert!(!request.is_cancelled());
+        // BlueZ cancelled or timed out the call, the agent's future holding `answer` is dropped
+        drop(answer);
+        assert!(request.is_cancelled());
+
+        let (reply, answer) = oneshot::channel();
+        let mut request = AgentRequest::Passkey { device: device(), reply };
+        drop(answer);
+        assert!(request.is_cancelled());
+    }
+
+    #[test]
+    fn a_display_request_is_cancelled_when_told_so() {
+        let (cancel, cancelled) = oneshot::channel();
+        let mut request = AgentRequest::DisplayPinCode { device: device(), pincode: "0000".to_string(), cancel: cancelled };
+        assert!(request.is_display());
+        assert!(!request.is_cancelled());
+        cancel.send(()).unwrap();
+        assert!(request.is_cancelled());
+
+        let (cancel, cancelled) = oneshot::channel::<()>();
+        let mut request = AgentRequest::DisplayPasskey { device: device(), passkey: 1, entered: 0, cancel: cancelled };
+        drop(cancel);
+        assert!(request.is_cancelled());
+    }
+}
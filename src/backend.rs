@@ -0,0 +1,612 @@
+use async_trait::async_trait;
+use bluer::{Adapter, AdapterEvent, AdapterProperty, Address, AddressType, DeviceEvent, DeviceProperty, DiscoveryFilter, DiscoveryTransport, ErrorKind, Modalias, Session, Uuid};
+use bluer::gatt::{CharacteristicFlags, remote::Characteristic};
+use futures::stream::{BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration
+};
+
+/*
+ * Snapshot of the properties btui reads from a device
+*/
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DeviceProperties
+{
+    pub name: Option<String>,
+    pub alias: Option<String>,
+    pub address_type: Option<AddressType>,
+    pub icon: Option<String>,
+    pub paired: bool,
+    pub connected: bool,
+    pub trusted: bool,
+    pub battery: Option<u8>,
+    /// Only reported while discovering, in dBm.
+    pub rssi: Option<i16>,
+    pub tx_power: Option<i16>,
+    /// Class of device, classic devices only.
+    pub class: Option<u32>,
+    /// GAP appearance, LE devices only.
+    pub appearance: Option<u16>,
+    pub modalias: Option<Modalias>,
+    /// Service UUIDs the device advertises.
+    pub uuids: BTreeSet<Uuid>,
+    pub manufacturer_data: BTreeMap<u16, Vec<u8>>,
+    pub service_data: BTreeMap<Uuid, Vec<u8>>,
+    pub legacy_pairing: bool,
+    pub blocked: bool,
+    pub services_resolved: bool,
+}
+
+impl DeviceProperties {
+    /*
+     * An advertisement carrying the data already known, what DuplicateData=false has BlueZ keep quiet about
+    */
+    pub fn repeats(&self, property: &DeviceProperty) -> bool {
+        match property {
+            DeviceProperty::ManufacturerData(data) => data.len() == self.manufacturer_data.len()
+                && data.iter().all(|(id, value)| self.manufacturer_data.get(id) == Some(value)),
+            DeviceProperty::ServiceData(data) => data.len() == self.service_data.len()
+                && data.iter().all(|(uuid, value)| self.service_data.get(uuid) == Some(value)),
+            DeviceProperty::TxPower(tx_power) => self.tx_power == Some(*tx_power),
+            _ => false,
+        }
+    }
+
+    pub fn apply(&mut self, property: DeviceProperty) {
+        match property {
+            DeviceProperty::Name(name) => self.name = Some(name),
+            DeviceProperty::Alias(alias) => self.alias = Some(alias),
+            DeviceProperty::AddressType(address_type) => self.address_type = Some(address_type),
+            DeviceProperty::Icon(icon) => self.icon = Some(icon),
+            DeviceProperty::Paired(paired) => self.paired = paired,
+            DeviceProperty::Connected(connected) => self.connected = connected,
+            DeviceProperty::Trusted(trusted) => self.trusted = trusted,
+            DeviceProperty::BatteryPercentage(battery) => self.battery = Some(battery),
+            DeviceProperty::Rssi(rssi) => self.rssi = Some(rssi),
+            DeviceProperty::TxPower(tx_power) => self.tx_power = Some(tx_power),
+            DeviceProperty::Class(class) => self.class = Some(class),
+            DeviceProperty::Appearance(appearance) => self.appearance = Some(appearance),
+            DeviceProperty::Modalias(modalias) => self.modalias = Some(modalias),
+            DeviceProperty::Uuids(uuids) => self.uuids = uuids.into_iter().collect(),
+            DeviceProperty::ManufacturerData(data) => self.manufacturer_data = data.into_iter().collect(),
+            DeviceProperty::ServiceData(data) => self.service_data = data.into_iter().collect(),
+            DeviceProperty::LegacyPairing(legacy_pairing) => self.legacy_pairing = legacy_pairing,
+            DeviceProperty::Blocked(blocked) => self.blocked = blocked,
+            DeviceProperty::ServicesResolved(resolved) => self.services_resolved = resolved,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport
+{
+    /// LE and BR/EDR, whatever the controller has enabled.
+    #[default]
+    Auto,
+    /// Bluetooth Low Energy only.
+    Le,
+    /// Classic Bluetooth only.
+    BrEdr,
+}
+
+/*
+ * What a scan reports, BlueZ applies it to the devices it finds.
+ * Devices it already knew are reported anyway, `matches` sorts those out.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanFilter
+{
+    pub transport: Transport,
+    /// In dBm, can't be combined with max_pathloss.
+    pub min_rssi: Option<i16>,
+    /// In dB, only devices advertising their TX power can be checked.
+    pub max_pathloss: Option<u16>,
+    /// Devices advertising any of them, every device when empty.
+    pub uuids: BTreeSet<Uuid>,
+    /// Prefix of the name or the address.
+    pub pattern: Option<String>,
+    /// Report every advertisement, not only the ones with new data.
+    /// bluer can't pass false on to BlueZ, manager::watch_device drops the repeats itself.
+    pub duplicate_data: bool,
+}
+
+impl Default for ScanFilter {
+    // BlueZ's own defaults
+    fn default() -> Self {
+        Self {
+            transport: Transport::Auto,
+            min_rssi: None,
+            max_pathloss: None,
+            uuids: BTreeSet::new(),
+            pattern: None,
+            duplicate_data: true,
+        }
+    }
+}
+
+impl ScanFilter {
+    pub fn is_default(&self) -> bool {
+        *self == ScanFilter::default()
+    }
+
+    pub fn matches(&self, address: Address, device: &DeviceProperties) -> bool {
+        let transport = !matches!(
+            (self.transport, device.address_type),
+            (Transport::Le, Some(AddressType::BrEdr)) | (Transport::BrEdr, Some(AddressType::LePublic | AddressType::LeRandom))
+        );
+        let rssi = self.min_rssi.is_none_or(|min| device.rssi.is_some_and(|rssi| rssi >= min));
+        let pathloss = match (self.max_pathloss, device.tx_power, device.rssi) {
+            (Some(max), Some(tx_power), Some(rssi)) => tx_power - rssi <= max as i16,
+            (Some(_), _, _) => false,
+            (None, _, _) => true,
+        };
+        let uuids = self.uuids.is_empty() || !self.uuids.is_disjoint(&device.uuids);
+        let pattern = self.pattern.as_deref().is_none_or(|pattern| {
+            address.to_string().starts_with(&pattern.to_uppercase())
+                || [&device.alias, &device.name].into_iter().flatten().any(|name| name.starts_with(pattern))
+        });
+        transport && rssi && pathloss && uuids && pattern
+    }
+
+    fn to_discovery_filter(&self) -> DiscoveryFilter {
+        DiscoveryFilter {
+            uuids: self.uuids.iter().copied().collect(),
+            rssi: self.min_rssi,
+            pathloss: self.max_pathloss,
+            transport: match self.transport {
+                Transport::Auto => DiscoveryTransport::Auto,
+                Transport::Le => DiscoveryTransport::Le,
+                Transport::BrEdr => DiscoveryTransport::BrEdr,
+            },
+            // bluer leaves a false value out and BlueZ goes back to true, the repeats are dropped in manager::watch_device
+            duplicate_data: true,
+            pattern: self.pattern.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/*
+ * A full UUID, or the 16 or 32 bit short form of a Bluetooth assigned number, e.g. "180d"
+*/
+pub fn parse_uuid(text: &str) -> Option<Uuid>
+{
+    let text = text.trim().trim_start_matches("0x");
+    match text.len() {
+        4 | 8 => u32::from_str_radix(text, 16).ok().map(|short| Uuid::from_u128(((short as u128) << 96) | 0x0000_0000_0000_1000_8000_0080_5f9b_34fb)),
+        _ => text.parse().ok(),
+    }
+}
+
+/*
+ * Snapshot of the properties btui reads from an adapter
+*/
+#[derive(Clone, Debug, Default)]
+pub struct AdapterProperties
+{
+    pub address: Option<Address>,
+    pub address_type: Option<AddressType>,
+    /// Name set by the system, the alias defaults to it.
+    pub system_name: Option<String>,
+    pub alias: Option<String>,
+    pub class: Option<u32>,
+    pub modalias: Option<Modalias>,
+    pub uuids: BTreeSet<Uuid>,
+    /// e.g. "central", "peripheral", empty when BlueZ doesn't say.
+    pub roles: Vec<String>,
+    pub powered: bool,
+    pub discoverable: bool,
+    /// In seconds, 0 means forever.
+    pub discoverable_timeout: u32,
+    pub pairable: bool,
+    pub pairable_timeout: u32,
+    pub discovering: bool,
+}
+
+impl AdapterProperties {
+    pub fn apply(&mut self, property: AdapterProperty) {
+        match property {
+            AdapterProperty::Address(address) => self.address = Some(address),
+            AdapterProperty::AddressType(address_type) => self.address_type = Some(address_type),
+            AdapterProperty::SystemName(name) => self.system_name = Some(name),
+            AdapterProperty::Alias(alias) => self.alias = Some(alias),
+            AdapterProperty::Class(class) => self.class = Some(class),
+            AdapterProperty::Modalias(modalias) => self.modalias = Some(modalias),
+            AdapterProperty::Uuids(uuids) => self.uuids = uuids.into_iter().collect(),
+            AdapterProperty::Powered(powered) => self.powered = powered,
+            AdapterProperty::Discoverable(discoverable) => self.discoverable = discoverable,
+            AdapterProperty::DiscoverableTimeout(timeout) => self.discoverable_timeout = timeout,
+            AdapterProperty::Pairable(pairable) => self.pairable = pairable,
+            AdapterProperty::PairableTimeout(timeout) => self.pairable_timeout = timeout,
+            AdapterProperty::Discovering(discovering) => self.discovering = discovering,
+            _ => {}
+        }
+    }
+}
+
+/*
+ * A characteristic, or one of its descriptors, on a device
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GattHandle
+{
+    pub service: u16,
+    pub characteristic: u16,
+    pub descriptor: Option<u16>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GattService
+{
+    pub id: u16,
+    pub uuid: Uuid,
+    pub primary: bool,
+    pub characteristics: Vec<GattCharacteristic>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GattCharacteristic
+{
+    pub id: u16,
+    pub uuid: Uuid,
+    pub flags: CharacteristicFlags,
+    pub descriptors: Vec<GattDescriptor>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GattDescriptor
+{
+    pub id: u16,
+    pub uuid: Uuid,
+}
+
+/*
+ * Everything btui needs from a Bluetooth adapter.
+ * BluerBackend talks to bluetoothd, fake::FakeBackend is an in-memory adapter for tests.
+*/
+#[async_trait]
+pub trait Backend: Clone + Send + Sync + 'static
+{
+    fn adapter_name(&self) -> &str;
+
+    /// Names of every adapter present, e.g. ["hci0", "hci1"].
+    async fn adapter_names(&self) -> bluer::Result<Vec<String>>;
+    /// The same backend, talking to another adapter.
+    async fn for_adapter(&self, name: &str) -> bluer::Result<Self>;
+
+    async fn is_powered(&self) -> bluer::Result<bool>;
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()>;
+    async fn adapter_properties(&self) -> bluer::Result<AdapterProperties>;
+    async fn set_discoverable(&self, discoverable: bool) -> bluer::Result<()>;
+    async fn set_discoverable_timeout(&self, seconds: u32) -> bluer::Result<()>;
+    async fn set_pairable(&self, pairable: bool) -> bluer::Result<()>;
+    async fn set_pairable_timeout(&self, seconds: u32) -> bluer::Result<()>;
+    /// An empty alias goes back to the system name.
+    async fn set_adapter_alias(&self, alias: String) -> bluer::Result<()>;
+
+    /// Starts discovery, which lasts as long as the returned stream is alive.
+    async fn discover_devices(&self, filter: &ScanFilter) -> bluer::Result<BoxStream<'static, AdapterEvent>>;
+    /// Devices added/removed and adapter property changes, without starting discovery.
+    async fn adapter_events(&self) -> bluer::Result<BoxStream<'static, AdapterEvent>>;
+
+    /// Every device BlueZ knows on this adapter, paired or only seen.
+    async fn device_addresses(&self) -> bluer::Result<Vec<Address>>;
+    async fn device_properties(&self, address: Address) -> bluer::Result<DeviceProperties>;
+    async fn device_events(&self, address: Address) -> bluer::Result<BoxStream<'static, DeviceEvent>>;
+
+    async fn pair(&self, address: Address) -> bluer::Result<()>;
+    /// Stops a pairing in progress, dropping the `pair` call leaves BlueZ going on.
+    async fn cancel_pairing(&self, address: Address) -> bluer::Result<()>;
+    async fn connect(&self, address: Address) -> bluer::Result<()>;
+    async fn disconnect(&self, address: Address) -> bluer::Result<()>;
+    async fn set_trusted(&self, address: Address, trusted: bool) -> bluer::Result<()>;
+    /// A blocked device is disconnected and every connection from it refused.
+    async fn set_blocked(&self, address: Address, blocked: bool) -> bluer::Result<()>;
+    /// An empty alias goes back to the name the device reports.
+    async fn set_alias(&self, address: Address, alias: String) -> bluer::Result<()>;
+    async fn remove_device(&self, address: Address) -> bluer::Result<()>;
+
+    /// GATT services of a connected device, with their characteristics and descriptors.
+    async fn gatt_services(&self, address: Address) -> bluer::Result<Vec<GattService>>;
+    async fn gatt_read(&self, address: Address, handle: GattHandle) -> bluer::Result<Vec<u8>>;
+    async fn gatt_write(&self, address: Address, handle: GattHandle, value: Vec<u8>) -> bluer::Result<()>;
+    /// Values notified by a characteristic, notifications stop when the stream is dropped.
+    async fn gatt_notify(&self, address: Address, handle: GattHandle) -> bluer::Result<BoxStream<'static, Vec<u8>>>;
+}
+
+#[derive(Clone)]
+pub struct BluerBackend
+{
+    session: Session,
+    adapter: Adapter,
+}
+
+impl BluerBackend {
+    /*
+     * Uses the adapter called `name`, or the default one
+    */
+    pub async fn new(session: &Session, name: Option<&str>) -> bluer::Result<Self> {
+        let adapter = match name {
+            Some(name) => {
+                if !session.adapter_names().await?.iter().any(|n| n == name) {
+                    return Err(bluer::Error { kind: ErrorKind::NotFound, message: format!("no adapter named {}", name) });
+                }
+                session.adapter(name)?
+            }
+            None => session.default_adapter().await?,
+        };
+        Ok(Self { session: session.clone(), adapter })
+    }
+
+    async fn characteristic(&self, address: Address, handle: GattHandle) -> bluer::Result<Characteristic> {
+        self.adapter.device(address)?.service(handle.service).await?.characteristic(handle.characteristic).await
+    }
+
+    /*
+     * bluer doesn't know the Roles property, it is read over a connection of our own.
+     * Older BlueZ versions don't have it either, that leaves the list empty.
+    */
+    async fn roles(&self) -> Vec<String> {
+        use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+
+        let Ok((resource, connection)) = dbus_tokio::connection::new_system_sync() else {
+            return vec![];
+        };
+        let task = tokio::spawn(async move {
+            let _ = resource.await;
+        });
+        let path = format!("/org/bluez/{}", self.adapter.name());
+        let proxy = dbus::nonblock::Proxy::new("org.bluez", path, Duration::from_secs(5), connection);
+        let roles = proxy.get::<Vec<String>>("org.bluez.Adapter1", "Roles").await.unwrap_or_default();
+        task.abort();
+        roles
+    }
+}
+
+#[async_trait]
+impl Backend for BluerBackend
+{
+    fn adapter_name(&self) -> &str {
+        self.adapter.name()
+    }
+
+    async fn adapter_names(&self) -> bluer::Result<Vec<String>> {
+        let mut names = self.session.adapter_names().await?;
+        names.sort();
+        Ok(names)
+    }
+
+    async fn for_adapter(&self, name: &str) -> bluer::Result<Self> {
+        BluerBackend::new(&self.session, Some(name)).await
+    }
+
+    async fn is_powered(&self) -> bluer::Result<bool> {
+        self.adapter.is_powered().await
+    }
+
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+        self.adapter.set_powered(powered).await
+    }
+
+    async fn adapter_properties(&self) -> bluer::Result<AdapterProperties> {
+        let mut properties = AdapterProperties::default();
+        for property in self.adapter.all_properties().await? {
+            properties.apply(property);
+        }
+        properties.roles = self.roles().await;
+        Ok(properties)
+    }
+
+    async fn set_discoverable(&self, discoverable: bool) -> bluer::Result<()> {
+        self.adapter.set_discoverable(discoverable).await
+    }
+
+    async fn set_discoverable_timeout(&self, seconds: u32) -> bluer::Result<()> {
+        self.adapter.set_discoverable_timeout(seconds).await
+    }
+
+    async fn set_pairable(&self, pairable: bool) -> bluer::Result<()> {
+        self.adapter.set_pairable(pairable).await
+    }
+
+    async fn set_pairable_timeout(&self, seconds: u32) -> bluer::Result<()> {
+        self.adapter.set_pairable_timeout(seconds).await
+    }
+
+    async fn set_adapter_alias(&self, alias: String) -> bluer::Result<()> {
+        self.adapter.set_alias(alias).await
+    }
+
+    async fn discover_devices(&self, filter: &ScanFilter) -> bluer::Result<BoxStream<'static, AdapterEvent>> {
+        self.adapter.set_discovery_filter(filter.to_discovery_filter()).await?;
+        Ok(self.adapter.discover_devices().await?.boxed())
+    }
+
+    async fn adapter_events(&self) -> bluer::Result<BoxStream<'static, AdapterEvent>> {
+        Ok(self.adapter.events().await?.boxed())
+    }
+
+    async fn device_addresses(&self) -> bluer::Result<Vec<Address>> {
+        self.adapter.device_addresses().await
+    }
+
+    async fn device_properties(&self, address: Address) -> bluer::Result<DeviceProperties> {
+        let device = self.adapter.device(address)?;
+        let mut properties = DeviceProperties::default();
+        for property in device.all_properties().await? {
+            properties.apply(property);
+        }
+        Ok(properties)
+    }
+
+    async fn device_events(&self, address: Address) -> bluer::Result<BoxStream<'static, DeviceEvent>> {
+        Ok(self.adapter.device(address)?.events().await?.boxed())
+    }
+
+    async fn pair(&self, address: Address) -> bluer::Result<()> {
+        self.adapter.device(address)?.pair().await
+    }
+
+    /*
+     * bluer has no CancelPairing either
+    */
+    async fn cancel_pairing(&self, address: Address) -> bluer::Result<()> {
+        let failed = |err: dbus::Error| bluer::Error { kind: ErrorKind::Failed, message: err.to_string() };
+        let (resource, connection) = dbus_tokio::connection::new_system_sync().map_err(failed)?;
+        let task = tokio::spawn(async move {
+            let _ = resource.await;
+        });
+        let path = format!("/org/bluez/{}/dev_{}", self.adapter.name(), address.to_string().replace(':', "_"));
+        let proxy = dbus::nonblock::Proxy::new("org.bluez", path, Duration::from_secs(5), connection);
+        let result = proxy.method_call::<(), _, _, _>("org.bluez.Device1", "CancelPairing", ()).await;
+        task.abort();
+        result.map_err(failed)
+    }
+
+    async fn connect(&self, address: Address) -> bluer::Result<()> {
+        self.adapter.device(address)?.connect().await
+    }
+
+    async fn disconnect(&self, address: Address) -> bluer::Result<()> {
+        self.adapter.device(address)?.disconnect().await
+    }
+
+    async fn set_trusted(&self, address: Address, trusted: bool) -> bluer::Result<()> {
+        self.adapter.device(address)?.set_trusted(trusted).await
+    }
+
+    async fn set_blocked(&self, address: Address, blocked: bool) -> bluer::Result<()> {
+        self.adapter.device(address)?.set_blocked(blocked).await
+    }
+
+    async fn set_alias(&self, address: Address, alias: String) -> bluer::Result<()> {
+        self.adapter.device(address)?.set_alias(alias).await
+    }
+
+    async fn remove_device(&self, address: Address) -> bluer::Result<()> {
+        self.adapter.remove_device(address).await
+    }
+
+    async fn gatt_services(&self, address: Address) -> bluer::Result<Vec<GattService>> {
+        let mut services = vec![];
+        for service in self.adapter.device(address)?.services().await? {
+            let mut characteristics = vec![];
+            for characteristic in service.characteristics().await? {
+                let mut descriptors = vec![];
+                for descriptor in characteristic.descriptors().await? {
+                    descriptors.push(GattDescriptor { id: descriptor.id(), uuid: descriptor.uuid().await? });
+                }
+                characteristics.push(GattCharacteristic {
+                    id: characteristic.id(),
+                    uuid: characteristic.uuid().await?,
+                    flags: characteristic.flags().await?,
+                    descriptors,
+                });
+            }
+            services.push(GattService { id: service.id(), uuid: service.uuid().await?, primary: service.primary().await?, characteristics });
+        }
+        Ok(services)
+    }
+
+    async fn gatt_read(&self, address: Address, handle: GattHandle) -> bluer::Result<Vec<u8>> {
+        let characteristic = self.characteristic(address, handle).await?;
+        match handle.descriptor {
+            Some(id) => characteristic.descriptor(id).await?.read().await,
+            None => characteristic.read().await,
+        }
+    }
+
+    async fn gatt_write(&self, address: Address, handle: GattHandle, value: Vec<u8>) -> bluer::Result<()> {
+        let characteristic = self.characteristic(address, handle).await?;
+        match handle.descriptor {
+            Some(id) => characteristic.descriptor(id).await?.write(&value).await,
+            None => characteristic.write(&value).await,
+        }
+    }
+
+    async fn gatt_notify(&self, address: Address, handle: GattHandle) -> bluer::Result<BoxStream<'static, Vec<u8>>> {
+        Ok(self.characteristic(address, handle).await?.notify().await?.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEART_RATE: &str = "0000180d-0000-1000-8000-00805f9b34fb";
+
+    fn address() -> Address {
+        "EE:00:00:00:00:01".parse().unwrap()
+    }
+
+    fn heart_rate_monitor() -> DeviceProperties {
+        DeviceProperties {
+            name: Some("Polar H10".to_string()),
+            address_type: Some(AddressType::LeRandom),
+            rssi: Some(-70),
+            tx_power: Some(-10),
+            uuids: [HEART_RATE.parse().unwrap()].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn short_uuids_are_bluetooth_assigned_numbers() {
+        assert_eq!(parse_uuid("180d"), Some(HEART_RATE.parse().unwrap()));
+        assert_eq!(parse_uuid("0x180D"), Some(HEART_RATE.parse().unwrap()));
+        assert_eq!(parse_uuid("0000180d"), Some(HEART_RATE.parse().unwrap()));
+        assert_eq!(parse_uuid(HEART_RATE), Some(HEART_RATE.parse().unwrap()));
+        assert_eq!(parse_uuid("180"), None);
+        assert_eq!(parse_uuid("zzzz"), None);
+        assert_eq!(parse_uuid("heart rate"), None);
+    }
+
+    #[test]
+    fn the_default_filter_matches_anything() {
+        assert!(ScanFilter::default().matches(address(), &heart_rate_monitor()));
+        assert!(ScanFilter::default().matches(address(), &DeviceProperties::default()));
+    }
+
+    #[test]
+    fn each_part_of_the_filter_is_checked() {
+        let device = heart_rate_monitor();
+        let matches = |filter: ScanFilter| filter.matches(address(), &device);
+
+        assert!(matches(ScanFilter { transport: Transport::Le, ..Default::default() }));
+        assert!(!matches(ScanFilter { transport: Transport::BrEdr, ..Default::default() }));
+        assert!(matches(ScanFilter { min_rssi: Some(-70), ..Default::default() }));
+        assert!(!matches(ScanFilter { min_rssi: Some(-60), ..Default::default() }));
+        // -10 dBm sent, -70 dBm received
+        assert!(matches(ScanFilter { max_pathloss: Some(60), ..Default::default() }));
+        assert!(!matches(ScanFilter { max_pathloss: Some(59), ..Default::default() }));
+        assert!(matches(ScanFilter { uuids: [parse_uuid("180d").unwrap(), parse_uuid("180f").unwrap()].into(), ..Default::default() }));
+        assert!(!matches(ScanFilter { uuids: [parse_uuid("180f").unwrap()].into(), ..Default::default() }));
+        assert!(matches(ScanFilter { pattern: Some("Polar".to_string()), ..Default::default() }));
+        assert!(matches(ScanFilter { pattern: Some("ee:00".to_string()), ..Default::default() }));
+        assert!(!matches(ScanFilter { pattern: Some("H10".to_string()), ..Default::default() }));
+    }
+
+    #[test]
+    fn thresholds_need_the_values_they_check() {
+        let device = DeviceProperties { rssi: None, tx_power: None, ..heart_rate_monitor() };
+        assert!(!ScanFilter { min_rssi: Some(-100), ..Default::default() }.matches(address(), &device));
+        assert!(!ScanFilter { max_pathloss: Some(100), ..Default::default() }.matches(address(), &device));
+    }
+
+    #[test]
+    fn repeated_advertising_data_is_recognised() {
+        let device = DeviceProperties { manufacturer_data: [(0x004c, vec![1, 2])].into(), ..heart_rate_monitor() };
+
+        assert!(device.repeats(&DeviceProperty::ManufacturerData([(0x004c, vec![1, 2])].into())));
+        assert!(!device.repeats(&DeviceProperty::ManufacturerData([(0x004c, vec![1, 3])].into())));
+        assert!(!device.repeats(&DeviceProperty::ManufacturerData([(0x004c, vec![1, 2]), (0x0006, vec![])].into())));
+        assert!(!device.repeats(&DeviceProperty::ServiceData([(parse_uuid("180d").unwrap(), vec![0])].into())));
+        assert!(device.repeats(&DeviceProperty::TxPower(-10)));
+        assert!(!device.repeats(&DeviceProperty::Rssi(-70)), "only the advertised data counts");
+    }
+}
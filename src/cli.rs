@@ -0,0 +1,513 @@
+use btui::activity::ActivityLog;
+use btui::agent::{self, AgentRequest};
+use btui::backend::{self, Backend, BluerBackend, ScanFilter, Transport};
+use btui::error::Error;
+use btui::manager::{self, ConnectionState, DeviceInfo};
+use btui::rfkill::{Block, Rfkill};
+use btui::store::DeviceStore;
+use crate::prompt::AgentPrompt;
+use bluer::{Address, Session};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::{
+    process::ExitCode,
+    sync::{Arc, Mutex}
+};
+use tokio::{sync::{mpsc, oneshot}, time::Duration};
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  1  the operation failed
+  2  invalid arguments
+  3  no device matches, or the name matches several devices
+  4  the adapter is missing or powered off";
+
+#[derive(Parser)]
+#[command(version, about = "Terminal UI for managing Bluetooth devices", after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Adapter to use, e.g. hci1 (defaults to BlueZ's default adapter)
+    #[arg(long, global = true)]
+    pub adapter: Option<String>,
+
+    /// Run a single command instead of starting the TUI
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List the paired devices
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Scan for nearby devices and list them
+    Scan {
+        /// How long to scan, in seconds (defaults to scan_timeout from the config file)
+        #[arg(long)]
+        timeout: Option<u64>,
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Connect to a device, pairing it first if needed
+    Connect { device: String },
+    /// Disconnect from a device
+    Disconnect { device: String },
+    /// Pair with a device
+    Pair { device: String },
+    /// Let a device connect without asking
+    Trust { device: String },
+    /// Ask again before a device connects
+    Untrust { device: String },
+    /// Refuse every connection from a device
+    Block { device: String },
+    /// Let a blocked device connect again
+    Unblock { device: String },
+    /// Tag a paired device, e.g. `btui tag Headphones work travel`
+    Tag {
+        device: String,
+        #[arg(required = true)]
+        tags: Vec<String>,
+        /// Take the tags off instead
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Write a note about a paired device, an empty one clears it
+    Note { device: String, text: String },
+    /// Remove a device from BlueZ
+    Forget { device: String },
+    /// Turn the adapter on or off
+    Power { state: PowerState },
+}
+
+/*
+ * Discovery filter of `scan`, without any of them the last filter used is kept
+*/
+#[derive(Args)]
+pub struct FilterArgs {
+    /// Radio to scan with
+    #[arg(long, value_enum)]
+    transport: Option<TransportArg>,
+    /// Only devices received at least this strong, in dBm
+    #[arg(long, allow_negative_numbers = true, conflicts_with = "pathloss")]
+    rssi: Option<i16>,
+    /// Only devices whose path loss is at most this, in dB
+    #[arg(long)]
+    pathloss: Option<u16>,
+    /// Only devices advertising this service, e.g. 180d (repeatable)
+    #[arg(long, value_parser = parse_uuid)]
+    uuid: Vec<bluer::Uuid>,
+    /// Only devices whose name or address starts with this
+    #[arg(long)]
+    pattern: Option<String>,
+    /// Report an advertisement only when its data changed
+    #[arg(long)]
+    no_duplicate_data: bool,
+    /// Scan without any filter
+    #[arg(long, conflicts_with_all = ["transport", "rssi", "pathloss", "uuid", "pattern", "no_duplicate_data"])]
+    clear_filter: bool,
+}
+
+impl FilterArgs {
+    fn filter(self, last: ScanFilter) -> ScanFilter {
+        let given = self.transport.is_some() || self.rssi.is_some() || self.pathloss.is_some()
+            || !self.uuid.is_empty() || self.pattern.is_some() || self.no_duplicate_data;
+        if !given && !self.clear_filter {
+            return last;
+        }
+        ScanFilter {
+            transport: self.transport.map_or(Transport::Auto, Transport::from),
+            min_rssi: self.rssi,
+            max_pathloss: self.pathloss,
+            uuids: self.uuid.into_iter().collect(),
+            pattern: self.pattern,
+            duplicate_data: !self.no_duplicate_data,
+        }
+    }
+}
+
+fn parse_uuid(text: &str) -> Result<bluer::Uuid, String>
+{
+    backend::parse_uuid(text).ok_or(format!("\"{}\" is not a UUID", text))
+}
+
+/*
+ * backend::Transport as written on the command line
+*/
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TransportArg {
+    /// LE and BR/EDR, whatever the controller has enabled
+    Auto,
+    /// Bluetooth Low Energy only
+    Le,
+    /// Classic Bluetooth only
+    #[value(name = "bredr")]
+    BrEdr,
+}
+
+impl From<TransportArg> for Transport {
+    fn from(transport: TransportArg) -> Self {
+        match transport {
+            TransportArg::Auto => Transport::Auto,
+            TransportArg::Le => Transport::Le,
+            TransportArg::BrEdr => Transport::BrEdr,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PowerState {
+    On,
+    Off,
+    Toggle,
+}
+
+/*
+ * Why a command failed, each kind has its own exit code
+*/
+enum CliError {
+    Failed(String),
+    NotFound(String),
+    Adapter(String),
+}
+
+impl CliError {
+    fn code(&self) -> u8 {
+        match self {
+            CliError::Failed(_) => 1,
+            CliError::NotFound(_) => 3,
+            CliError::Adapter(_) => 4,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            CliError::Failed(message) | CliError::NotFound(message) | CliError::Adapter(message) => message,
+        }
+    }
+}
+
+impl From<Error> for CliError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NotFound(message) => CliError::NotFound(message),
+            Error::Adapter(message) => CliError::Adapter(message),
+            Error::Authentication(message) | Error::Busy(message) | Error::Failed(message) => CliError::Failed(message),
+        }
+    }
+}
+
+impl From<bluer::Error> for CliError {
+    fn from(err: bluer::Error) -> Self {
+        Error::from(err).into()
+    }
+}
+
+pub async fn run(adapter: Option<&str>, command: Command, scan_timeout: Duration) -> ExitCode
+{
+    let session = match Session::new().await {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("btui: {}", err);
+            return ExitCode::from(CliError::Adapter(String::new()).code());
+        }
+    };
+
+    let result = match BluerBackend::new(&session, adapter).await {
+        Ok(backend) => {
+            // pairing may need a PIN or a confirmation, it is asked on the terminal
+            let (agent_tx, agent_rx) = mpsc::unbounded_channel();
+            let agent_handle = match command {
+                Command::Connect { .. } | Command::Pair { .. } => session.register_agent(agent::build_agent(agent_tx)).await.ok(),
+                _ => None,
+            };
+            answer_on_terminal(&backend, agent_rx);
+
+            let result = execute(&backend, command, scan_timeout).await;
+            drop(agent_handle);
+            result
+        }
+        Err(err) => Err(CliError::Adapter(err.to_string())),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("btui: {}", err.message());
+            ExitCode::from(err.code())
+        }
+    }
+}
+
+async fn execute<B: Backend>(backend: &B, command: Command, scan_timeout: Duration) -> Result<(), CliError>
+{
+    if let Command::Power { state } = command {
+        let powered = match state {
+            PowerState::On => true,
+            PowerState::Off => false,
+            PowerState::Toggle => !backend.is_powered().await?,
+        };
+        // BlueZ only answers "Blocked through rfkill"
+        let blocked = Rfkill::default().block(backend.adapter_name());
+        if powered && blocked != Block::Unblocked {
+            return Err(CliError::Adapter(blocked.explain(backend.adapter_name())));
+        }
+        return manager::set_adapter_power(backend, powered).await.map_err(|err| CliError::Failed(err.to_string()));
+    }
+
+    if !backend.is_powered().await? {
+        return Err(CliError::Adapter(format!("adapter {} is powered off", backend.adapter_name())));
+    }
+
+    match command {
+        Command::List { json } => {
+            let mut paired: Vec<Address> = vec![];
+            let store = manager::initiate(backend, &mut paired).await?;
+            if let Some(problem) = store.problem() {
+                eprintln!("btui: {}", problem);
+            }
+            let mut devices = vec![];
+            for address in paired {
+                devices.push(DeviceInfo::from_properties(address, backend.device_properties(address).await?));
+            }
+            print_devices(&devices, json)
+        }
+        Command::Scan { timeout, json, filter } => {
+            let mut paired: Vec<Address> = vec![];
+            let store = manager::initiate(backend, &mut paired).await?;
+            if let Some(problem) = store.problem() {
+                eprintln!("btui: {}", problem);
+            }
+            store.set_discovery_filter(filter.filter(store.discovery_filter()));
+            let found = Arc::new(Mutex::new(vec![]));
+            manager::scan_devices(backend, &mut paired, &store, found.clone(), timeout.map_or(scan_timeout, Duration::from_secs), &ActivityLog::default()).await?;
+            let devices = found.lock().unwrap().clone();
+            print_devices(&devices, json)
+        }
+        Command::Connect { device } => Ok(manager::connect_device(backend, resolve(backend, &device).await?).await?),
+        Command::Disconnect { device } => Ok(manager::disconnect_device(backend, resolve(backend, &device).await?).await?),
+        Command::Pair { device } => Ok(manager::pair_device(backend, resolve(backend, &device).await?).await?),
+        Command::Trust { device } => Ok(manager::set_trust(backend, resolve(backend, &device).await?, true).await?),
+        Command::Untrust { device } => Ok(manager::set_trust(backend, resolve(backend, &device).await?, false).await?),
+        Command::Block { device } => Ok(manager::set_blocked(backend, resolve(backend, &device).await?, true).await?),
+        Command::Unblock { device } => Ok(manager::set_blocked(backend, resolve(backend, &device).await?, false).await?),
+        Command::Tag { device, tags, remove } => {
+            let address = resolve(backend, &device).await?;
+            stored(backend, address).await?.update(address, |stored| {
+                if remove {
+                    stored.tags.retain(|tag| !tags.contains(tag));
+                } else {
+                    for tag in tags {
+                        if !stored.tags.contains(&tag) {
+                            stored.tags.push(tag);
+                        }
+                    }
+                }
+            });
+            Ok(())
+        }
+        Command::Note { device, text } => {
+            let address = resolve(backend, &device).await?;
+            stored(backend, address).await?.update(address, |stored| stored.notes = text.trim().to_string());
+            Ok(())
+        }
+        Command::Forget { device } => {
+            let address = resolve(backend, &device).await?;
+            Ok(manager::forget_device(backend, address, Arc::new(Mutex::new(vec![]))).await?)
+        }
+        Command::Power { .. } => unreachable!("handled above"),
+    }
+}
+
+/*
+ * Accepts an address, or a device name or alias matched without case
+*/
+async fn resolve<B: Backend>(backend: &B, device: &str) -> Result<Address, CliError>
+{
+    if let Ok(address) = device.parse::<Address>() {
+        return Ok(address);
+    }
+
+    let mut matches: Vec<Address> = vec![];
+    for address in backend.device_addresses().await? {
+        if let Ok(properties) = backend.device_properties(address).await
+            && [properties.alias, properties.name].into_iter().flatten().any(|name| name.eq_ignore_ascii_case(device))
+        {
+            matches.push(address);
+        }
+    }
+
+    match matches.as_slice() {
+        [address] => Ok(*address),
+        [] => Err(CliError::NotFound(format!("no device named \"{}\"", device))),
+        _ => Err(CliError::NotFound(format!("\"{}\" matches {} devices, use an address instead", device, matches.len()))),
+    }
+}
+
+/*
+ * The adapter's store, once sure a change to `address` will be kept: only paired devices are remembered
+*/
+async fn stored<B: Backend>(backend: &B, address: Address) -> Result<DeviceStore, CliError>
+{
+    let mut paired: Vec<Address> = vec![];
+    let store = manager::initiate(backend, &mut paired).await?;
+    if store.get(address).is_none() {
+        return Err(CliError::NotFound(format!("{} is not paired, only paired devices keep tags and notes", address)));
+    }
+    if !store.is_saved() {
+        return Err(CliError::Failed("the device store was written by a newer btui, it is left as is".to_string()));
+    }
+    Ok(store)
+}
+
+fn print_devices(devices: &[DeviceInfo], json: bool) -> Result<(), CliError>
+{
+    if json {
+        println!("{}", serde_json::to_string_pretty(devices).map_err(|err| CliError::Failed(err.to_string()))?);
+        return Ok(());
+    }
+
+    for device in devices {
+        let state = match device.state {
+            ConnectionState::Connected => "connected",
+            ConnectionState::Paired => "paired",
+            ConnectionState::Discovered => "-",
+        };
+        let battery = device.battery.map(|b| format!("{}%", b)).unwrap_or_default();
+        println!(
+            "{}  {:<24}  {:<9}  {:<7}  {}",
+            device.address,
+            device.display_name(),
+            state,
+            if device.trusted { "trusted" } else { "-" },
+            battery,
+        );
+    }
+    Ok(())
+}
+
+/*
+ * Answers the pairing agent from stdin.
+ * Lines are read on their own thread so a pending read never holds the exit back.
+*/
+fn answer_on_terminal<B: Backend>(backend: &B, mut requests: mpsc::UnboundedReceiver<AgentRequest>)
+{
+    let backend = backend.clone();
+    tokio::spawn(async move {
+        while let Some(request) = requests.recv().await {
+            let name = backend.device_properties(request.device()).await.ok()
+                .and_then(|d| DeviceInfo::from_properties(request.device(), d).name);
+            let prompt = AgentPrompt::new(request, name.unwrap_or("Unknown".to_string()));
+            eprintln!("{}", prompt.question());
+            if prompt.request.is_display() {
+                continue;
+            }
+
+            match prompt.request {
+                AgentRequest::PinCode { .. } | AgentRequest::Passkey { .. } => eprint!("> "),
+                _ => eprint!("[y/N] "),
+            }
+            let (line_tx, line_rx) = oneshot::channel();
+            std::thread::spawn(move || {
+                let _ = line_tx.send(manager::read_input());
+            });
+            prompt.answer_line(&line_rx.await.unwrap_or_default());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btui::backend::DeviceProperties;
+    use btui::fake::FakeBackend;
+
+    const HEADPHONES: &str = "EE:00:00:00:00:01";
+    const SPEAKER: &str = "EE:00:00:00:00:02";
+    const OTHER_SPEAKER: &str = "EE:00:00:00:00:03";
+
+    fn backend() -> FakeBackend {
+        FakeBackend::new("hci0")
+            .with_device(HEADPHONES.parse().unwrap(), DeviceProperties {
+                name: Some("WH-1000XM4".to_string()), alias: Some("Headphones".to_string()), paired: true, connected: true, ..Default::default()
+            })
+            .with_device(SPEAKER.parse().unwrap(), DeviceProperties { name: Some("Speaker".to_string()), ..Default::default() })
+            .with_device(OTHER_SPEAKER.parse().unwrap(), DeviceProperties { name: Some("speaker".to_string()), ..Default::default() })
+    }
+
+    fn filter_args(args: &[&str]) -> FilterArgs {
+        let cli = Cli::try_parse_from(["btui", "scan"].iter().chain(args)).unwrap();
+        match cli.command {
+            Some(Command::Scan { filter, .. }) => filter,
+            _ => unreachable!("parsed as scan"),
+        }
+    }
+
+    #[tokio::test]
+    async fn an_address_is_taken_as_it_is() {
+        let backend = backend();
+        assert_eq!(resolve(&backend, HEADPHONES).await.ok(), HEADPHONES.parse().ok());
+        // BlueZ may not know it yet, the command itself tells
+        assert_eq!(resolve(&backend, "EE:00:00:00:00:09").await.ok(), "EE:00:00:00:00:09".parse().ok());
+    }
+
+    #[tokio::test]
+    async fn names_and_aliases_match_without_case() {
+        let backend = backend();
+        assert_eq!(resolve(&backend, "headphones").await.ok(), HEADPHONES.parse().ok());
+        assert_eq!(resolve(&backend, "wh-1000xm4").await.ok(), HEADPHONES.parse().ok());
+    }
+
+    #[tokio::test]
+    async fn an_unknown_or_shared_name_is_not_found() {
+        let backend = backend();
+        let unknown = resolve(&backend, "Keyboard").await.err().unwrap();
+        assert_eq!((unknown.code(), unknown.message()), (3, "no device named \"Keyboard\""));
+        let shared = resolve(&backend, "SPEAKER").await.err().unwrap();
+        assert_eq!((shared.code(), shared.message()), (3, "\"SPEAKER\" matches 2 devices, use an address instead"));
+    }
+
+    #[test]
+    fn errors_map_to_the_documented_exit_codes() {
+        let code = |err: Error| CliError::from(err).code();
+        assert_eq!(code(Error::Failed(String::new())), 1);
+        assert_eq!(code(Error::Busy(String::new())), 1);
+        assert_eq!(code(Error::Authentication(String::new())), 1);
+        assert_eq!(code(Error::NotFound(String::new())), 3);
+        assert_eq!(code(Error::Adapter(String::new())), 4);
+        for code in ["1", "3", "4"] {
+            assert!(EXIT_CODES.lines().any(|line| line.trim_start().starts_with(code)), "exit code {} is documented", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_exit_with_the_code_of_their_failure() {
+        let backend = backend();
+        let code = async |backend: &FakeBackend, command| execute(backend, command, Duration::ZERO).await.err().map(|err| err.code());
+
+        assert_eq!(code(&backend, Command::Disconnect { device: "Headphones".to_string() }).await, None);
+        assert_eq!(backend.calls().last().map(String::as_str), Some("disconnect EE:00:00:00:00:01"));
+        assert_eq!(code(&backend, Command::Connect { device: "Keyboard".to_string() }).await, Some(3));
+        backend.fail_next("connect");
+        assert_eq!(code(&backend, Command::Connect { device: SPEAKER.to_string() }).await, Some(1));
+
+        let off = backend.with_powered(false);
+        assert_eq!(code(&off, Command::Connect { device: SPEAKER.to_string() }).await, Some(4));
+    }
+
+    #[test]
+    fn the_last_filter_is_kept_unless_one_is_given() {
+        let last = ScanFilter { min_rssi: Some(-70), pattern: Some("JBL".to_string()), ..Default::default() };
+        assert_eq!(filter_args(&[]).filter(last.clone()), last);
+        assert_eq!(filter_args(&["--clear-filter"]).filter(last.clone()), ScanFilter::default());
+
+        let given = filter_args(&["--transport", "bredr", "--rssi", "-60", "--uuid", "180d", "--no-duplicate-data"]).filter(last);
+        assert_eq!(given.transport, Transport::BrEdr);
+        assert_eq!(given.min_rssi, Some(-60));
+        assert_eq!(given.pattern, None, "a filter given replaces the whole last one");
+        assert_eq!(given.uuids.len(), 1);
+        assert!(!given.duplicate_data);
+    }
+}
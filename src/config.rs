@@ -0,0 +1,634 @@
+use ratatui::{crossterm::event::KeyCode, style::Color};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration
+};
+
+/*
+ * Settings read from $XDG_CONFIG_HOME/bluetooi/config.toml, e.g.
+ *
+ *   scan_timeout = 15
+ *
+ *   [keys]
+ *   scan = ["s", "F5"]
+ *   quit = "q"
+ *
+ *   [colors]
+ *   connected = "#00ff87"
+ *
+ *   [icons]
+ *   watch = "W"
+ *
+ * Every action below can be rebound. Enter, Esc and Tab are fixed where an action would not fit:
+ * Enter confirms and Esc closes the dialogs, pickers and text inputs, Tab switches a GATT write between
+ * hex and text, and Esc drops the search before it quits. In the device list they only do what their
+ * actions are bound to, Enter connects and Esc quits by default.
+*/
+
+/*
+ * Everything a key can be bound to in the device list and the GATT view
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action
+{
+    Quit,
+    Power,
+    Scan,
+    Connect,
+    Pair,
+    Trust,
+    Block,
+    Reconnect,
+    Rename,
+    Forget,
+    Cancel,
+    Adapter,
+    Settings,
+    ScanFilter,
+    Search,
+    Sort,
+    FilterConnected,
+    FilterPaired,
+    FilterTrusted,
+    FilterBlocked,
+    HideUnnamed,
+    Gatt,
+    Info,
+    Log,
+    LogLevel,
+    SaveLog,
+    Unblock,
+    Up,
+    Down,
+    GattRead,
+    GattWrite,
+    GattNotify,
+    ScrollUp,
+    ScrollDown,
+}
+
+/*
+ * Where a key is looked up, the same key can mean one thing in each
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum View
+{
+    /// The device list and the panes opened over it.
+    List,
+    Gatt,
+}
+
+impl Action {
+    pub const ALL: [Action; 34] = [
+        Action::Quit, Action::Power, Action::Scan, Action::Connect, Action::Pair, Action::Trust, Action::Block, Action::Reconnect,
+        Action::Rename, Action::Forget, Action::Cancel, Action::Adapter, Action::Settings, Action::ScanFilter,
+        Action::Search, Action::Sort, Action::FilterConnected, Action::FilterPaired, Action::FilterTrusted, Action::FilterBlocked, Action::HideUnnamed,
+        Action::Gatt, Action::Info, Action::Log, Action::LogLevel, Action::SaveLog, Action::Unblock, Action::Up, Action::Down,
+        Action::GattRead, Action::GattWrite, Action::GattNotify, Action::ScrollUp, Action::ScrollDown,
+    ];
+
+    /// Name used in the [keys] table of the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Power => "power",
+            Action::Scan => "scan",
+            Action::Connect => "connect",
+            Action::Pair => "pair",
+            Action::Trust => "trust",
+            Action::Block => "block",
+            Action::Reconnect => "reconnect",
+            Action::Rename => "rename",
+            Action::Forget => "forget",
+            Action::Cancel => "cancel",
+            Action::Adapter => "adapter",
+            Action::Settings => "settings",
+            Action::ScanFilter => "scan_filter",
+            Action::Search => "search",
+            Action::Sort => "sort",
+            Action::FilterConnected => "filter_connected",
+            Action::FilterPaired => "filter_paired",
+            Action::FilterTrusted => "filter_trusted",
+            Action::FilterBlocked => "filter_blocked",
+            Action::HideUnnamed => "hide_unnamed",
+            Action::Gatt => "gatt",
+            Action::Info => "info",
+            Action::Log => "log",
+            Action::LogLevel => "log_level",
+            Action::SaveLog => "save_log",
+            Action::Unblock => "unblock",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::GattRead => "gatt_read",
+            Action::GattWrite => "gatt_write",
+            Action::GattNotify => "gatt_notify",
+            Action::ScrollUp => "scroll_up",
+            Action::ScrollDown => "scroll_down",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::Power => "On/off",
+            Action::Scan => "Scan",
+            Action::Connect => "Connect",
+            Action::Pair => "Pair",
+            Action::Trust => "Trust",
+            Action::Block => "Block",
+            Action::Reconnect => "Reconnect",
+            Action::Rename => "Rename",
+            Action::Forget => "Forget",
+            Action::Cancel => "Cancel",
+            Action::Adapter => "Adapter",
+            Action::Settings => "Settings",
+            Action::ScanFilter => "Discovery filter",
+            Action::Search => "Search",
+            Action::Sort => "Sort",
+            Action::FilterConnected => "Connected",
+            Action::FilterPaired => "Paired",
+            Action::FilterTrusted => "Trusted",
+            Action::FilterBlocked => "Blocked",
+            Action::HideUnnamed => "Named",
+            Action::Gatt => "Gatt",
+            Action::Info => "Info",
+            Action::Log => "Log",
+            Action::LogLevel => "Level",
+            Action::SaveLog => "Write log",
+            Action::Unblock => "Unblock",
+            Action::Up => "Up",
+            Action::Down => "Down",
+            Action::GattRead => "Read",
+            Action::GattWrite => "Write",
+            Action::GattNotify => "Notify",
+            Action::ScrollUp => "Scroll up",
+            Action::ScrollDown => "Scroll down",
+        }
+    }
+
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Quit => &["q", "esc"],
+            Action::Power => &["o"],
+            Action::Scan => &["s"],
+            Action::Connect => &["c", "enter"],
+            Action::Pair => &["p"],
+            Action::Trust => &["t"],
+            Action::Block => &["b"],
+            Action::Reconnect => &["r"],
+            Action::Rename => &["n"],
+            Action::Forget => &["f"],
+            Action::Cancel => &["x"],
+            Action::Adapter => &["a"],
+            Action::Settings => &["e"],
+            Action::ScanFilter => &["d"],
+            Action::Search => &["/"],
+            Action::Sort => &["v"],
+            Action::FilterConnected => &["1"],
+            Action::FilterPaired => &["2"],
+            Action::FilterTrusted => &["3"],
+            Action::FilterBlocked => &["5"],
+            Action::HideUnnamed => &["4"],
+            Action::Gatt => &["g"],
+            Action::Info => &["i"],
+            Action::Log => &["l"],
+            Action::LogLevel => &["-"],
+            Action::SaveLog => &["w"],
+            Action::Unblock => &["u"],
+            Action::Up => &["up", "k"],
+            Action::Down => &["down", "j"],
+            Action::GattRead => &["r"],
+            Action::GattWrite => &["w"],
+            Action::GattNotify => &["n"],
+            Action::ScrollUp => &["pageup"],
+            Action::ScrollDown => &["pagedown"],
+        }
+    }
+
+    /*
+     * Moving, scrolling the log and quitting work everywhere
+    */
+    fn is_in(self, view: View) -> bool {
+        match self {
+            Action::Up | Action::Down | Action::ScrollUp | Action::ScrollDown | Action::Quit => true,
+            Action::GattRead | Action::GattWrite | Action::GattNotify => view == View::Gatt,
+            _ => view == View::List,
+        }
+    }
+}
+
+/*
+ * Key bindings, letters match regardless of case
+*/
+#[derive(Clone, Debug)]
+pub struct KeyMap
+{
+    bindings: Vec<(KeyCode, Action)>,
+}
+
+impl KeyMap {
+    pub fn action(&self, code: KeyCode) -> Option<Action> {
+        self.action_in(View::List, code)
+    }
+
+    pub fn action_in(&self, view: View, code: KeyCode) -> Option<Action> {
+        let code = lowercase(code);
+        self.bindings.iter().find(|(key, action)| *key == code && action.is_in(view)).map(|(_, action)| *action)
+    }
+
+    /*
+     * How the action is shown in the commands bar, e.g. "(S)can" or "[F5] Scan"
+    */
+    pub fn hint(&self, action: Action) -> String {
+        let label = action.label();
+        let Some((key, _)) = self.bindings.iter().find(|(_, a)| *a == action) else {
+            return label.to_string();
+        };
+        match key {
+            KeyCode::Char(c) if label.to_lowercase().starts_with(*c) => format!("({}){}", c.to_ascii_uppercase(), &label[1..]),
+            _ => format!("[{}] {}", key_name(*key), label),
+        }
+    }
+
+    /*
+     * The first key of each action, e.g. "PgUp/PgDn", for a hint shared by them
+    */
+    pub fn keys(&self, actions: &[Action]) -> String {
+        actions.iter()
+            .filter_map(|action| self.bindings.iter().find(|(_, a)| a == action))
+            .map(|(key, _)| key_name(*key))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let bindings = Action::ALL
+            .iter()
+            .flat_map(|action| action.default_keys().iter().map(|key| (parse_key(key).unwrap(), *action)))
+            .collect();
+        Self { bindings }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Theme
+{
+    pub text: Color,
+    pub connected: Color,
+    pub powered_off: Color,
+    pub scanning: Color,
+    pub highlight: Color,
+    pub popup: Color,
+    pub signal_good: Color,
+    pub signal_fair: Color,
+    pub signal_weak: Color,
+    pub info: Color,
+    pub warning: Color,
+    pub error: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            text: Color::White,
+            connected: Color::LightGreen,
+            powered_off: Color::Red,
+            scanning: Color::LightYellow,
+            highlight: Color::DarkGray,
+            popup: Color::LightCyan,
+            signal_good: Color::LightGreen,
+            signal_fair: Color::Yellow,
+            signal_weak: Color::Red,
+            info: Color::LightBlue,
+            warning: Color::Yellow,
+            error: Color::LightRed,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config
+{
+    pub scan_timeout: Duration,
+    pub keys: KeyMap,
+    pub theme: Theme,
+    /// Icon mappings added to or replacing the built-in ones, keyed by a part of the BlueZ icon name.
+    pub icons: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scan_timeout: Duration::from_secs(30),
+            keys: KeyMap::default(),
+            theme: Theme::default(),
+            icons: HashMap::new(),
+        }
+    }
+}
+
+/*
+ * The file as written by the user, checked by Config::parse
+*/
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig
+{
+    scan_timeout: Option<u64>,
+    keys: HashMap<String, RawKeys>,
+    colors: HashMap<String, String>,
+    icons: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawKeys
+{
+    One(String),
+    Many(Vec<String>),
+}
+
+pub fn config_path() -> Option<PathBuf>
+{
+    let mut path = dirs::config_dir()?;
+    path.push("bluetooi/config.toml");
+    Some(path)
+}
+
+impl Config {
+    /*
+     * Reads the config file, a missing file means the defaults.
+     * Every problem found is returned, not only the first one.
+    */
+    pub fn load() -> Result<Self, Vec<String>> {
+        let Some(path) = config_path().filter(|path| path.exists()) else {
+            return Ok(Config::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Config::parse(&text),
+            Err(err) => Err(vec![format!("cannot read {}: {}", path.display(), err)]),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Vec<String>> {
+        let raw: RawConfig = toml::from_str(text).map_err(|err| vec![err.to_string()])?;
+        let mut config = Config::default();
+        let mut errors = vec![];
+
+        match raw.scan_timeout {
+            Some(0) => errors.push("scan_timeout must be at least 1 second".to_string()),
+            Some(seconds) => config.scan_timeout = Duration::from_secs(seconds),
+            None => {}
+        }
+
+        for (name, keys) in raw.keys {
+            let Some(action) = Action::ALL.iter().find(|a| a.name() == name) else {
+                errors.push(format!("[keys] unknown action \"{}\", expected one of: {}", name, Action::ALL.map(Action::name).join(", ")));
+                continue;
+            };
+            let keys = match keys {
+                RawKeys::One(key) => vec![key],
+                RawKeys::Many(keys) => keys,
+            };
+            if keys.is_empty() {
+                errors.push(format!("[keys] {} needs at least one key", name));
+                continue;
+            }
+
+            config.keys.bindings.retain(|(_, a)| a != action);
+            for key in keys {
+                match parse_key(&key) {
+                    Some(code) => config.keys.bindings.push((code, *action)),
+                    None => errors.push(format!("[keys] {}: unknown key \"{}\"", name, key)),
+                }
+            }
+        }
+        // r reconnects in the list and reads in the GATT view
+        let share_a_view = |a: Action, b: Action| [View::List, View::Gatt].iter().any(|view| a.is_in(*view) && b.is_in(*view));
+        for (i, (code, action)) in config.keys.bindings.iter().enumerate() {
+            if let Some((_, other)) = config.keys.bindings[..i].iter().find(|(c, a)| c == code && a != action && share_a_view(*a, *action)) {
+                errors.push(format!("[keys] \"{}\" is bound to both {} and {}", key_name(*code), other.name(), action.name()));
+            }
+        }
+
+        for (slot, value) in raw.colors {
+            let color = match Color::from_str(&value) {
+                Ok(color) => color,
+                Err(_) => {
+                    errors.push(format!("[colors] {}: unknown colour \"{}\"", slot, value));
+                    continue;
+                }
+            };
+            match slot.as_str() {
+                "text" => config.theme.text = color,
+                "connected" => config.theme.connected = color,
+                "powered_off" => config.theme.powered_off = color,
+                "scanning" => config.theme.scanning = color,
+                "highlight" => config.theme.highlight = color,
+                "popup" => config.theme.popup = color,
+                "signal_good" => config.theme.signal_good = color,
+                "signal_fair" => config.theme.signal_fair = color,
+                "signal_weak" => config.theme.signal_weak = color,
+                "info" => config.theme.info = color,
+                "warning" => config.theme.warning = color,
+                "error" => config.theme.error = color,
+                _ => errors.push(format!("[colors] unknown entry \"{}\", expected one of: text, connected, powered_off, scanning, highlight, popup, signal_good, signal_fair, signal_weak, info, warning, error", slot)),
+            }
+        }
+
+        for (name, icon) in raw.icons {
+            if name.is_empty() {
+                errors.push(format!("[icons] empty name for \"{}\"", icon));
+                continue;
+            }
+            config.icons.insert(name.to_lowercase(), icon);
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            errors.sort();
+            Err(errors)
+        }
+    }
+}
+
+fn lowercase(code: KeyCode) -> KeyCode
+{
+    match code {
+        KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+        code => code,
+    }
+}
+
+/*
+ * "a", "enter", "f5", "space"... as written in the config file
+*/
+fn parse_key(key: &str) -> Option<KeyCode>
+{
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(lowercase(KeyCode::Char(c)));
+    }
+
+    let key = key.to_lowercase();
+    let code = match key.as_str() {
+        "enter" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ => KeyCode::F(key.strip_prefix('f')?.parse().ok().filter(|n| (1..=12).contains(n))?),
+    };
+    Some(code)
+}
+
+fn key_name(code: KeyCode) -> String
+{
+    match code {
+        KeyCode::Char(' ') => "space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::PageUp => "PgUp".to_string(),
+        KeyCode::PageDown => "PgDn".to_string(),
+        code => code.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_section() {
+        let config = Config::parse(r##"
+            scan_timeout = 15
+
+            [keys]
+            scan = ["s", "F5"]
+            quit = "q"
+
+            [colors]
+            connected = "#00ff87"
+            error = "magenta"
+
+            [icons]
+            Watch = "W"
+        "##).unwrap();
+
+        assert_eq!(config.scan_timeout, Duration::from_secs(15));
+        assert_eq!(config.keys.action(KeyCode::F(5)), Some(Action::Scan));
+        assert_eq!(config.keys.action(KeyCode::Char('s')), Some(Action::Scan));
+        assert_eq!(config.keys.action(KeyCode::Esc), None, "quit is only q now");
+        assert_eq!(config.theme.connected, Color::Rgb(0x00, 0xff, 0x87));
+        assert_eq!(config.theme.error, Color::Magenta);
+        assert_eq!(config.icons.get("watch").map(String::as_str), Some("W"));
+    }
+
+    #[test]
+    fn what_is_not_set_keeps_its_default() {
+        let config = Config::parse("[keys]\nscan = \"F5\"\n").unwrap();
+        let defaults = Config::default();
+
+        assert_eq!(config.scan_timeout, defaults.scan_timeout);
+        assert_eq!(config.theme.connected, defaults.theme.connected);
+        assert_eq!(config.keys.action(KeyCode::Char('s')), None, "the old key is free");
+        assert_eq!(config.keys.action(KeyCode::Char('c')), Some(Action::Connect));
+        assert_eq!(config.keys.action(KeyCode::Enter), Some(Action::Connect));
+        assert!(Config::parse("").is_ok());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let errors = Config::parse(r#"
+            scan_timeout = 0
+
+            [keys]
+            teleport = "t"
+            scan = "hyper"
+            pair = []
+
+            [colors]
+            connected = "not a colour"
+            background = "red"
+        "#).unwrap_err();
+
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+        assert!(errors.iter().any(|error| error.starts_with("[keys] unknown action \"teleport\", expected one of: quit, power, scan")));
+        assert!(errors.contains(&"[keys] scan: unknown key \"hyper\"".to_string()));
+        assert!(errors.contains(&"[keys] pair needs at least one key".to_string()));
+        assert!(errors.contains(&"[colors] connected: unknown colour \"not a colour\"".to_string()));
+        assert!(errors.iter().any(|error| error.starts_with("[colors] unknown entry \"background\"")));
+        assert!(errors.contains(&"scan_timeout must be at least 1 second".to_string()));
+    }
+
+    #[test]
+    fn unknown_sections_are_refused() {
+        let errors = Config::parse("[sounds]\nconnected = \"beep\"\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("unknown field `sounds`"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn a_key_bound_twice_in_a_view_is_refused() {
+        assert_eq!(Config::parse("[keys]\nscan = \"c\"\n").unwrap_err(), ["[keys] \"c\" is bound to both connect and scan"]);
+        assert_eq!(Config::parse("[keys]\ngatt_read = \"j\"\n").unwrap_err(), ["[keys] \"j\" is bound to both down and gatt_read"]);
+        // r reconnects in the list and reads in the GATT view
+        assert!(Config::parse("[keys]\ngatt_read = \"s\"\n").is_ok());
+    }
+
+    #[test]
+    fn the_default_keys_parse_and_letters_ignore_case() {
+        let keys = KeyMap::default();
+        for action in Action::ALL {
+            assert!(keys.bindings.iter().any(|(_, a)| *a == action), "{} has no default key", action.name());
+        }
+        assert_eq!(keys.action(KeyCode::Char('S')), Some(Action::Scan));
+        assert_eq!(keys.action(KeyCode::Char('r')), Some(Action::Reconnect));
+        assert_eq!(keys.action_in(View::Gatt, KeyCode::Char('r')), Some(Action::GattRead));
+        assert_eq!(keys.action_in(View::Gatt, KeyCode::Char('j')), Some(Action::Down));
+        assert_eq!(keys.action_in(View::Gatt, KeyCode::Char('s')), None);
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(parse_key("space"), Some(KeyCode::Char(' ')));
+        assert_eq!(parse_key("PageUp"), Some(KeyCode::PageUp));
+        assert_eq!(parse_key("F12"), Some(KeyCode::F(12)));
+        assert_eq!(parse_key("f13"), None);
+        assert_eq!(parse_key("X"), Some(KeyCode::Char('x')));
+        assert_eq!(parse_key(""), None);
+
+        let config = Config::parse("[keys]\nscan = \"F5\"\n").unwrap();
+        assert_eq!(config.keys.hint(Action::Scan), "[F5] Scan");
+        assert_eq!(config.keys.hint(Action::Connect), "(C)onnect");
+        assert_eq!(config.keys.keys(&[Action::ScrollUp, Action::ScrollDown]), "PgUp/PgDn");
+    }
+
+    #[test]
+    fn scrolling_the_log_can_be_rebound() {
+        let keys = KeyMap::default();
+        assert_eq!(keys.action(KeyCode::PageUp), Some(Action::ScrollUp));
+        assert_eq!(keys.action_in(View::Gatt, KeyCode::PageDown), Some(Action::ScrollDown));
+
+        let config = Config::parse("[keys]\nscroll_up = \"home\"\nscroll_down = \"end\"\n").unwrap();
+        assert_eq!(config.keys.action_in(View::Gatt, KeyCode::Home), Some(Action::ScrollUp));
+        assert_eq!(config.keys.action(KeyCode::PageUp), None);
+        assert_eq!(config.keys.keys(&[Action::ScrollUp, Action::ScrollDown]), "Home/End");
+    }
+}
@@ -0,0 +1,228 @@
+use bluer::Uuid;
+use std::time::{Duration, SystemTime};
+
+/*
+ * Decoders for the raw values shown in the detail panel
+*/
+
+/// Name of a profile or GATT service UUID, e.g. "AudioSink" or "Battery Service".
+pub fn uuid_name(uuid: Uuid) -> Option<String>
+{
+    bluer::id::ServiceClass::try_from(uuid).map(|id| id.to_string())
+        .or_else(|_| bluer::id::Service::try_from(uuid).map(|id| id.to_string()))
+        .ok()
+}
+
+pub fn manufacturer_name(id: u16) -> Option<String>
+{
+    bluer::id::Manufacturer::try_from(id).ok().map(|id| id.to_string())
+}
+
+/*
+ * Class of device as "Major: Minor (services)", from the Bluetooth assigned numbers
+*/
+pub fn describe_class(class: u32) -> String
+{
+    let major = (class >> 8) & 0x1f;
+    let minor = (class >> 2) & 0x3f;
+
+    let (major_name, minor_name) = match major {
+        0 => ("Miscellaneous", None),
+        1 => ("Computer", [None, Some("Desktop"), Some("Server"), Some("Laptop"), Some("Handheld"), Some("Palm-size"), Some("Wearable"), Some("Tablet")]
+            .get(minor as usize).copied().flatten()),
+        2 => ("Phone", [None, Some("Cellular"), Some("Cordless"), Some("Smartphone"), Some("Wired modem"), Some("ISDN")]
+            .get(minor as usize).copied().flatten()),
+        3 => ("Network access point", None),
+        4 => ("Audio/Video", [
+            None, Some("Headset"), Some("Hands-free"), None, Some("Microphone"), Some("Loudspeaker"), Some("Headphones"),
+            Some("Portable audio"), Some("Car audio"), Some("Set-top box"), Some("HiFi audio"), Some("VCR"), Some("Video camera"),
+            Some("Camcorder"), Some("Video monitor"), Some("Video display and loudspeaker"), Some("Video conferencing"), None, Some("Gaming/Toy"),
+        ].get(minor as usize).copied().flatten()),
+        5 => ("Peripheral", match (minor >> 4, minor & 0xf) {
+            (_, 1) => Some("Joystick"),
+            (_, 2) => Some("Gamepad"),
+            (_, 3) => Some("Remote control"),
+            (_, 4) => Some("Sensing device"),
+            (_, 5) => Some("Digitizer tablet"),
+            (_, 6) => Some("Card reader"),
+            (1, _) => Some("Keyboard"),
+            (2, _) => Some("Pointing device"),
+            (3, _) => Some("Keyboard and pointing device"),
+            _ => None,
+        }),
+        6 => ("Imaging", match minor >> 2 {
+            m if m & 0x8 != 0 => Some("Printer"),
+            m if m & 0x4 != 0 => Some("Scanner"),
+            m if m & 0x2 != 0 => Some("Camera"),
+            m if m & 0x1 != 0 => Some("Display"),
+            _ => None,
+        }),
+        7 => ("Wearable", [None, Some("Wristwatch"), Some("Pager"), Some("Jacket"), Some("Helmet"), Some("Glasses")]
+            .get(minor as usize).copied().flatten()),
+        8 => ("Toy", None),
+        9 => ("Health", None),
+        _ => ("Uncategorized", None),
+    };
+
+    let services: Vec<&str> = [
+        (13, "Limited discoverable"), (16, "Positioning"), (17, "Networking"), (18, "Rendering"),
+        (19, "Capturing"), (20, "Object transfer"), (21, "Audio"), (22, "Telephony"), (23, "Information"),
+    ]
+    .into_iter()
+    .filter(|(bit, _)| class & (1 << bit) != 0)
+    .map(|(_, name)| name)
+    .collect();
+
+    let mut text = match minor_name {
+        Some(minor_name) => format!("{}: {}", major_name, minor_name),
+        None => major_name.to_string(),
+    };
+    if !services.is_empty() {
+        text.push_str(&format!(" ({})", services.join(", ")));
+    }
+    format!("0x{:06x} {}", class, text)
+}
+
+/*
+ * GAP appearance, only its category is named
+*/
+pub fn describe_appearance(appearance: u16) -> String
+{
+    let category = match appearance >> 6 {
+        0 => "Unknown",
+        1 => "Phone",
+        2 => "Computer",
+        3 => "Watch",
+        4 => "Clock",
+        5 => "Display",
+        6 => "Remote control",
+        7 => "Eye-glasses",
+        8 => "Tag",
+        9 => "Keyring",
+        10 => "Media player",
+        11 => "Barcode scanner",
+        12 => "Thermometer",
+        13 => "Heart rate sensor",
+        14 => "Blood pressure",
+        15 => match appearance & 0x3f {
+            1 => "Keyboard",
+            2 => "Mouse",
+            3 => "Joystick",
+            4 => "Gamepad",
+            5 => "Digitizer tablet",
+            6 => "Card reader",
+            7 => "Digital pen",
+            8 => "Barcode scanner",
+            _ => "Human interface device",
+        },
+        16 => "Glucose meter",
+        17 => "Running walking sensor",
+        18 => "Cycling",
+        19 => "Control device",
+        20 => "Network device",
+        21 => "Sensor",
+        22 => "Light fixtures",
+        23 => "Fan",
+        24 => "HVAC",
+        25 => "Air conditioning",
+        26 => "Humidifier",
+        27 => "Heating",
+        28 => "Access control",
+        29 => "Motorized device",
+        30 => "Power device",
+        31 => "Light source",
+        32 => "Window covering",
+        33 => "Audio sink",
+        34 => "Audio source",
+        35 => "Motorized vehicle",
+        36 => "Domestic appliance",
+        37 => "Wearable audio device",
+        38 => "Aircraft",
+        39 => "AV equipment",
+        40 => "Display equipment",
+        41 => "Hearing aid",
+        42 => "Gaming",
+        43 => "Signage",
+        49 => "Pulse oximeter",
+        50 => "Weight scale",
+        51 => "Personal mobility device",
+        52 => "Continuous glucose monitor",
+        53 => "Insulin pump",
+        54 => "Medication delivery",
+        55 => "Spirometer",
+        81 => "Outdoor sports activity",
+        _ => "Reserved",
+    };
+    format!("0x{:04x} {}", appearance, category)
+}
+
+pub fn hex(value: &[u8]) -> String
+{
+    value.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+/*
+ * "42s ago", "5m ago", "3h ago", "2d ago", counted back from `now`
+*/
+pub fn ago(time: SystemTime, now: SystemTime) -> String
+{
+    let seconds = now.duration_since(time).unwrap_or(Duration::ZERO).as_secs();
+    match seconds {
+        0..60 => format!("{}s ago", seconds),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86400 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classes_of_device() {
+        for (class, expected) in [
+            (0x240404, "0x240404 Audio/Video: Headset (Rendering, Audio)"),
+            (0x5a020c, "0x5a020c Phone: Smartphone (Networking, Capturing, Object transfer, Telephony)"),
+            (0x002540, "0x002540 Peripheral: Keyboard (Limited discoverable)"),
+            (0x000580, "0x000580 Peripheral: Pointing device"),
+            (0x000508, "0x000508 Peripheral: Gamepad"),
+            (0x000680, "0x000680 Imaging: Printer"),
+            (0x00010c, "0x00010c Computer: Laptop"),
+            (0x000704, "0x000704 Wearable: Wristwatch"),
+            (0x0004fc, "0x0004fc Audio/Video"),
+            (0x001f00, "0x001f00 Uncategorized"),
+            (0x000000, "0x000000 Miscellaneous"),
+        ] {
+            assert_eq!(describe_class(class), expected);
+        }
+    }
+
+    #[test]
+    fn appearances() {
+        for (appearance, expected) in [
+            (0x03c1, "0x03c1 Keyboard"),
+            (0x03c2, "0x03c2 Mouse"),
+            (0x03c0, "0x03c0 Human interface device"),
+            (0x00c2, "0x00c2 Watch"),
+            (0x0941, "0x0941 Wearable audio device"),
+            (0x0000, "0x0000 Unknown"),
+            (0xffff, "0xffff Reserved"),
+        ] {
+            assert_eq!(describe_appearance(appearance), expected);
+        }
+    }
+
+    #[test]
+    fn ago_rounds_down_to_the_largest_unit() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_718_000_000);
+        let ago = |seconds| ago(now - Duration::from_secs(seconds), now);
+        assert_eq!(ago(0), "0s ago");
+        assert_eq!(ago(59), "59s ago");
+        assert_eq!(ago(60), "1m ago");
+        assert_eq!(ago(3599), "59m ago");
+        assert_eq!(ago(3600), "1h ago");
+        assert_eq!(ago(86400 * 2 + 5), "2d ago");
+        assert_eq!(super::ago(now + Duration::from_secs(10), now), "0s ago", "a clock set back");
+    }
+}
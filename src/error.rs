@@ -0,0 +1,107 @@
+use bluer::ErrorKind;
+use std::fmt;
+
+/*
+ * Why a manager operation failed, sorted by what the user can do about it
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error
+{
+    /// The adapter is missing, powered off or not ready yet.
+    Adapter(String),
+    /// BlueZ does not know the device, or no longer.
+    NotFound(String),
+    /// Pairing was rejected, cancelled or timed out, on either side.
+    Authentication(String),
+    /// The device is already busy with another request, trying again later may work.
+    Busy(String),
+    /// Anything else BlueZ refused.
+    Failed(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Adapter(message)
+            | Error::NotFound(message)
+            | Error::Authentication(message)
+            | Error::Busy(message)
+            | Error::Failed(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<bluer::Error> for Error {
+    fn from(err: bluer::Error) -> Self {
+        let message = err.to_string();
+        match err.kind {
+            ErrorKind::NotReady | ErrorKind::NotFound | ErrorKind::NotAvailable => Error::Adapter(message),
+            ErrorKind::DoesNotExist | ErrorKind::InvalidAddress(_) => Error::NotFound(message),
+            ErrorKind::AuthenticationCanceled
+            | ErrorKind::AuthenticationFailed
+            | ErrorKind::AuthenticationRejected
+            | ErrorKind::AuthenticationTimeout => Error::Authentication(message),
+            ErrorKind::InProgress | ErrorKind::AlreadyConnected | ErrorKind::AlreadyExists => Error::Busy(message),
+            _ => Error::Failed(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(kind: ErrorKind) -> Error {
+        bluer::Error { kind, message: "refused".to_string() }.into()
+    }
+
+    #[test]
+    fn bluez_errors_are_sorted_by_what_the_user_can_do() {
+        let adapter = Error::Adapter(String::new());
+        let not_found = Error::NotFound(String::new());
+        let authentication = Error::Authentication(String::new());
+        let busy = Error::Busy(String::new());
+        let failed = Error::Failed(String::new());
+        let cases = [
+            (ErrorKind::NotReady, &adapter),
+            (ErrorKind::NotFound, &adapter),
+            (ErrorKind::NotAvailable, &adapter),
+            (ErrorKind::DoesNotExist, &not_found),
+            (ErrorKind::InvalidAddress("EE:00".to_string()), &not_found),
+            (ErrorKind::AuthenticationCanceled, &authentication),
+            (ErrorKind::AuthenticationFailed, &authentication),
+            (ErrorKind::AuthenticationRejected, &authentication),
+            (ErrorKind::AuthenticationTimeout, &authentication),
+            (ErrorKind::InProgress, &busy),
+            (ErrorKind::AlreadyConnected, &busy),
+            (ErrorKind::AlreadyExists, &busy),
+            (ErrorKind::Failed, &failed),
+            (ErrorKind::ConnectionAttemptFailed, &failed),
+            (ErrorKind::NotSupported, &failed),
+            (ErrorKind::NotAuthorized, &failed),
+            (ErrorKind::ServicesUnresolved, &failed),
+        ];
+
+        for (kind, expected) in cases {
+            let error = from(kind.clone());
+            assert_eq!(std::mem::discriminant(&error), std::mem::discriminant(expected), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn the_bluez_message_is_kept() {
+        let error = from(ErrorKind::AuthenticationRejected);
+        assert!(error.message().contains("refused"), "{}", error);
+        assert_eq!(error.to_string(), error.message());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::backend::{AdapterProperties, Backend, DeviceProperties, GattHandle, GattService, ScanFilter};
+use async_trait::async_trait;
+use bluer::{AdapterEvent, AdapterProperty, Address, DeviceEvent, DeviceProperty, ErrorKind};
+use futures::stream::{self, BoxStream, StreamExt};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration
+};
+use tokio::sync::broadcast;
+
+/*
+ * In-memory adapters with a scripted set of devices.
+ * Lets btui run deterministically on machines with no controller or bluetoothd.
+*/
+#[derive(Clone)]
+pub struct FakeBackend
+{
+    name: String,
+    state: Arc<Mutex<FakeState>>,
+    adapter_events: broadcast::Sender<AdapterEvent>,
+    device_events: broadcast::Sender<(Address, DeviceProperty)>,
+    gatt_events: broadcast::Sender<(Address, GattHandle, Vec<u8>)>,
+    // every fake adapter, shared by the backends handed out by for_adapter
+    adapters: Arc<Mutex<BTreeMap<String, FakeAdapter>>>,
+}
+
+#[derive(Clone)]
+struct FakeAdapter
+{
+    state: Arc<Mutex<FakeState>>,
+    adapter_events: broadcast::Sender<AdapterEvent>,
+    device_events: broadcast::Sender<(Address, DeviceProperty)>,
+    gatt_events: broadcast::Sender<(Address, GattHandle, Vec<u8>)>,
+}
+
+impl FakeAdapter {
+    fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FakeState {
+                powered: true,
+                adapter: AdapterProperties {
+                    system_name: Some("btui-fake".to_string()),
+                    alias: Some("btui-fake".to_string()),
+                    roles: vec!["central".to_string(), "peripheral".to_string()],
+                    pairable: true,
+                    discoverable_timeout: 180,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })),
+            adapter_events: broadcast::channel(64).0,
+            device_events: broadcast::channel(64).0,
+            gatt_events: broadcast::channel(64).0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct FakeState
+{
+    powered: bool,
+    // everything but `powered`, which is kept above
+    adapter: AdapterProperties,
+    devices: HashMap<Address, DeviceProperties>,
+    // devices that show up once a scan is started
+    in_range: Vec<(Address, DeviceProperties)>,
+    gatt: HashMap<Address, Vec<GattService>>,
+    gatt_values: HashMap<(Address, GattHandle), Vec<u8>>,
+    // operations that will fail the next time they are called
+    failures: HashSet<&'static str>,
+    // how long pairing and connecting take, as with a real device
+    latency: Duration,
+    calls: Vec<String>,
+}
+
+impl FakeBackend {
+    pub fn new(name: &str) -> Self {
+        let adapter = FakeAdapter::new();
+        Self {
+            name: name.to_string(),
+            state: adapter.state.clone(),
+            adapter_events: adapter.adapter_events.clone(),
+            device_events: adapter.device_events.clone(),
+            gatt_events: adapter.gatt_events.clone(),
+            adapters: Arc::new(Mutex::new(BTreeMap::from([(name.to_string(), adapter)]))),
+        }
+    }
+
+    /// Adds another, empty adapter. Use `for_adapter` to script its devices.
+    pub fn with_adapter(self, name: &str) -> Self {
+        self.adapters.lock().unwrap().insert(name.to_string(), FakeAdapter::new());
+        self
+    }
+
+    pub fn with_powered(self, powered: bool) -> Self {
+        self.state.lock().unwrap().powered = powered;
+        self
+    }
+
+    /// Adds a device the adapter already knows about.
+    pub fn with_device(self, address: Address, properties: DeviceProperties) -> Self {
+        self.state.lock().unwrap().devices.insert(address, properties);
+        self
+    }
+
+    /// Adds a device that will be reported by the next scan.
+    pub fn with_device_in_range(self, address: Address, properties: DeviceProperties) -> Self {
+        self.state.lock().unwrap().in_range.push((address, properties));
+        self
+    }
+
+    /// Gives a device GATT services, readable once it is connected.
+    pub fn with_gatt(self, address: Address, services: Vec<GattService>) -> Self {
+        self.state.lock().unwrap().gatt.insert(address, services);
+        self
+    }
+
+    /// Changes a characteristic value and notifies the subscribers, as a sensor would.
+    pub fn notify_value(&self, address: Address, handle: GattHandle, value: Vec<u8>) {
+        self.state.lock().unwrap().gatt_values.insert((address, handle), value.clone());
+        let _ = self.gatt_events.send((address, handle, value));
+    }
+
+    pub fn gatt_value(&self, address: Address, handle: GattHandle) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().gatt_values.get(&(address, handle)).cloned()
+    }
+
+    /// Makes pairing, connecting and disconnecting take `latency` before they complete.
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.state.lock().unwrap().latency = latency;
+        self
+    }
+
+    /// Makes the next call to `operation` (e.g. "pair", "connect") fail.
+    pub fn fail_next(&self, operation: &'static str) {
+        self.state.lock().unwrap().failures.insert(operation);
+    }
+
+    /// Changes a device property as if it happened outside btui (e.g. a headset powering off).
+    pub fn set_property(&self, address: Address, property: DeviceProperty) {
+        let _ = self.update(address, property);
+    }
+
+    pub fn device(&self, address: Address) -> Option<DeviceProperties> {
+        self.state.lock().unwrap().devices.get(&address).cloned()
+    }
+
+    /// Every operation performed so far, as "operation address" strings.
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    async fn wait(&self) {
+        let latency = self.state.lock().unwrap().latency;
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+    }
+
+    fn record(&self, operation: &'static str, address: Option<Address>) -> bluer::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(match address {
+            Some(address) => format!("{} {}", operation, address),
+            None => operation.to_string(),
+        });
+
+        if state.failures.remove(operation) {
+            return Err(error(ErrorKind::Failed, operation));
+        }
+        if !state.powered && operation != "set_powered" && operation != "is_powered" {
+            return Err(error(ErrorKind::NotReady, "adapter is powered off"));
+        }
+        Ok(())
+    }
+
+    /*
+     * Checks that `handle` exists on a connected device
+    */
+    fn check_gatt(&self, address: Address, handle: Option<GattHandle>) -> bluer::Result<()> {
+        let state = self.state.lock().unwrap();
+        if !state.devices.get(&address).is_some_and(|d| d.connected) {
+            return Err(error(ErrorKind::ServicesUnresolved, "device is not connected"));
+        }
+        let Some(handle) = handle else {
+            return Ok(());
+        };
+        let found = state.gatt.get(&address).into_iter().flatten()
+            .filter(|service| service.id == handle.service)
+            .flat_map(|service| &service.characteristics)
+            .filter(|characteristic| characteristic.id == handle.characteristic)
+            .any(|characteristic| handle.descriptor.is_none_or(|id| characteristic.descriptors.iter().any(|d| d.id == id)));
+        if found {
+            Ok(())
+        } else {
+            Err(error(ErrorKind::DoesNotExist, "no such characteristic"))
+        }
+    }
+
+    fn update_adapter(&self, property: AdapterProperty) -> bluer::Result<()> {
+        self.state.lock().unwrap().adapter.apply(property.clone());
+        let _ = self.adapter_events.send(AdapterEvent::PropertyChanged(property));
+        Ok(())
+    }
+
+    fn update(&self, address: Address, property: DeviceProperty) -> bluer::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let device = state.devices.get_mut(&address).ok_or_else(|| error(ErrorKind::DoesNotExist, "no such device"))?;
+        device.apply(property.clone());
+        let _ = self.device_events.send((address, property));
+        Ok(())
+    }
+}
+
+fn error(kind: ErrorKind, message: &str) -> bluer::Error {
+    bluer::Error { kind, message: message.to_string() }
+}
+
+fn receiver_stream<T: Clone + Send + 'static>(receiver: broadcast::Receiver<T>) -> impl futures::Stream<Item = T> + Send + 'static {
+    stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(item) => return Some((item, receiver)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[async_trait]
+impl Backend for FakeBackend
+{
+    fn adapter_name(&self) -> &str {
+        &self.name
+    }
+
+    async fn adapter_names(&self) -> bluer::Result<Vec<String>> {
+        Ok(self.adapters.lock().unwrap().keys().cloned().collect())
+    }
+
+    async fn for_adapter(&self, name: &str) -> bluer::Result<Self> {
+        let adapter = self.adapters.lock().unwrap().get(name).cloned()
+            .ok_or_else(|| error(ErrorKind::NotFound, "no such adapter"))?;
+        Ok(Self {
+            name: name.to_string(),
+            state: adapter.state,
+            adapter_events: adapter.adapter_events,
+            device_events: adapter.device_events,
+            gatt_events: adapter.gatt_events,
+            adapters: self.adapters.clone(),
+        })
+    }
+
+    async fn is_powered(&self) -> bluer::Result<bool> {
+        Ok(self.state.lock().unwrap().powered)
+    }
+
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+        self.record("set_powered", None)?;
+        let connected: Vec<Address> = {
+            let mut state = self.state.lock().unwrap();
+            state.powered = powered;
+            state.devices.iter().filter(|(_, d)| d.connected).map(|(address, _)| *address).collect()
+        };
+        let _ = self.adapter_events.send(AdapterEvent::PropertyChanged(AdapterProperty::Powered(powered)));
+        if !powered {
+            for address in connected {
+                let _ = self.update(address, DeviceProperty::Connected(false));
+            }
+        }
+        Ok(())
+    }
+
+    async fn adapter_properties(&self) -> bluer::Result<AdapterProperties> {
+        let state = self.state.lock().unwrap();
+        Ok(AdapterProperties { powered: state.powered, ..state.adapter.clone() })
+    }
+
+    async fn set_discoverable(&self, discoverable: bool) -> bluer::Result<()> {
+        self.record("set_discoverable", None)?;
+        self.update_adapter(AdapterProperty::Discoverable(discoverable))
+    }
+
+    async fn set_discoverable_timeout(&self, seconds: u32) -> bluer::Result<()> {
+        self.record("set_discoverable_timeout", None)?;
+        self.update_adapter(AdapterProperty::DiscoverableTimeout(seconds))
+    }
+
+    async fn set_pairable(&self, pairable: bool) -> bluer::Result<()> {
+        self.record("set_pairable", None)?;
+        self.update_adapter(AdapterProperty::Pairable(pairable))
+    }
+
+    async fn set_pairable_timeout(&self, seconds: u32) -> bluer::Result<()> {
+        self.record("set_pairable_timeout", None)?;
+        self.update_adapter(AdapterProperty::PairableTimeout(seconds))
+    }
+
+    async fn set_adapter_alias(&self, alias: String) -> bluer::Result<()> {
+        self.record("set_adapter_alias", None)?;
+        let alias = match alias.is_empty() {
+            true => self.state.lock().unwrap().adapter.system_name.clone().unwrap_or_default(),
+            false => alias,
+        };
+        self.update_adapter(AdapterProperty::Alias(alias))
+    }
+
+    async fn discover_devices(&self, filter: &ScanFilter) -> bluer::Result<BoxStream<'static, AdapterEvent>> {
+        self.record("discover_devices", None)?;
+        let mut state = self.state.lock().unwrap();
+        // the others stay in range for a scan with another filter
+        let (found, ignored): (Vec<_>, Vec<_>) = state.in_range.drain(..).partition(|(address, properties)| filter.matches(*address, properties));
+        state.in_range = ignored;
+        let mut events = vec![];
+        for (address, properties) in found {
+            state.devices.insert(address, properties);
+            let _ = self.adapter_events.send(AdapterEvent::DeviceAdded(address));
+            events.push(AdapterEvent::DeviceAdded(address));
+        }
+        Ok(stream::iter(events).boxed())
+    }
+
+    async fn adapter_events(&self) -> bluer::Result<BoxStream<'static, AdapterEvent>> {
+        Ok(receiver_stream(self.adapter_events.subscribe()).boxed())
+    }
+
+    async fn device_addresses(&self) -> bluer::Result<Vec<Address>> {
+        Ok(self.state.lock().unwrap().devices.keys().copied().collect())
+    }
+
+    async fn device_properties(&self, address: Address) -> bluer::Result<DeviceProperties> {
+        self.device(address).ok_or_else(|| error(ErrorKind::DoesNotExist, "no such device"))
+    }
+
+    async fn device_events(&self, address: Address) -> bluer::Result<BoxStream<'static, DeviceEvent>> {
+        let events = receiver_stream(self.device_events.subscribe())
+            .filter_map(move |(device, property)| async move {
+                (device == address).then_some(DeviceEvent::PropertyChanged(property))
+            });
+        Ok(events.boxed())
+    }
+
+    async fn pair(&self, address: Address) -> bluer::Result<()> {
+        self.record("pair", Some(address))?;
+        self.wait().await;
+        self.update(address, DeviceProperty::Paired(true))
+    }
+
+    async fn cancel_pairing(&self, address: Address) -> bluer::Result<()> {
+        self.record("cancel_pairing", Some(address))
+    }
+
+    async fn connect(&self, address: Address) -> bluer::Result<()> {
+        self.record("connect", Some(address))?;
+        self.wait().await;
+        self.update(address, DeviceProperty::Connected(true))
+    }
+
+    async fn disconnect(&self, address: Address) -> bluer::Result<()> {
+        self.record("disconnect", Some(address))?;
+        self.wait().await;
+        self.update(address, DeviceProperty::Connected(false))
+    }
+
+    async fn set_trusted(&self, address: Address, trusted: bool) -> bluer::Result<()> {
+        self.record("set_trusted", Some(address))?;
+        self.update(address, DeviceProperty::Trusted(trusted))
+    }
+
+    async fn set_blocked(&self, address: Address, blocked: bool) -> bluer::Result<()> {
+        self.record("set_blocked", Some(address))?;
+        if blocked {
+            self.update(address, DeviceProperty::Connected(false))?;
+        }
+        self.update(address, DeviceProperty::Blocked(blocked))
+    }
+
+    async fn set_alias(&self, address: Address, alias: String) -> bluer::Result<()> {
+        self.record("set_alias", Some(address))?;
+        let alias = match alias.is_empty() {
+            // as BlueZ does, the remote name or else the address
+            true => self.device(address).and_then(|d| d.name).unwrap_or(address.to_string().replace(':', "-")),
+            false => alias,
+        };
+        self.update(address, DeviceProperty::Alias(alias))
+    }
+
+    async fn remove_device(&self, address: Address) -> bluer::Result<()> {
+        self.record("remove_device", Some(address))?;
+        let mut state = self.state.lock().unwrap();
+        match state.devices.remove(&address) {
+            Some(_) => {
+                let _ = self.adapter_events.send(AdapterEvent::DeviceRemoved(address));
+                Ok(())
+            }
+            None => Err(error(ErrorKind::DoesNotExist, "no such device")),
+        }
+    }
+
+    async fn gatt_services(&self, address: Address) -> bluer::Result<Vec<GattService>> {
+        self.record("gatt_services", Some(address))?;
+        self.check_gatt(address, None)?;
+        Ok(self.state.lock().unwrap().gatt.get(&address).cloned().unwrap_or_default())
+    }
+
+    async fn gatt_read(&self, address: Address, handle: GattHandle) -> bluer::Result<Vec<u8>> {
+        self.record("gatt_read", Some(address))?;
+        self.check_gatt(address, Some(handle))?;
+        Ok(self.gatt_value(address, handle).unwrap_or_default())
+    }
+
+    async fn gatt_write(&self, address: Address, handle: GattHandle, value: Vec<u8>) -> bluer::Result<()> {
+        self.record("gatt_write", Some(address))?;
+        self.check_gatt(address, Some(handle))?;
+        self.state.lock().unwrap().gatt_values.insert((address, handle), value);
+        Ok(())
+    }
+
+    async fn gatt_notify(&self, address: Address, handle: GattHandle) -> bluer::Result<BoxStream<'static, Vec<u8>>> {
+        self.record("gatt_notify", Some(address))?;
+        self.check_gatt(address, Some(handle))?;
+        let values = receiver_stream(self.gatt_events.subscribe())
+            .filter_map(move |(device, characteristic, value)| async move {
+                (device == address && characteristic == handle).then_some(value)
+            });
+        Ok(values.boxed())
+    }
+}
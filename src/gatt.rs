@@ -0,0 +1,384 @@
+use btui::backend::{Backend, GattHandle, GattService};
+use crate::config::{Action, KeyMap};
+use bluer::{Address, Uuid, gatt::CharacteristicFlags};
+use futures::StreamExt;
+use ratatui::crossterm::event::KeyCode;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex}
+};
+
+// older values are dropped from the log past this
+const LOG_LENGTH: usize = 500;
+
+/*
+ * One line of the service tree
+*/
+pub struct GattRow
+{
+    pub depth: usize,
+    pub uuid: Uuid,
+    pub name: Option<String>,
+    pub flags: Vec<&'static str>,
+    // services only group the rows below them
+    pub handle: Option<GattHandle>,
+}
+
+/*
+ * Value being typed for a write, as hex bytes or UTF-8 text
+*/
+pub struct WriteInput
+{
+    pub handle: GattHandle,
+    pub text: String,
+    pub hex: bool,
+}
+
+/*
+ * Pane listing the GATT services of a connected device,
+ * values read, written and notified go to a shared log
+*/
+pub struct GattExplorer
+{
+    pub address: Address,
+    pub device_name: String,
+    pub rows: Vec<GattRow>,
+    pub selected_index: usize,
+    pub input: Option<WriteInput>,
+    pub log: Arc<Mutex<Vec<String>>>,
+    // lines scrolled back from the end of the log
+    pub log_scroll: usize,
+    subscriptions: HashMap<GattHandle, tokio::task::JoinHandle<()>>,
+}
+
+impl GattExplorer {
+    pub fn new(address: Address, device_name: String, services: Vec<GattService>) -> Self {
+        Self {
+            address,
+            device_name,
+            rows: build_rows(services),
+            selected_index: 0,
+            input: None,
+            log: Arc::new(Mutex::new(vec![])),
+            log_scroll: 0,
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    pub fn is_subscribed(&self, handle: &GattHandle) -> bool {
+        self.subscriptions.contains_key(handle)
+    }
+
+    pub fn keys(&self, keys: &KeyMap) -> String {
+        match self.input {
+            Some(_) => "(Enter) Write | (Tab) Hex/UTF-8 | (Esc) Cancel".to_string(),
+            None => format!(
+                "{} | ({}) Scroll log | (Esc) Back",
+                [Action::GattRead, Action::GattWrite, Action::GattNotify].map(|action| keys.hint(action)).join(" | "),
+                keys.keys(&[Action::ScrollUp, Action::ScrollDown]),
+            ),
+        }
+    }
+
+    /*
+     * Returns false once the pane is closed, `action` is looked up in the GATT view
+    */
+    pub fn handle_key<B: Backend>(&mut self, backend: &B, code: KeyCode, action: Option<Action>) -> bool {
+        if let Some(input) = &mut self.input {
+            match code {
+                KeyCode::Esc => self.input = None,
+                KeyCode::Tab => input.hex = !input.hex,
+                KeyCode::Backspace => {
+                    input.text.pop();
+                }
+                KeyCode::Enter => {
+                    let input = self.input.take().unwrap();
+                    self.write(backend, input);
+                }
+                KeyCode::Char(c) => input.text.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        let selected = self.rows.get(self.selected_index).and_then(|row| row.handle);
+        match (code, action) {
+            (_, Some(Action::GattRead)) => {
+                if let Some(handle) = selected {
+                    self.read(backend, handle);
+                }
+            }
+            (_, Some(Action::GattWrite)) => {
+                if let Some(handle) = selected {
+                    self.input = Some(WriteInput { handle, text: String::new(), hex: true });
+                }
+            }
+            (_, Some(Action::GattNotify)) => {
+                if let Some(handle) = selected.filter(|handle| handle.descriptor.is_none()) {
+                    self.toggle_notify(backend, handle);
+                }
+            }
+            (_, Some(Action::ScrollUp)) => self.log_scroll = (self.log_scroll + 10).min(self.log.lock().unwrap().len().saturating_sub(1)),
+            (_, Some(Action::ScrollDown)) => self.log_scroll = self.log_scroll.saturating_sub(10),
+            (_, Some(Action::Up)) if !self.rows.is_empty() => {
+                self.selected_index = (self.selected_index + self.rows.len() - 1) % self.rows.len();
+            }
+            (_, Some(Action::Down)) if !self.rows.is_empty() => {
+                self.selected_index = (self.selected_index + 1) % self.rows.len();
+            }
+            (KeyCode::Esc, _) | (_, Some(Action::Quit)) => {
+                self.close();
+                return false;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /*
+     * Stops every notification session
+    */
+    pub fn close(&mut self) {
+        for (_, handle) in self.subscriptions.drain() {
+            handle.abort();
+        }
+    }
+
+    fn label(&self, handle: GattHandle) -> String {
+        self.rows.iter()
+            .find(|row| row.handle == Some(handle))
+            .map(|row| row.name.clone().unwrap_or(row.uuid.to_string()))
+            .unwrap_or_default()
+    }
+
+    fn read<B: Backend>(&self, backend: &B, handle: GattHandle) {
+        let backend = backend.clone();
+        let address = self.address;
+        let label = self.label(handle);
+        let log = self.log.clone();
+
+        tokio::spawn(async move {
+            let line = match backend.gatt_read(address, handle).await {
+                Ok(value) => format!("{} read: {}", label, format_value(&value)),
+                Err(err) => format!("{} read failed: {}", label, err),
+            };
+            push_log(&log, line);
+        });
+    }
+
+    fn write<B: Backend>(&self, backend: &B, input: WriteInput) {
+        let label = self.label(input.handle);
+        let value = if input.hex {
+            match parse_hex(&input.text) {
+                Some(value) => value,
+                None => {
+                    push_log(&self.log, format!("{}: \"{}\" is not hex, e.g. 01 ff or 0x01ff", label, input.text));
+                    return;
+                }
+            }
+        } else {
+            input.text.into_bytes()
+        };
+
+        let backend = backend.clone();
+        let address = self.address;
+        let log = self.log.clone();
+
+        tokio::spawn(async move {
+            let line = match backend.gatt_write(address, input.handle, value.clone()).await {
+                Ok(()) => format!("{} wrote: {}", label, format_value(&value)),
+                Err(err) => format!("{} write failed: {}", label, err),
+            };
+            push_log(&log, line);
+        });
+    }
+
+    fn toggle_notify<B: Backend>(&mut self, backend: &B, handle: GattHandle) {
+        let label = self.label(handle);
+        if let Some(task) = self.subscriptions.remove(&handle) {
+            task.abort();
+            push_log(&self.log, format!("{} unsubscribed", label));
+            return;
+        }
+
+        let backend = backend.clone();
+        let address = self.address;
+        let log = self.log.clone();
+
+        let task = tokio::spawn(async move {
+            let mut values = match backend.gatt_notify(address, handle).await {
+                Ok(values) => values,
+                Err(err) => {
+                    push_log(&log, format!("{} subscribe failed: {}", label, err));
+                    return;
+                }
+            };
+            push_log(&log, format!("{} subscribed", label));
+            while let Some(value) = values.next().await {
+                push_log(&log, format!("{} notified: {}", label, format_value(&value)));
+            }
+        });
+        self.subscriptions.insert(handle, task);
+    }
+}
+
+fn push_log(log: &Mutex<Vec<String>>, line: String)
+{
+    let mut log = log.lock().unwrap();
+    log.push(line);
+    if log.len() > LOG_LENGTH {
+        let excess = log.len() - LOG_LENGTH;
+        log.drain(..excess);
+    }
+}
+
+/*
+ * Flattens the services into the rows of the tree, in the order BlueZ numbers them
+*/
+fn build_rows(mut services: Vec<GattService>) -> Vec<GattRow>
+{
+    services.sort_by_key(|service| service.id);
+    let mut rows = vec![];
+
+    for mut service in services {
+        rows.push(GattRow {
+            depth: 0,
+            uuid: service.uuid,
+            name: bluer::id::Service::try_from(service.uuid).ok().map(|id| id.to_string()),
+            flags: if service.primary { vec!["primary"] } else { vec!["secondary"] },
+            handle: None,
+        });
+
+        service.characteristics.sort_by_key(|characteristic| characteristic.id);
+        for mut characteristic in service.characteristics {
+            let handle = GattHandle { service: service.id, characteristic: characteristic.id, descriptor: None };
+            rows.push(GattRow {
+                depth: 1,
+                uuid: characteristic.uuid,
+                name: bluer::id::Characteristic::try_from(characteristic.uuid).ok().map(|id| id.to_string()),
+                flags: flag_names(&characteristic.flags),
+                handle: Some(handle),
+            });
+
+            characteristic.descriptors.sort_by_key(|descriptor| descriptor.id);
+            for descriptor in characteristic.descriptors {
+                rows.push(GattRow {
+                    depth: 2,
+                    uuid: descriptor.uuid,
+                    name: bluer::id::Descriptor::try_from(descriptor.uuid).ok().map(|id| id.to_string()),
+                    flags: vec![],
+                    handle: Some(GattHandle { descriptor: Some(descriptor.id), ..handle }),
+                });
+            }
+        }
+    }
+    rows
+}
+
+fn flag_names(flags: &CharacteristicFlags) -> Vec<&'static str>
+{
+    [
+        (flags.broadcast, "broadcast"),
+        (flags.read, "read"),
+        (flags.write, "write"),
+        (flags.write_without_response, "write-without-response"),
+        (flags.reliable_write, "reliable-write"),
+        (flags.authenticated_signed_writes, "signed-write"),
+        (flags.notify, "notify"),
+        (flags.indicate, "indicate"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect()
+}
+
+/*
+ * Hex bytes, followed by the text when the value is printable
+*/
+pub fn format_value(value: &[u8]) -> String
+{
+    let hex: Vec<String> = value.iter().map(|byte| format!("{:02x}", byte)).collect();
+    match std::str::from_utf8(value) {
+        Ok(text) if !text.is_empty() && text.chars().all(|c| !c.is_control()) => format!("{} \"{}\"", hex.join(" "), text),
+        _ => hex.join(" "),
+    }
+}
+
+/*
+ * "01 ff", "01ff" or "0x01ff", every byte is two digits and spaces only go between bytes
+*/
+fn parse_hex(text: &str) -> Option<Vec<u8>>
+{
+    let text = text.trim();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    let mut bytes = vec![];
+    for group in text.split_whitespace() {
+        if !group.len().is_multiple_of(2) || !group.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        for i in (0..group.len()).step_by(2) {
+            bytes.push(u8::from_str_radix(&group[i..i + 2], 16).ok()?);
+        }
+    }
+    Some(bytes).filter(|bytes| !bytes.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, View};
+    use btui::{backend::{GattCharacteristic, parse_uuid}, fake::FakeBackend};
+
+    #[test]
+    fn hex_is_read_with_or_without_spaces_and_prefix() {
+        assert_eq!(parse_hex("01 ff"), Some(vec![0x01, 0xff]));
+        assert_eq!(parse_hex("01FF"), Some(vec![0x01, 0xff]));
+        assert_eq!(parse_hex(" 0x01ff "), Some(vec![0x01, 0xff]));
+        assert_eq!(parse_hex("01  ff 0203"), Some(vec![0x01, 0xff, 0x02, 0x03]));
+    }
+
+    #[test]
+    fn bad_hex_is_refused() {
+        assert_eq!(parse_hex(""), None);
+        assert_eq!(parse_hex("0x"), None);
+        assert_eq!(parse_hex("1ff"), None, "an odd number of digits");
+        assert_eq!(parse_hex("0 1"), None, "a byte is not split by a space");
+        assert_eq!(parse_hex("1 23"), None);
+        assert_eq!(parse_hex("01 f"), None);
+        assert_eq!(parse_hex("+f"), None);
+        assert_eq!(parse_hex("zz"), None);
+        assert_eq!(parse_hex("éé"), None);
+    }
+
+    #[test]
+    fn printable_values_are_shown_as_text_too() {
+        assert_eq!(format_value(b"Hi"), "48 69 \"Hi\"");
+        assert_eq!(format_value(&[0x64]), "64 \"d\"");
+        assert_eq!(format_value(&[0x00, 0x0a]), "00 0a");
+        assert_eq!(format_value(&[0xff, 0xfe]), "ff fe", "not UTF-8");
+        assert_eq!(format_value(&[]), "");
+    }
+
+    #[test]
+    fn the_keys_follow_the_config() {
+        let config = Config::parse("[keys]\ngatt_write = \"F2\"\n").unwrap();
+        let backend = FakeBackend::new("hci0");
+        let battery = GattService {
+            id: 1,
+            uuid: parse_uuid("180f").unwrap(),
+            primary: true,
+            characteristics: vec![GattCharacteristic { id: 2, uuid: parse_uuid("2a19").unwrap(), flags: Default::default(), descriptors: vec![] }],
+        };
+        let mut explorer = GattExplorer::new(Address::any(), "Headphones".to_string(), vec![battery]);
+        let press = |explorer: &mut GattExplorer, code| explorer.handle_key(&backend, code, config.keys.action_in(View::Gatt, code));
+
+        assert!(press(&mut explorer, KeyCode::Char('j')));
+        assert!(press(&mut explorer, KeyCode::Char('w')));
+        assert!(explorer.input.is_none(), "w no longer writes");
+        assert!(press(&mut explorer, KeyCode::F(2)));
+        assert!(explorer.input.as_ref().is_some_and(|input| input.handle.characteristic == 2));
+        assert_eq!(explorer.keys(&config.keys), "(Enter) Write | (Tab) Hex/UTF-8 | (Esc) Cancel");
+        explorer.input = None;
+        assert_eq!(explorer.keys(&config.keys), "(R)ead | [F2] Write | (N)otify | (PgUp/PgDn) Scroll log | (Esc) Back");
+    }
+}
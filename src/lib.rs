@@ -0,0 +1,53 @@
+//! Device management behind the btui terminal UI, usable on its own.
+//!
+//! Everything goes through a [`backend::Backend`]: [`backend::BluerBackend`] talks to
+//! bluetoothd over D-Bus, [`fake::FakeBackend`] keeps an adapter in memory for tests.
+//! [`manager`] builds the device list on top of it and keeps it in sync with BlueZ,
+//! [`store::DeviceStore`] remembers the paired devices between runs.
+//!
+//! ```no_run
+//! use btui::{activity::ActivityLog, backend::BluerBackend, manager};
+//! use std::sync::{Arc, Mutex};
+//!
+//! # async fn example() -> btui::error::Result<()> {
+//! let session = bluer::Session::new().await?;
+//! let backend = BluerBackend::new(&session, None).await?;
+//! let log = ActivityLog::default();
+//!
+//! let mut paired = vec![];
+//! let store = manager::initiate(&backend, &mut paired).await?;
+//! let devices = Arc::new(Mutex::new(vec![]));
+//! manager::load_device_list(&backend, &paired, devices.clone(), &store, &log);
+//!
+//! if let Some(device) = devices.lock().unwrap().first() {
+//!     println!("{} {:?}", device.display_name(), device.state);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+/// What btui did and what BlueZ reported.
+pub mod activity;
+/// Pairing agent forwarding BlueZ's requests over a channel.
+pub mod agent;
+/// Adapter access, the seam between btui and BlueZ.
+pub mod backend;
+/// Decoders for classes, appearances, UUIDs and manufacturers.
+pub mod details;
+pub mod error;
+/// In-memory backend for tests.
+pub mod fake;
+/// Device model and the adapter operations built on a backend.
+pub mod manager;
+/// Device operations run as tracked background tasks.
+pub mod operations;
+/// Radio kill switches holding the adapters back.
+pub mod rfkill;
+/// Filtering the device list.
+pub mod search;
+/// Ordering and grouping the device list.
+pub mod sort;
+/// Messages about finished operations, shared with background tasks.
+pub mod status;
+/// Devices remembered between runs.
+pub mod store;
@@ -1,316 +1,1081 @@
-mod manager;
-use bluer::{Adapter, Session};
-use std::{path::PathBuf, vec};
-use color_eyre::{Result};
+mod cli;
+mod config;
+// shared with tests/bluez.rs, which uses the rest of it
+#[cfg(test)]
+#[path = "../tests/fake_bluez/mod.rs"]
+#[allow(dead_code)]
+mod fake_bluez;
+mod gatt;
+mod prompt;
+mod scan_options;
+mod settings;
+mod ui;
+use btui::activity::{self, ActivityLog, Level};
+use btui::agent::{self, AgentRequest};
+use btui::backend::{Backend, BluerBackend};
+use btui::error;
+use btui::manager::{self, ConnectionState, DeviceInfo};
+use btui::operations::{OperationKind, OperationTarget, Operations};
+use btui::rfkill::{Block, Rfkill};
+use btui::search::ListFilter;
+use btui::status::{Severity, StatusBar};
+use btui::store::DeviceStore;
+use bluer::Session;
+use clap::Parser;
+use cli::Cli;
+use config::{Action, Config, View};
+use gatt::GattExplorer;
+use prompt::AgentPrompt;
+use scan_options::ScanOptions;
+use settings::AdapterSettings;
+use std::{collections::HashMap, process::ExitCode};
+use color_eyre::{Result, eyre::eyre};
 use ratatui::{
-    DefaultTerminal, Frame, crossterm::event::{self, Event, KeyCode}, widgets::{Block, Borders, List, ListItem, ListState, Paragraph}
+    DefaultTerminal, crossterm::event::{self, Event, KeyCode}
 };
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use tokio::sync::mpsc;
 
 struct AppState {
     devices_list: Arc<Mutex<Vec<manager::DeviceInfo>>>,
+    store: DeviceStore,
+    // position in the filtered list
     selected_index: usize,
+    // device the selection stays on while the list is filtered or reordered
+    selected: Option<bluer::Address>,
+    filter: ListFilter,
+    // typing the query after `/`
+    searching: bool,
 }
 
 impl AppState {
-    fn new(devices_list: Arc<Mutex<Vec<manager::DeviceInfo>>>) -> Self {
+    fn new(devices_list: Arc<Mutex<Vec<manager::DeviceInfo>>>, store: DeviceStore) -> Self {
         Self {
             devices_list,
+            store,
             selected_index: 0,
+            selected: None,
+            filter: ListFilter::default(),
+            searching: false,
         }
     }
-    
+
+    /*
+     * Indices in `devices` of the devices the filter lets through, in the order they are listed
+    */
+    fn visible(&self, devices: &[DeviceInfo]) -> Vec<usize> {
+        let sort = self.store.sort_key();
+        let last_seen = |device: &DeviceInfo| self.store.get(device.address).and_then(|stored| stored.last_seen);
+        let mut visible: Vec<usize> = (0..devices.len()).filter(|&i| self.filter.matches(&devices[i])).collect();
+        visible.sort_by(|&a, &b| sort.compare(&devices[a], &devices[b], last_seen));
+        visible
+    }
+
     fn select_next(&mut self) {
-        let len = self.devices_list.lock().unwrap().len();
-        if len > 0 {
-            self.selected_index = (self.selected_index + 1) % len;
-        }
+        self.select_by(|index, len| (index + 1) % len);
     }
-    
+
     fn select_previous(&mut self) {
-        let len = self.devices_list.lock().unwrap().len();
-        if len > 0 {
-            self.selected_index = if self.selected_index == 0 {
-                len - 1
-            } else {
-                self.selected_index - 1
-            };
+        self.select_by(|index, len| (index + len - 1) % len);
+    }
+
+    fn select_by(&mut self, step: impl FnOnce(usize, usize) -> usize) {
+        let devices = self.devices_list.lock().unwrap();
+        let visible = self.visible(&devices);
+        if !visible.is_empty() {
+            self.selected_index = step(self.selected_index.min(visible.len() - 1), visible.len());
+            self.selected = Some(devices[visible[self.selected_index]].address);
+        }
+    }
+
+    /*
+     * The list changes on its own and with the filter, keep the selection on the same device
+     * or, once it is gone, at the same place
+    */
+    fn follow_selection(&mut self) {
+        let devices = self.devices_list.lock().unwrap();
+        let visible = self.visible(&devices);
+        match visible.iter().position(|&i| Some(devices[i].address) == self.selected) {
+            Some(index) => self.selected_index = index,
+            None => {
+                self.selected_index = self.selected_index.min(visible.len().saturating_sub(1));
+                self.selected = visible.get(self.selected_index).map(|&i| devices[i].address);
+            }
         }
     }
 
-    fn reset_index(&mut self) {
-        let len = self.devices_list.lock().unwrap().len();
-        self.selected_index = len - 1;
+    fn selected(&self) -> Option<DeviceInfo> {
+        let address = self.selected?;
+        self.devices_list.lock().unwrap().iter().find(|d| d.address == address).cloned()
     }
 }
 
 
 #[tokio::main(flavor = "multi_thread")]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
+
+    let cli = Cli::parse();
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("btui: invalid configuration in {}", config::config_path().unwrap_or_default().display());
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    if let Some(command) = cli.command {
+        return Ok(cli::run(cli.adapter.as_deref(), command, config.scan_timeout).await);
+    }
+
+    let session = Session::new().await?;
+    let backend = BluerBackend::new(&session, cli.adapter.as_deref()).await?;
+
+    // btui answers pairing requests itself, the agent stays registered as long as the handle lives
+    let (agent_tx, agent_rx) = mpsc::unbounded_channel();
+    let _agent_handle = session.register_agent(agent::build_agent(agent_tx)).await?;
+
+    let mut app = App::new(backend, agent_rx, config).await?;
 
-    let session = Session::new().await?; 
-    let mut paired_devices: Vec<bluer::Address> = vec![];
-    let adapter_path : PathBuf = manager::initiate(&session, &mut paired_devices).await.expect("An error occured while trying to initiate the program");
-    
     let terminal = ratatui::init();
-    let result = run(terminal, &session, &mut paired_devices, &adapter_path).await;
+    install_panic_hook();
+    let result = run(terminal, &mut app).await;
     ratatui::restore();
-    result
+    result.map(|_| ExitCode::SUCCESS)
+}
+
+// set by the panic hook, the event loop stops drawing once it is
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+/*
+ * A panic anywhere, in a spawned task too, puts the terminal back before the previous hook prints it.
+ * A panic of the main thread then unwinds out of main, one of a task ends the event loop:
+ * the TUI would otherwise go on drawing into a terminal no longer in raw mode
+*/
+fn install_panic_hook()
+{
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        ratatui::restore();
+        PANICKED.store(true, Ordering::Relaxed);
+        hook(info);
+    }));
+}
+
+/*
+ * One adapter with its own device list and cache directory.
+ * Views of the adapters not shown keep being updated in the background.
+*/
+struct AdapterView<B: Backend> {
+    backend: B,
+    paired_devices: Vec<bluer::Address>,
+    store: DeviceStore,
+    devices_list: Arc<Mutex<Vec<manager::DeviceInfo>>>,
+    // kept up to date by manager::watch_adapter
+    powered: Arc<AtomicBool>,
+    scan_handle: Option<tokio::task::JoinHandle<()>>,
 }
 
-async fn run(mut terminal: DefaultTerminal, session: &Session, paired_devices: &mut Vec<bluer::Address>, dir: &PathBuf) -> Result<()> {
-    let adapter: Adapter = manager::get_adapter(&session).await.expect("Unable to get any adapter");
-    let devices_list = Arc::new(Mutex::new(Vec::<manager::DeviceInfo>::new()));
-    
-    {
-        let mut list = devices_list.lock().unwrap();
-        paired_to_render(&mut list, &paired_devices, &session).await.expect("An error occured while loading paired devices...");
+impl<B: Backend> AdapterView<B> {
+    async fn open(backend: B, status: &StatusBar) -> Result<Self> {
+        let log = status.log();
+        let mut paired_devices: Vec<bluer::Address> = vec![];
+        let store = manager::initiate(&backend, &mut paired_devices).await?;
+        let devices_list = Arc::new(Mutex::new(Vec::<manager::DeviceInfo>::new()));
+        let powered = Arc::new(AtomicBool::new(backend.is_powered().await?));
+
+        if let Some(problem) = store.problem() {
+            status.warn(problem);
+        }
+        manager::load_device_list(&backend, &paired_devices, devices_list.clone(), &store, log);
+        manager::watch_adapter(&backend, powered.clone(), devices_list.clone(), log);
+        log.info(format!("Opened {}, {} paired devices", backend.adapter_name(), paired_devices.len()));
+
+        Ok(Self {
+            backend,
+            paired_devices,
+            store,
+            devices_list,
+            powered,
+            scan_handle: None,
+        })
     }
 
-    let mut app_state = AppState::new(devices_list.clone());
-    let mut scan_handle: Option<tokio::task::JoinHandle<_>> = None;
-    
-    loop {
-        let adapter_status: bool = adapter.is_powered().await?;
-        terminal.draw(|frame| {
-            render(frame, &app_state, adapter_status, scan_handle.is_some());
-        })?;
+    fn name(&self) -> &str {
+        self.backend.adapter_name()
+    }
+}
 
+/*
+ * Adapter switcher popup
+*/
+struct AdapterPicker {
+    names: Vec<String>,
+    selected_index: usize,
+}
 
-        if let Some(handle) = &mut scan_handle {
-            if handle.is_finished() {
-                scan_handle = None;
-            }
+/*
+ * Alias being typed for a device, shown under the list
+*/
+struct RenameInput {
+    address: bluer::Address,
+    text: String,
+    // what an empty alias goes back to
+    remote_name: Option<String>,
+}
+
+/*
+ * Activity log pane under the device list
+*/
+struct LogPane {
+    // entries below it are hidden
+    level: Level,
+    // lines scrolled back from the newest entry
+    scroll: usize,
+}
+
+/*
+ * A view a background operation finished reading
+*/
+enum Opened {
+    Gatt(GattExplorer),
+    Settings(AdapterSettings),
+}
+
+/*
+ * Everything the event loop works on, independent from the terminal
+ * so that key handling can be driven by a fake backend.
+*/
+struct App<B: Backend> {
+    adapter: AdapterView<B>,
+    parked: HashMap<String, AdapterView<B>>,
+    app_state: AppState,
+    adapter_status: bool,
+    rfkill: Rfkill,
+    // refreshed every tick, a switch can be flipped at any time
+    blocked: Block,
+    // device operations run in the background so agent prompts can be answered meanwhile
+    operations: Operations,
+    agent_requests: mpsc::UnboundedReceiver<AgentRequest>,
+    prompt: Option<AgentPrompt>,
+    picker: Option<AdapterPicker>,
+    rename: Option<RenameInput>,
+    settings: Option<AdapterSettings>,
+    // filter of the next scans
+    scan_options: Option<ScanOptions>,
+    gatt: Option<GattExplorer>,
+    // views whose content is read in the background, shown by the next tick
+    opened: Arc<Mutex<Option<Opened>>>,
+    // detail panel next to the device list
+    detail: bool,
+    log_pane: Option<LogPane>,
+    // writes to the activity log as well
+    status: StatusBar,
+    config: Config,
+}
+
+impl<B: Backend> App<B> {
+    async fn new(backend: B, agent_requests: mpsc::UnboundedReceiver<AgentRequest>, config: Config) -> Result<Self> {
+        let status = StatusBar::new(ActivityLog::default());
+        let adapter = AdapterView::open(backend, &status).await?;
+
+        Ok(Self {
+            app_state: AppState::new(adapter.devices_list.clone(), adapter.store.clone()),
+            adapter_status: adapter.powered.load(Ordering::Relaxed),
+            rfkill: Rfkill::default(),
+            blocked: Block::Unblocked,
+            adapter,
+            parked: HashMap::new(),
+            operations: Operations::default(),
+            agent_requests,
+            prompt: None,
+            picker: None,
+            rename: None,
+            settings: None,
+            scan_options: None,
+            gatt: None,
+            opened: Arc::new(Mutex::new(None)),
+            detail: false,
+            log_pane: None,
+            status,
+            config,
+        })
+    }
+
+    /*
+     * Shows `name`, reusing its view if it was opened before
+    */
+    async fn switch_adapter(&mut self, name: &str) -> Result<()> {
+        if name == self.adapter.name() {
+            return Ok(());
         }
-        if event::poll(std::time::Duration::from_millis(200))? {
-            if let Event::Key(key) = event::read()?
+
+        let next = match self.parked.remove(name) {
+            Some(view) => view,
+            None => AdapterView::open(self.adapter.backend.for_adapter(name).await?, &self.status).await?,
+        };
+        let previous = std::mem::replace(&mut self.adapter, next);
+        self.parked.insert(previous.name().to_string(), previous);
+
+        self.app_state = AppState::new(self.adapter.devices_list.clone(), self.adapter.store.clone());
+        self.adapter_status = self.adapter.powered.load(Ordering::Relaxed);
+        Ok(())
+    }
+
+    /*
+     * Catch up with what changed in the background between two frames
+    */
+    fn tick(&mut self) {
+        self.adapter_status = self.adapter.powered.load(Ordering::Relaxed);
+        let blocked = self.rfkill.block(self.adapter.name());
+        if blocked != self.blocked {
+            self.status.log().info(format!("{}: rfkill {}", self.adapter.name(), blocked.label()));
+            self.blocked = blocked;
+        }
+        self.app_state.follow_selection();
+
+        match self.opened.lock().unwrap().take() {
+            Some(Opened::Gatt(explorer)) => self.gatt = Some(explorer),
+            Some(Opened::Settings(settings)) => self.settings = Some(settings),
+            None => {}
+        }
+
+        for view in std::iter::once(&mut self.adapter).chain(self.parked.values_mut()) {
+            if let Some(handle) = &view.scan_handle
+                && handle.is_finished()
             {
-                match key.code
-                {
-                    KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Esc =>
-                    {
-                        if let Some(handle) = scan_handle {
-                            handle.abort();
-                        }
-                        break;
-                    }
-                    KeyCode::Char('o') | KeyCode::Char('O') =>
-                    {
-                        manager::power_adapter(&session).await?;
-                        refresh_device_list(devices_list.clone(), paired_devices, session).await?;
-                    }
-                    KeyCode::Char('s') | KeyCode::Char('S') =>
-                    {
-                        if scan_handle.is_none() & adapter_status
-                        {
-                            let session_clone = session.clone();
-                            let mut paired_clone = paired_devices.clone();
-                            let dir_clone = dir.clone();
-                            let devices_list_clone = devices_list.clone();
-
-                            // Delete previous scan results (devices that aren't paired)
-                            {
-                                let mut list = devices_list.lock().unwrap();
-                                list.retain(|x| x.paired != " ");
-                            }
-
-                            scan_handle = Some(tokio::spawn(async move {
-                                manager::scan_devices(&session_clone, &mut paired_clone, &dir_clone, devices_list_clone).await.expect("Unable to start scanning...");
-                            }));
-                        }
-                    }
-                    KeyCode::Up | KeyCode::Char('k') | KeyCode::Char('K') =>
-                    {
-                        app_state.select_previous();
-                    }
-                    KeyCode::Down | KeyCode::Char('j') | KeyCode::Char('J') =>
-                    {
-                        app_state.select_next();
-                    }
-                    KeyCode::Char('c') | KeyCode::Char('C') | KeyCode::Enter=>
-                    {
-                        if adapter_status
-                        {
-                            let device_address = app_state.devices_list.lock().unwrap()[app_state.selected_index].address.to_string();
-                            manager::dis_connect_device(&session, device_address).await.expect("Error occured while connecting to the selected device.");
-
-                            refresh_device_list(devices_list.clone(), paired_devices, session).await?;
-                            app_state.reset_index();
-                        }
-                    }
-                    KeyCode::Char('p') | KeyCode::Char('P')=>
-                    {
-                        if adapter_status{
-                            let device_address = app_state.devices_list.lock().unwrap()[app_state.selected_index].address.to_string();
-                            manager::pair_device(&session, device_address).await.expect("Error occured while pairing to the selected device.");
-
-                            refresh_device_list(devices_list.clone(), paired_devices, session).await?;
-                            app_state.reset_index();
-                        }
-                    }
-                    KeyCode::Char('t') | KeyCode::Char('T')=>
-                    {
-                        if adapter_status{
-                            let device_address = app_state.devices_list.lock().unwrap()[app_state.selected_index].address.to_string();
-                            manager::un_trust_device(&session, device_address).await.expect("An error occured");
-
-                            refresh_device_list(devices_list.clone(), paired_devices, session).await?;
-                            app_state.reset_index();
-                        }
-                    }
-                    KeyCode::Char('f') | KeyCode::Char('F')=>
-                    {
-                        if adapter_status{
-                            let device_address = app_state.devices_list.lock().unwrap()[app_state.selected_index].address.to_string();
-                            manager::forget_device(&session, device_address, devices_list.clone()).await.expect("An error occured");
-                        }
+                view.scan_handle = None;
+            }
+        }
+
+        self.poll_agent();
+    }
+
+    fn poll_agent(&mut self) {
+        if let Some(prompt) = &mut self.prompt
+            && prompt.request.is_cancelled()
+        {
+            self.prompt = None;
+        }
+
+        while self.prompt.as_ref().is_none_or(|prompt| prompt.request.is_display()) {
+            let Ok(request) = self.agent_requests.try_recv() else {
+                break;
+            };
+            let device_name = self.adapter.devices_list.lock().unwrap()
+                .iter()
+                .find(|d| d.address == request.device())
+                .map(|d| d.display_name().to_string())
+                .unwrap_or("Unknown".to_string());
+            self.prompt = Some(AgentPrompt::new(request, device_name));
+        }
+    }
+
+    /*
+     * Returns false once the user asked to quit
+    */
+    async fn handle_key(&mut self, code: KeyCode) -> Result<bool> {
+        if let Some(prompt) = self.prompt.take() {
+            self.prompt = prompt.handle_key(code);
+            return Ok(true);
+        }
+
+        if let Some(input) = &mut self.rename {
+            match code {
+                KeyCode::Esc => self.rename = None,
+                KeyCode::Backspace => {
+                    input.text.pop();
+                }
+                KeyCode::Enter | KeyCode::Tab => {
+                    let input = self.rename.take().unwrap();
+                    // Tab resets, so does an empty alias
+                    let alias = Some(input.text.trim().to_string()).filter(|text| code == KeyCode::Enter && !text.is_empty());
+                    let backend = self.adapter.backend.clone();
+                    let name = input.remote_name.clone().unwrap_or(input.address.to_string());
+                    self.start_operation(input.address, name, OperationKind::Renaming, async move {
+                        manager::rename_device(&backend, input.address, alias.as_deref()).await
+                    });
+                }
+                KeyCode::Char(c) => input.text.push(c),
+                _ => {}
+            }
+            return Ok(true);
+        }
+
+        if self.app_state.searching {
+            match code {
+                KeyCode::Esc => {
+                    self.app_state.searching = false;
+                    self.app_state.filter.query.clear();
+                }
+                KeyCode::Enter => self.app_state.searching = false,
+                KeyCode::Backspace => {
+                    self.app_state.filter.query.pop();
+                }
+                KeyCode::Up => self.app_state.select_previous(),
+                KeyCode::Down => self.app_state.select_next(),
+                KeyCode::Char(c) => self.app_state.filter.query.push(c),
+                _ => {}
+            }
+            self.app_state.follow_selection();
+            return Ok(true);
+        }
+
+        let action = self.config.keys.action(code);
+
+        if let Some(explorer) = &mut self.gatt {
+            if !explorer.handle_key(&self.adapter.backend, code, self.config.keys.action_in(View::Gatt, code)) {
+                self.gatt = None;
+            }
+            return Ok(true);
+        }
+
+        if let Some(settings) = &mut self.settings {
+            if !settings.handle_key(&self.adapter.backend, code, action) {
+                self.settings = None;
+            }
+            return Ok(true);
+        }
+
+        if let Some(options) = &mut self.scan_options {
+            if !options.handle_key(code, action) {
+                self.scan_options = None;
+            }
+            return Ok(true);
+        }
+
+        if let Some(picker) = &mut self.picker {
+            match (code, action) {
+                (KeyCode::Enter, _) => {
+                    let name = picker.names[picker.selected_index].clone();
+                    self.picker = None;
+                    if let Err(err) = self.switch_adapter(&name).await {
+                        self.status.show(Severity::Error, format!("Cannot open {}: {}", name, err));
                     }
-                    _ =>{}
                 }
+                (_, Some(Action::Up)) => {
+                    picker.selected_index = (picker.selected_index + picker.names.len() - 1) % picker.names.len();
+                }
+                (_, Some(Action::Down)) => {
+                    picker.selected_index = (picker.selected_index + 1) % picker.names.len();
+                }
+                (KeyCode::Esc, _) | (_, Some(Action::Quit)) => self.picker = None,
+                _ => {}
             }
+            return Ok(true);
         }
-    }
-    Ok(())
-}
 
+        if let Some(pane) = &mut self.log_pane {
+            match action {
+                Some(Action::ScrollUp) => {
+                    let count = self.status.log().entries(pane.level).len();
+                    pane.scroll = (pane.scroll + 10).min(count.saturating_sub(1));
+                    return Ok(true);
+                }
+                Some(Action::ScrollDown) => {
+                    pane.scroll = pane.scroll.saturating_sub(10);
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+
+        // Esc drops the search before it quits
+        if code == KeyCode::Esc && !self.app_state.filter.query.is_empty() {
+            self.app_state.filter.query.clear();
+            self.app_state.follow_selection();
+            return Ok(true);
+        }
 
-fn render(frame: &mut Frame, app_state: &AppState, adapter_status: bool, scan_status: bool) {
-    use ratatui::prelude::*;
-
-    let devices = app_state.devices_list.lock().unwrap();
-    let items: Vec<ListItem> = devices
-        .iter()
-        .map(|d| {
-            ListItem::new(format!("{} | {}     {}    [{}] {} {} ", 
-                d.trusted, d.paired, d.device_type, d.address, d.device_name, d.battery))
-                .add_modifier(
-                    if d.paired == ""{
-                        Modifier::BOLD
-                    } else {
-                        Modifier::empty()
+        let Some(action) = action else {
+            return Ok(true);
+        };
+        let backend = &self.adapter.backend;
+        let adapter_status = self.adapter_status;
+
+        match action
+        {
+            Action::Quit =>
+            {
+                if let Some(explorer) = &mut self.gatt {
+                    explorer.close();
+                }
+                if let Some(settings) = &mut self.settings {
+                    settings.close();
+                }
+                self.operations.cancel_all();
+                for view in std::iter::once(&mut self.adapter).chain(self.parked.values_mut()) {
+                    if let Some(handle) = view.scan_handle.take() {
+                        handle.abort();
                     }
-                )
-                .style(
-                    if d.paired == "" && adapter_status{
-                        Color::LightGreen
-                    } else if !adapter_status {
-                        Color::Red
-                    } else {
-                        Color::White
+                }
+                return Ok(false);
+            }
+            Action::Adapter =>
+            {
+                let names = self.status.report("Listing adapters", backend.adapter_names().await).unwrap_or_default();
+                if !names.is_empty() {
+                    let selected_index = names.iter().position(|n| n == self.adapter.name()).unwrap_or(0);
+                    self.picker = Some(AdapterPicker { names, selected_index });
+                }
+            }
+            Action::Settings =>
+            {
+                let backend = backend.clone();
+                let opened = self.opened.clone();
+                let adapter = self.adapter.name().to_string();
+                self.start_operation(OperationTarget::Adapter(adapter.clone()), adapter, OperationKind::Reading, async move {
+                    let properties = backend.adapter_properties().await?;
+                    *opened.lock().unwrap() = Some(Opened::Settings(AdapterSettings::new(&backend, properties)));
+                    Ok(())
+                });
+            }
+            Action::ScanFilter => self.scan_options = Some(ScanOptions::new(&self.adapter.store)),
+            Action::Search => self.app_state.searching = true,
+            Action::Sort =>
+            {
+                let store = &self.app_state.store;
+                store.set_sort_key(store.sort_key().next());
+                self.app_state.follow_selection();
+            }
+            Action::FilterConnected | Action::FilterPaired | Action::FilterTrusted | Action::FilterBlocked | Action::HideUnnamed =>
+            {
+                let filter = &mut self.app_state.filter;
+                let toggle = match action {
+                    Action::FilterConnected => &mut filter.connected_only,
+                    Action::FilterPaired => &mut filter.paired_only,
+                    Action::FilterTrusted => &mut filter.trusted_only,
+                    Action::FilterBlocked => &mut filter.blocked_only,
+                    _ => &mut filter.hide_unnamed,
+                };
+                *toggle = !*toggle;
+                self.app_state.follow_selection();
+            }
+            Action::Gatt if adapter_status =>
+            {
+                let Some(device) = self.app_state.selected() else {
+                    return Ok(true);
+                };
+                let device_name = device.display_name().to_string();
+                if device.state != ConnectionState::Connected {
+                    self.status.warn(format!("{} is not connected, connect it to explore its services", device_name));
+                    return Ok(true);
+                }
+
+                let address = device.address;
+                let backend = backend.clone();
+                let opened = self.opened.clone();
+                self.start_operation(address, device_name.clone(), OperationKind::Exploring, async move {
+                    let services = backend.gatt_services(address).await?;
+                    *opened.lock().unwrap() = Some(Opened::Gatt(GattExplorer::new(address, device_name, services)));
+                    Ok(())
+                });
+            }
+            Action::Info =>
+            {
+                self.detail = !self.detail;
+            }
+            Action::Log =>
+            {
+                self.log_pane = match self.log_pane {
+                    Some(_) => None,
+                    None => Some(LogPane { level: Level::Info, scroll: 0 }),
+                };
+            }
+            Action::LogLevel =>
+            {
+                if let Some(pane) = &mut self.log_pane {
+                    pane.level = pane.level.next();
+                    pane.scroll = 0;
+                }
+            }
+            Action::SaveLog =>
+            {
+                // everything is saved, whatever level the pane shows
+                let Some(path) = activity::save_path(std::time::SystemTime::now()) else {
+                    self.status.warn("No cache directory to save the log to");
+                    return Ok(true);
+                };
+                match self.status.log().save(&path) {
+                    Ok(()) => self.status.info(format!("Log saved to {}", path.display())),
+                    Err(err) => self.status.show(Severity::Error, format!("Cannot save the log: {}", err)),
+                }
+            }
+            Action::Power if !adapter_status && self.blocked == Block::Hard =>
+            {
+                self.status.show(Severity::Error, self.blocked.explain(self.adapter.name()));
+            }
+            Action::Power if !adapter_status && self.blocked == Block::Soft =>
+            {
+                self.status.warn(format!("{} is soft-blocked by rfkill, {} it first", self.adapter.name(), self.config.keys.hint(Action::Unblock)));
+            }
+            Action::Power =>
+            {
+                self.status.log().info(format!("Turning {} {}", self.adapter.name(), if adapter_status { "off" } else { "on" }));
+                self.status.report("Power", manager::power_adapter(backend).await);
+            }
+            Action::Unblock =>
+            {
+                match self.blocked {
+                    Block::Unblocked => self.status.info(self.blocked.explain(self.adapter.name())),
+                    Block::Hard => self.status.show(Severity::Error, self.blocked.explain(self.adapter.name())),
+                    Block::Soft => match self.rfkill.unblock(self.adapter.name()) {
+                        Ok(()) => {
+                            self.blocked = self.rfkill.block(self.adapter.name());
+                            self.status.info(format!("Unblocked {}, {} to turn it on", self.adapter.name(), self.config.keys.hint(Action::Power)));
+                        }
+                        Err(err) => self.status.show(Severity::Error, format!("Cannot unblock {}: {}", self.adapter.name(), err)),
+                    },
+                }
+            }
+            Action::Scan if self.adapter.scan_handle.is_none() && adapter_status =>
+            {
+                let backend_clone = backend.clone();
+                let mut paired_clone = self.adapter.paired_devices.clone();
+                let store_clone = self.adapter.store.clone();
+                let devices_list_clone = self.adapter.devices_list.clone();
+                let scan_timeout = self.config.scan_timeout;
+                let status = self.status.clone();
+                status.log().info(format!("Scanning on {} for {}s", self.adapter.name(), scan_timeout.as_secs()));
+
+                // Delete previous scan results (devices that aren't paired)
+                {
+                    let mut list = self.adapter.devices_list.lock().unwrap();
+                    list.retain(|x| x.state != ConnectionState::Discovered);
+                }
+
+                self.adapter.scan_handle = Some(tokio::spawn(async move {
+                    let result = manager::scan_devices(&backend_clone, &mut paired_clone, &store_clone, devices_list_clone, scan_timeout, status.log()).await;
+                    if status.report("Scan", result).is_some() {
+                        status.log().info("Scan finished");
                     }
+                }));
+            }
+            Action::Up =>
+            {
+                self.app_state.select_previous();
+            }
+            Action::Down =>
+            {
+                self.app_state.select_next();
+            }
+            Action::Connect if adapter_status =>
+            {
+                let Some(device) = self.app_state.selected() else {
+                    return Ok(true);
+                };
+                let backend = backend.clone();
+                let address = device.address;
+                let disconnect = device.state == ConnectionState::Connected;
 
-                )
-        })
-        .collect();
-
-    let mut list_state = ListState::default();
-    list_state.select(Some(app_state.selected_index));
-
-    let list = List::new(items)
-        .block(Block::new()
-            .borders(Borders::ALL)
-            .title("Devices")
-            .style(Style::default().fg(
-                if scan_status{
-                    Color::LightYellow
-                } else if adapter_status {
-                    Color::White
+                let started = if disconnect {
+                    self.start_operation(address, device.display_name().to_string(), OperationKind::Disconnecting, async move {
+                        manager::disconnect_device(&backend, address).await
+                    })
                 } else {
-                    Color::Red
+                    self.start_operation(address, device.display_name().to_string(), OperationKind::Connecting, async move {
+                        manager::connect_device(&backend, address).await
+                    })
+                };
+                // a device disconnected on purpose is not reconnected behind the user's back
+                if started && let Some(entry) = self.adapter.devices_list.lock().unwrap().iter_mut().find(|d| d.address == address) {
+                    entry.user_disconnected = disconnect;
                 }
-            )))
-        .highlight_style(Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD))
-        .highlight_symbol(">> ");
-
-    let layout = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(vec![Constraint::Percentage(5), Constraint::Percentage(95)])
-        .split(frame.area());
-
-    frame.render_widget(
-        Paragraph::new("(O)n/off | (S)can | (C)onnect | (P)air | (T)rust | (F)orget | (Q)uit")
-        .alignment(Alignment::Center)
-        .block(Block::new().borders(Borders::NONE).title("Commands").title_alignment(Alignment::Center)),
-        layout[0],
-    );
-    
-    frame.render_stateful_widget(list, layout[1], &mut list_state);
-}
-async fn paired_to_render (devices_list: &mut Vec<manager::DeviceInfo>, paired: &Vec<bluer::Address>, session: &Session) -> bluer::Result<()>
-{
-    let icons = manager::build_icon_map();
-    let adapter: Adapter = manager::get_adapter(&session).await.expect("Unable to get any adapter");
-    for address in paired
-    {
-
-        let device = adapter.device(*address)?;
-        let device_icon: String = match device.icon().await {
-            Ok(Some(icon)) => icon.to_string().to_lowercase(),
-            Ok(None) => "unknown".to_string(),
-            Err(_) => "unknown".to_string()
-                            
-        }; 
-        let new_device: manager::DeviceInfo = manager::DeviceInfo  
-        {
-            address: address.to_string(),
-            device_name: device.name().await?.unwrap_or("Unknown".to_string()),
-            device_type: icons
-                .iter()
-                .find(|(key, _)| device_icon.contains(*key))
-                .map(|(_, icon)| *icon)
-                .unwrap_or("")
-                .to_string(),            
-            trusted: if device.is_trusted().await? {
-                "T".to_string()
-            } else {
-                " ".to_string()
-                },
-            paired: if device.is_connected().await?{
-                "".to_string()
-            } else if device.is_paired().await? {
-                "".to_string()
-            } else {
-                "".to_string()
-            },
-            battery: if device.is_connected().await? {
-                let percentage: u8 = match device.battery_percentage().await{
-                    Ok(Some(value)) => value,
-                    Ok(None) => 0,
-                    Err(_) => 0
+            }
+            Action::Pair if adapter_status =>
+            {
+                let Some(device) = self.app_state.selected() else {
+                    return Ok(true);
                 };
-                let bat_icon: String = if percentage>75 {
-                    "󰁹"
-                } else if percentage>50 {
-                    "󰂀"
-                } else if percentage>25 {
-                    "󰁾"
-                } else if percentage>1 {
-                    "󰁻"
-                } else { " " }.to_string();
-                format!("{:?}% {}", percentage, bat_icon)
-            } else {
-                " ".to_string()
+                let backend = backend.clone();
+                // a rejected or cancelled pairing request ends up in the status bar
+                self.start_operation(device.address, device.display_name().to_string(), OperationKind::Pairing, async move {
+                    manager::pair_device(&backend, device.address).await
+                });
             }
-        };
+            Action::Trust if adapter_status =>
+            {
+                let Some(device) = self.app_state.selected() else {
+                    return Ok(true);
+                };
+                let backend = backend.clone();
+                let kind = if device.trusted { OperationKind::Untrusting } else { OperationKind::Trusting };
+                self.start_operation(device.address, device.display_name().to_string(), kind, async move {
+                    manager::set_trust(&backend, device.address, !device.trusted).await
+                });
+            }
+            Action::Block if adapter_status =>
+            {
+                let Some(device) = self.app_state.selected() else {
+                    return Ok(true);
+                };
+                let backend = backend.clone();
+                let kind = if device.blocked { OperationKind::Unblocking } else { OperationKind::Blocking };
+                self.start_operation(device.address, device.display_name().to_string(), kind, async move {
+                    manager::set_blocked(&backend, device.address, !device.blocked).await
+                });
+            }
+            Action::Cancel =>
+            {
+                let Some(device) = self.app_state.selected() else {
+                    return Ok(true);
+                };
+                let address = device.address;
+                if let Some(kind) = self.operations.cancel(address) {
+                    let backend = backend.clone();
+                    let status = self.status.clone();
+                    tokio::spawn(async move {
+                        status.report("Cancel", manager::cancel_operation(&backend, address, kind).await);
+                    });
+                    self.status.info(format!("{} cancelled", kind.describe(device.display_name())));
+                }
+            }
+            Action::Reconnect =>
+            {
+                // only paired devices are in the store
+                if let Some(address) = self.app_state.selected
+                    && let Some(stored) = self.adapter.store.get(address)
+                {
+                    self.adapter.store.update(address, |d| d.auto_reconnect = !stored.auto_reconnect);
+                    self.status.info(format!("Auto-reconnect {}", if stored.auto_reconnect { "off" } else { "on" }));
+                    if !stored.auto_reconnect {
+                        manager::auto_reconnect(backend, address, self.adapter.devices_list.clone(), &self.adapter.store, self.status.log());
+                    }
+                }
+            }
+            Action::Rename if adapter_status =>
+            {
+                self.rename = self.app_state.selected()
+                    .map(|d| RenameInput {
+                        address: d.address,
+                        text: d.name.clone().unwrap_or_default(),
+                        remote_name: d.properties.name.clone(),
+                    });
+            }
+            Action::Forget if adapter_status =>
+            {
+                let Some(device) = self.app_state.selected() else {
+                    return Ok(true);
+                };
+                let backend = backend.clone();
+                let devices_list = self.adapter.devices_list.clone();
+                self.start_operation(device.address, device.display_name().to_string(), OperationKind::Forgetting, async move {
+                    manager::forget_device(&backend, device.address, devices_list).await
+                });
+            }
+            Action::Scan | Action::Connect | Action::Pair | Action::Trust | Action::Block | Action::Rename | Action::Forget | Action::Gatt if !adapter_status =>
+            {
+                self.status.warn(format!("{} is powered off, {} to turn it on", self.adapter.name(), self.config.keys.hint(Action::Power)));
+            }
+            _ =>{}
+        }
+        Ok(true)
+    }
+
+    /*
+     * What the next frame shows, taken in one go
+    */
+    fn screen(&self) -> ui::Screen<'_> {
+        let state = &self.app_state;
+        let devices = state.devices_list.lock().unwrap();
+        let rows: Vec<ui::DeviceRow> = state.visible(&devices)
+            .into_iter()
+            .map(|i| {
+                let device = devices[i].clone();
+                let auto_reconnect = state.store.get(device.address).is_some_and(|stored| stored.auto_reconnect);
+                let running = self.operations.get(device.address);
+                ui::DeviceRow::new(device, auto_reconnect, running)
+            })
+            .collect();
+        let stored = rows.get(state.selected_index).and_then(|row| state.store.get(row.device.address));
+
+        ui::Screen {
+            adapter_name: self.adapter.name(),
+            powered: self.adapter_status,
+            blocked: self.blocked,
+            scanning: self.adapter.scan_handle.is_some(),
+            rows,
+            total: devices.len(),
+            selected: state.selected_index,
+            filter: state.filter.clone(),
+            searching: state.searching,
+            sort: state.store.sort_key(),
+            detail: self.detail,
+            stored,
+            rename: self.rename.as_ref(),
+            picker: self.picker.as_ref(),
+            settings: self.settings.as_ref(),
+            scan_options: self.scan_options.as_ref(),
+            gatt: self.gatt.as_ref(),
+            prompt: self.prompt.as_ref(),
+            log_pane: self.log_pane.as_ref(),
+            log: self.log_pane.as_ref().map(|pane| self.status.log().entries(pane.level)).unwrap_or_default(),
+            status: self.status.current(),
+            now: btui::store::now(),
+        }
+    }
+
+    /*
+     * Runs `work` in the background, a device or adapter busy with something else is left alone.
+     * Returns whether it started.
+    */
+    fn start_operation(&self, target: impl Into<OperationTarget>, name: String, kind: OperationKind, work: impl Future<Output = error::Result<()>> + Send + 'static) -> bool {
+        match self.operations.start(target, kind, name.clone(), &self.status, work) {
+            Ok(()) => true,
+            Err(busy) => {
+                self.status.warn(format!("{} is busy: {}", name, busy.label()));
+                false
+            }
+        }
+    }
+}
 
-        devices_list.push(new_device);
-            
+async fn run<B: Backend>(mut terminal: DefaultTerminal, app: &mut App<B>) -> Result<()> {
+    loop {
+        if PANICKED.load(Ordering::Relaxed) {
+            return Err(eyre!("a background task panicked"));
+        }
+        app.tick();
+        terminal.draw(|frame| {
+            ui::render(frame, &app.screen(), &app.config);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(100))?
+            && let Event::Key(key) = event::read()?
+            && !app.handle_key(key.code).await?
+        {
+            break;
+        }
     }
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btui::backend::{DeviceProperties, GattService};
+    use btui::fake::FakeBackend;
+    use ratatui::{Terminal, backend::TestBackend};
+    use tokio::time::{Duration, Instant, sleep};
 
-async fn refresh_device_list(
-    devices_list: Arc<Mutex<Vec<manager::DeviceInfo>>>,
-    paired_devices: &Vec<bluer::Address>,
-    session: &Session
-) -> Result<()> {
-    let mut list = devices_list.lock().unwrap();
-    list.clear();
-    drop(list);
-    
-    let mut list = devices_list.lock().unwrap();
-    paired_to_render(&mut list, paired_devices, session).await?;
+    const HEADPHONES: &str = "EE:00:00:00:00:01";
+    const SPEAKER: &str = "EE:00:00:00:00:02";
 
-    Ok(())
+    /*
+     * The store of a fake adapter, empty at first and removed when dropped
+    */
+    struct FakeStore(std::path::PathBuf);
+
+    impl FakeStore {
+        fn new(adapter: &str) -> Self {
+            fake_bluez::isolate();
+            let dir = fake_bluez::store_dir(adapter);
+            let _ = std::fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for FakeStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /*
+     * Paired headphones, connected, and a paired speaker on `adapter`
+    */
+    fn paired_backend(adapter: &str) -> FakeBackend {
+        FakeBackend::new(adapter)
+            .with_device(HEADPHONES.parse().unwrap(), DeviceProperties {
+                name: Some("Headphones".to_string()), paired: true, connected: true, ..Default::default()
+            })
+            .with_device(SPEAKER.parse().unwrap(), DeviceProperties {
+                name: Some("Speaker".to_string()), paired: true, ..Default::default()
+            })
+    }
+
+    async fn fake_app(backend: &FakeBackend) -> App<FakeBackend> {
+        let (_, agent_requests) = mpsc::unbounded_channel();
+        App::new(backend.clone(), agent_requests, Config::default()).await.unwrap()
+    }
+
+    async fn press<B: Backend>(app: &mut App<B>, key: char) {
+        assert!(app.handle_key(KeyCode::Char(key)).await.unwrap());
+    }
+
+    /*
+     * Ticks the app until `condition` holds, fails after two seconds
+    */
+    async fn until<B: Backend>(app: &mut App<B>, what: &str, mut condition: impl FnMut(&App<B>) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            app.tick();
+            if condition(app) {
+                return;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn selected_name<B: Backend>(app: &App<B>) -> Option<String> {
+        app.app_state.selected().and_then(|device| device.name)
+    }
+
+    /*
+     * The frame `app` draws next, one string per line
+    */
+    fn frame<B: Backend>(app: &mut App<B>) -> Vec<String> {
+        app.tick();
+        let mut terminal = Terminal::new(TestBackend::new(100, 12)).unwrap();
+        terminal.draw(|frame| ui::render(frame, &app.screen(), &app.config)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect::<String>().trim_end().to_string())
+            .collect()
+    }
+
+    /*
+     * Draws until a line of the frame passes `condition`, fails after two seconds
+    */
+    async fn drawn<B: Backend>(app: &mut App<B>, what: &str, condition: impl Fn(&[String]) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let lines = frame(app);
+            if condition(&lines) {
+                return;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for {}:\n{}", what, lines.join("\n"));
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn shows(lines: &[String], text: &str) -> bool {
+        lines.iter().any(|line| line.contains(text))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_list_follows_bluez() {
+        let bluez = fake_bluez::FakeBluez::start(include_str!("../tests/scenarios/headphones.toml")).await;
+        let (_, agent_requests) = mpsc::unbounded_channel();
+        let mut app = App::new(bluez.backend().await, agent_requests, Config::default()).await.unwrap();
+
+        drawn(&mut app, "the headphones", |lines| shows(lines, "Connected (1)") && shows(lines, "[AA:BB:CC:DD:EE:01] WH-1000XM4 80%")).await;
+        // the watcher is subscribed once a change comes through
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !app.status.log().entries(Level::Debug).iter().any(|entry| entry.text == "WH-1000XM4: Rssi(-40)") {
+            assert!(Instant::now() < deadline, "the device watcher never started");
+            bluez.set_device_property("AA:BB:CC:DD:EE:01", "RSSI", -40i16);
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        bluez.set_device_property("AA:BB:CC:DD:EE:01", "Connected", false);
+        drawn(&mut app, "the headphones to move to the paired ones", |lines| shows(lines, "Paired (1)") && !shows(lines, "Connected (")).await;
+    }
+
+    #[tokio::test]
+    async fn connect_toggles_the_selected_device() {
+        let _store = FakeStore::new("keyconnect");
+        let backend = paired_backend("keyconnect");
+        let mut app = fake_app(&backend).await;
+        until(&mut app, "the headphones to be selected", |app| selected_name(app).as_deref() == Some("Headphones")).await;
+
+        press(&mut app, 'j').await;
+        assert_eq!(selected_name(&app).as_deref(), Some("Speaker"));
+        press(&mut app, 'c').await;
+        let speaker = SPEAKER.parse().unwrap();
+        until(&mut app, "the speaker to connect", |_| backend.device(speaker).is_some_and(|d| d.connected)).await;
+
+        // connected, the speaker moved up next to the headphones and stayed selected
+        until(&mut app, "the speaker to be idle", |app| app.operations.get(speaker).is_none()).await;
+        assert_eq!(selected_name(&app).as_deref(), Some("Speaker"));
+        press(&mut app, 'c').await;
+        until(&mut app, "the speaker to disconnect", |_| backend.device(speaker).is_some_and(|d| !d.connected)).await;
+        assert_eq!(backend.calls().iter().filter(|call| call.ends_with(SPEAKER)).collect::<Vec<_>>(), [
+            &format!("connect {}", SPEAKER), &format!("disconnect {}", SPEAKER),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn pair_pairs_a_discovered_device() {
+        let _store = FakeStore::new("keypair");
+        let phone = "EE:00:00:00:00:03".parse().unwrap();
+        let backend = FakeBackend::new("keypair").with_device_in_range(phone, DeviceProperties {
+            name: Some("Phone".to_string()), rssi: Some(-50), ..Default::default()
+        });
+        let mut app = fake_app(&backend).await;
+
+        press(&mut app, 's').await;
+        until(&mut app, "the phone to be found", |app| selected_name(app).as_deref() == Some("Phone")).await;
+        press(&mut app, 'p').await;
+        until(&mut app, "the phone to pair", |_| backend.device(phone).is_some_and(|d| d.paired)).await;
+        until(&mut app, "the status bar", |app| app.status.current().is_some_and(|message| message.text == "Paired with Phone")).await;
+        assert!(backend.calls().contains(&"pair EE:00:00:00:00:03".to_string()));
+    }
+
+    #[tokio::test]
+    async fn forget_removes_the_selected_device() {
+        let _store = FakeStore::new("keyforget");
+        let backend = paired_backend("keyforget");
+        let mut app = fake_app(&backend).await;
+        until(&mut app, "the headphones to be selected", |app| selected_name(app).as_deref() == Some("Headphones")).await;
+
+        press(&mut app, 'j').await;
+        press(&mut app, 'f').await;
+        let speaker = SPEAKER.parse().unwrap();
+        until(&mut app, "the speaker to leave the list", |app| {
+            !app.adapter.devices_list.lock().unwrap().iter().any(|d| d.address == speaker)
+        }).await;
+        assert!(backend.device(speaker).is_none());
+        assert!(backend.calls().contains(&format!("remove_device {}", SPEAKER)));
+        assert_eq!(selected_name(&app).as_deref(), Some("Headphones"));
+    }
+
+    #[tokio::test]
+    async fn gatt_opens_once_the_services_are_listed() {
+        let _store = FakeStore::new("keygatt");
+        let headphones = HEADPHONES.parse().unwrap();
+        let battery = GattService { id: 1, uuid: btui::backend::parse_uuid("180f").unwrap(), primary: true, characteristics: vec![] };
+        let backend = paired_backend("keygatt").with_gatt(headphones, vec![battery]);
+        let mut app = fake_app(&backend).await;
+        until(&mut app, "the headphones to be selected", |app| selected_name(app).as_deref() == Some("Headphones")).await;
+
+        press(&mut app, 'j').await;
+        press(&mut app, 'g').await;
+        assert!(app.status.current().is_some_and(|message| message.text == "Speaker is not connected, connect it to explore its services"));
+        assert!(!backend.calls().iter().any(|call| call.starts_with("gatt_services")));
+
+        press(&mut app, 'k').await;
+        press(&mut app, 'g').await;
+        until(&mut app, "the GATT view", |app| app.gatt.is_some()).await;
+        assert_eq!(app.gatt.as_ref().unwrap().address, headphones);
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_agent_request_closes_the_prompt() {
+        let _store = FakeStore::new("agent");
+        let backend = paired_backend("agent");
+        let mut app = fake_app(&backend).await;
+        let (requests, agent_requests) = mpsc::unbounded_channel();
+        app.agent_requests = agent_requests;
+        let device = SPEAKER.parse().unwrap();
+        until(&mut app, "the speaker to be listed", |app| app.adapter.devices_list.lock().unwrap().iter().any(|d| d.display_name() == "Speaker")).await;
+
+        let (reply, answer) = tokio::sync::oneshot::channel();
+        requests.send(AgentRequest::Confirmation { device, passkey: 123456, reply }).unwrap();
+        until(&mut app, "the prompt", |app| app.prompt.is_some()).await;
+        assert_eq!(app.prompt.as_ref().unwrap().question(), format!("Does Speaker [{}] show this passkey?\n\n123456", SPEAKER));
+        press(&mut app, 'y').await;
+        assert!(app.prompt.is_none());
+        assert_eq!(answer.await, Ok(true));
+
+        let (reply, answer) = tokio::sync::oneshot::channel();
+        requests.send(AgentRequest::Authorization { device, reply }).unwrap();
+        until(&mut app, "the prompt", |app| app.prompt.is_some()).await;
+        // BlueZ timed the request out
+        drop(answer);
+        until(&mut app, "the prompt to close", |app| app.prompt.is_none()).await;
+    }
 }
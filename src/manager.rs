@@ -1,277 +1,666 @@
-use bluer::{Adapter,Address,AdapterEvent};
-use bluer::{Session};
+use crate::activity::{ActivityLog, Level};
+use crate::backend::{Backend, DeviceProperties};
+use crate::error::Result;
+use crate::operations::OperationKind;
+use crate::store::{self, DeviceStore};
+use bluer::{Address,AdapterEvent,AdapterProperty,DeviceEvent,DeviceProperty};
 use futures::StreamExt;
+use serde::Serialize;
 use std::{
     io,
-    collections::HashMap,
-    fs,
-    fs::{File,ReadDir},
-    path::PathBuf,
-    sync::{Arc,Mutex}
+    collections::{BTreeSet, VecDeque},
+    sync::{Arc,Mutex,atomic::{AtomicBool,Ordering}},
+    time::SystemTime
 };
-use tokio::time::{timeout, Duration};
+use tokio::{sync::Notify, time::{sleep, timeout, Duration, Instant}};
 
+// RSSI samples kept per device for the sparkline
+pub const RSSI_HISTORY: usize = 60;
+
+// wait between two reconnection attempts, doubled after each failure
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(300);
+// a device heard from more recently than this is taken to be in range
+const IN_RANGE: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState
+{
+    /// Seen during a scan only.
+    Discovered,
+    Paired,
+    Connected,
+}
+
+/*
+ * Kind of device, guessed from the icon name BlueZ reports
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconKind
+{
+    Headset,
+    Headphones,
+    Speaker,
+    Mouse,
+    Gamepad,
+    Laptop,
+    Phone,
+    Card,
+    Tv,
+    Unknown,
+}
+
+impl IconKind {
+    pub fn from_icon(icon: Option<&str>) -> Self {
+        let icon = icon.unwrap_or_default().to_lowercase();
+        // more specific names first, "audio-headphones" also contains "phone"
+        [
+            ("input-gaming", IconKind::Gamepad),
+            ("headphone", IconKind::Headphones),
+            ("headset", IconKind::Headset),
+            ("speaker", IconKind::Speaker),
+            ("controller", IconKind::Gamepad),
+            ("laptop", IconKind::Laptop),
+            ("mouse", IconKind::Mouse),
+            ("phone", IconKind::Phone),
+            ("card", IconKind::Card),
+            ("pad", IconKind::Gamepad),
+            ("tv", IconKind::Tv),
+        ]
+        .into_iter()
+        .find(|(name, _)| icon.contains(name))
+        .map_or(IconKind::Unknown, |(_, kind)| kind)
+    }
+}
+
+/*
+ * Progress of manager::auto_reconnect for one device
+*/
 #[derive(Clone)]
+pub struct Reconnect
+{
+    pub attempt: u32,
+    // None while an attempt is running
+    pub retry_at: Option<Instant>,
+    /// Nothing heard from the device lately, attempts wait until it is back in range.
+    pub out_of_range: bool,
+    // cuts the wait short when the device shows up in range
+    wake: Arc<Notify>,
+}
+
+/*
+ * A device in the list, rendering is left to the UI
+*/
+#[derive(Clone, Serialize)]
 pub struct DeviceInfo
 {
-    pub address: String,
-    pub device_name: String,
-    pub device_type: String,
-    pub trusted: String,
-    pub paired: String,
-    pub battery: String,
+    pub address: Address,
+    pub name: Option<String>,
+    pub icon: IconKind,
+    pub state: ConnectionState,
+    pub trusted: bool,
+    /// Refused by BlueZ, left out of auto-reconnect.
+    pub blocked: bool,
+    pub battery: Option<u8>,
+    pub rssi: Option<i16>,
+    // oldest first, filled while scanning
+    #[serde(skip)]
+    pub rssi_history: VecDeque<i16>,
+    // connection changes seen while btui runs
+    #[serde(skip)]
+    pub connected_at: Option<SystemTime>,
+    #[serde(skip)]
+    pub disconnected_at: Option<SystemTime>,
+    // last RSSI, connection or resolved services, whatever says the device is around
+    #[serde(skip)]
+    pub seen_at: Option<Instant>,
+    #[serde(skip)]
+    pub reconnect: Option<Reconnect>,
+    // disconnected from btui, auto-reconnect leaves it alone until it connects again
+    #[serde(skip)]
+    pub user_disconnected: bool,
+    #[serde(skip)]
+    pub properties: DeviceProperties,
 }
 
-pub fn build_icon_map() -> HashMap<&'static str, &'static str> {
-    let mut map = HashMap::new();
+impl DeviceInfo {
+    pub fn from_properties(address: Address, device: DeviceProperties) -> Self {
+        DeviceInfo
+        {
+            address,
+            // BlueZ falls back to the address when a device has neither alias nor name
+            name: device.alias.clone()
+                .filter(|alias| *alias != address.to_string().replace(':', "-"))
+                .or(device.name.clone()),
+            icon: IconKind::from_icon(device.icon.as_deref()),
+            state: if device.connected {
+                ConnectionState::Connected
+            } else if device.paired {
+                ConnectionState::Paired
+            } else {
+                ConnectionState::Discovered
+            },
+            trusted: device.trusted,
+            blocked: device.blocked,
+            battery: device.battery,
+            rssi: device.rssi,
+            rssi_history: device.rssi.into_iter().collect(),
+            connected_at: None,
+            disconnected_at: None,
+            seen_at: (device.connected || device.rssi.is_some()).then(Instant::now),
+            reconnect: None,
+            user_disconnected: false,
+            properties: device,
+        }
+    }
 
-    map.insert("headset", "");
-    map.insert("headphone", "");
-    map.insert("speaker", "󰜟");
-    map.insert("mouse", "");
-    map.insert("input-gaming", "󰊴");
-    map.insert("pad", "󰊴");
-    map.insert("controller", "󰊴");
-    map.insert("laptop", "󰌢");
-    map.insert("phone", "");
-    map.insert("card", "󰢮");
-    map.insert("unknown", "");
-    map.insert("tv", "");
+    pub fn apply(&mut self, property: DeviceProperty) {
+        let mut properties = self.properties.clone();
+        let mut rssi_history = std::mem::take(&mut self.rssi_history);
+        let (mut connected_at, mut disconnected_at) = (self.connected_at, self.disconnected_at);
+        let mut seen_at = self.seen_at;
+        let reconnect = self.reconnect.take();
+        let mut user_disconnected = self.user_disconnected;
+        match property {
+            DeviceProperty::Rssi(rssi) => {
+                rssi_history.push_back(rssi);
+                if rssi_history.len() > RSSI_HISTORY {
+                    rssi_history.pop_front();
+                }
+                seen_at = Some(Instant::now());
+            }
+            DeviceProperty::Connected(true) if !properties.connected => {
+                connected_at = Some(SystemTime::now());
+                user_disconnected = false;
+                seen_at = Some(Instant::now());
+            }
+            // it was there until the link went, out of range or not
+            DeviceProperty::Connected(false) if properties.connected => {
+                disconnected_at = Some(SystemTime::now());
+                seen_at = Some(Instant::now());
+            }
+            DeviceProperty::ServicesResolved(true) => seen_at = Some(Instant::now()),
+            _ => {}
+        }
 
+        properties.apply(property);
+        *self = DeviceInfo::from_properties(self.address, properties);
+        self.rssi_history = rssi_history;
+        self.connected_at = connected_at;
+        self.disconnected_at = disconnected_at;
+        self.seen_at = seen_at;
+        self.reconnect = reconnect;
+        self.user_disconnected = user_disconnected;
+    }
 
-    map
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or("Unknown")
+    }
 }
 
 
-pub async fn initiate (session: &Session, paired_devices: &mut Vec<Address>) -> bluer::Result<PathBuf>
+pub async fn initiate<B: Backend> (backend: &B, paired_devices: &mut Vec<Address>) -> Result<DeviceStore>
 {
-    let adapter: Adapter = get_adapter(session).await.expect("");
-    let dir: PathBuf = create_cache_path(adapter.name());  // check if the directory exist, if not it'll create it
-    let path = fs::read_dir(&dir).unwrap();
-    load_paired_devices(paired_devices, path, &session).await.expect("error while loading already paired devices...");
+    // adapters can be opened while the TUI is drawn, so nothing is printed here
+    let store = DeviceStore::open(backend.adapter_name());
+    load_paired_devices(paired_devices, &store, backend).await?;
 
-    Ok(dir)
+    Ok(store)
 }
 
-pub async fn get_adapter(session: &Session) -> bluer::Result<Adapter>
+/*
+ * Every device BlueZ has paired, with bluetoothctl or another tool as well, is remembered.
+ * The stored devices BlueZ no longer has paired are dropped from the store.
+*/
+pub async fn load_paired_devices<B: Backend>(devices_array: &mut Vec<Address>, store: &DeviceStore, backend: &B) -> Result<()>
 {
-   let adapter = session.default_adapter().await?;
-   Ok(adapter)
+    let mut entries: BTreeSet<Address> = backend.device_addresses().await?.into_iter().collect();
+    entries.extend(store.addresses());
+
+    // ask every device at once, a slow device shouldn't hold the others back
+    let answers = futures::future::join_all(entries.iter().map(|address| backend.device_properties(*address))).await;
+
+    for (address, answer) in entries.into_iter().zip(answers)
+    {
+        match answer {
+            Ok(device) if device.paired => {
+                store.remember(address);
+                devices_array.push(address);
+            }
+            _ => store.forget(address),
+        }
+    }
+    Ok(())
 }
 
+/*
+ * Lists the paired devices right away, their properties are filled in by watch_device
+*/
+pub fn load_device_list<B: Backend>(backend: &B, paired: &[Address], devices_list: Arc<Mutex<Vec<DeviceInfo>>>, store: &DeviceStore, log: &ActivityLog)
+{
+    for address in paired
+    {
+        // what was known last time, until BlueZ answers
+        let stored = store.get(*address).unwrap_or_default();
+        let placeholder = DeviceProperties { paired: true, alias: stored.alias, ..Default::default() };
+        devices_list.lock().unwrap().push(DeviceInfo::from_properties(*address, placeholder));
+        watch_device(backend, *address, devices_list.clone(), store, log);
+    }
+}
 
-pub fn create_cache_path(ad_name: &str) -> PathBuf
+/*
+ * Keeps the entry of `address` in sync with BlueZ until it leaves the list.
+ * Changes are logged, the ones coming with every advertisement at debug level.
+*/
+pub fn watch_device<B: Backend>(backend: &B, address: Address, devices_list: Arc<Mutex<Vec<DeviceInfo>>>, store: &DeviceStore, log: &ActivityLog)
 {
-    let mut cache_path = dirs::cache_dir().expect("Could not find cache directory");
-    cache_path.push(format!("bluetooi/{}",ad_name));
+    let backend = backend.clone();
+    let store = store.clone();
+    let log = log.clone();
+
+    tokio::spawn(async move {
+        // subscribe before reading the properties so no change falls in between
+        let Ok(mut events) = backend.device_events(address).await else {
+            return;
+        };
+
+        if let Ok(device) = backend.device_properties(address).await {
+            if device.paired {
+                store.remember(address);
+            }
+            store.update(address, |stored| {
+                stored.alias = device.alias.clone().or(stored.alias.take());
+                if device.connected {
+                    stored.last_connected = Some(store::now());
+                    stored.last_seen = Some(store::now());
+                    stored.battery = device.battery.or(stored.battery);
+                }
+            });
+            let mut list = devices_list.lock().unwrap();
+            match list.iter_mut().find(|d| d.address == address) {
+                Some(entry) => *entry = DeviceInfo::from_properties(address, device),
+                None => return,
+            }
+        }
 
-    if !cache_path.exists() 
-    {   
-        println!("directory created");
-        let _ = fs::create_dir_all(&cache_path);
-    }
+        while let Some(DeviceEvent::PropertyChanged(property)) = events.next().await {
+            let repeated = devices_list.lock().unwrap().iter().any(|d| d.address == address && d.properties.repeats(&property));
+            if repeated && !store.discovery_filter().duplicate_data {
+                continue;
+            }
+            if let DeviceProperty::Paired(true) = property {
+                store.remember(address);
+            }
+            store.apply(address, &property);
+            let dropped = matches!(property, DeviceProperty::Connected(false));
+            let seen = matches!(property, DeviceProperty::Rssi(_) | DeviceProperty::Connected(true) | DeviceProperty::ServicesResolved(true));
+            let chatty = matches!(property, DeviceProperty::Rssi(_) | DeviceProperty::TxPower(_) | DeviceProperty::ManufacturerData(_) | DeviceProperty::ServiceData(_));
+
+            let mut list = devices_list.lock().unwrap();
+            let Some(entry) = list.iter_mut().find(|d| d.address == address) else {
+                break;
+            };
+            let text = format!("{}: {:?}", entry.display_name(), property);
+            if chatty {
+                log.debug(text);
+            } else {
+                log.info(text);
+            }
+            entry.apply(property);
+            if let (true, Some(reconnect)) = (seen, &entry.reconnect) {
+                reconnect.wake.notify_one();
+            }
+            let reconnect = dropped && !entry.user_disconnected && !entry.blocked && store.get(address).is_some_and(|d| d.auto_reconnect);
+            drop(list);
 
-    return cache_path;
+            if reconnect {
+                auto_reconnect(&backend, address, devices_list.clone(), &store, &log);
+            }
+        }
+    });
 }
 
-pub async fn load_paired_devices(devices_array: &mut Vec<Address>, directory: ReadDir,session: &Session) -> bluer::Result<()>
+/*
+ * Follows the adapter power state and drops the devices BlueZ removed, logging what the adapter reports
+*/
+pub fn watch_adapter<B: Backend>(backend: &B, powered: Arc<AtomicBool>, devices_list: Arc<Mutex<Vec<DeviceInfo>>>, log: &ActivityLog)
 {
-    let adapter: Adapter = get_adapter(session).await?;
-
-    for device_name in directory
-    {
-        if let Ok(entry) = device_name
-        {   
-            if let Some (name) = entry.file_name().to_str()
-            {
-                let address = string_to_address(name.to_string());
-                
-                match adapter.device(address)
-                {
-                    Ok(device) =>{
-                        match device.is_paired().await {
-                            Ok(true) => {devices_array.push(address);}
-                        
+    let backend = backend.clone();
+    let log = log.clone();
+
+    tokio::spawn(async move {
+        let Ok(mut events) = backend.adapter_events().await else {
+            return;
+        };
+        if let Ok(status) = backend.is_powered().await {
+            powered.store(status, Ordering::Relaxed);
+        }
 
-                            _ => if let Err(_) = std::fs::remove_file(entry.path()){}
-                        }
-                    }
-                    Err(_) => {
-                        if let Err(_) = std::fs::remove_file(entry.path()){}
+        let adapter = backend.adapter_name().to_string();
+        while let Some(event) = events.next().await {
+            match event {
+                AdapterEvent::PropertyChanged(property) => {
+                    if let AdapterProperty::Powered(status) = property {
+                        powered.store(status, Ordering::Relaxed);
                     }
-                    
+                    log.info(format!("{}: {:?}", adapter, property));
+                }
+                AdapterEvent::DeviceAdded(address) => log.info(format!("{}: device {} added", adapter, address)),
+                AdapterEvent::DeviceRemoved(address) => {
+                    devices_list.lock().unwrap().retain(|d| d.address != address);
+                    log.info(format!("{}: device {} removed", adapter, address));
                 }
             }
         }
-    }
-    Ok(())
+    });
 }
 
-pub fn _read_input() -> String{
+pub fn read_input() -> String{
 
     let mut input = String::new();
-    io::stdin().read_line(&mut input).expect("error: unable to read user input");  
-    return input.trim().to_string();
+    // a closed stdin reads as an empty answer
+    let _ = io::stdin().read_line(&mut input);
+    input.trim().to_string()
 
 }
 
-pub async fn scan_devices(session: &Session, paired_array: &mut Vec<Address>, cache_path: &PathBuf, devices_list: Arc<Mutex<Vec<DeviceInfo>>>) -> bluer::Result<()> {
-    let adapter: Adapter = get_adapter(session).await?;  
-    let discover = adapter.discover_devices().await?;
+pub async fn scan_devices<B: Backend>(backend: &B, paired_array: &mut [Address], store: &DeviceStore, devices_list: Arc<Mutex<Vec<DeviceInfo>>>, duration: Duration, log: &ActivityLog) -> Result<()> {
+    let filter = store.discovery_filter();
+    let discover = backend.discover_devices(&filter).await?;
     tokio::pin!(discover);
-   // started scanning 
-    let _scan_result = timeout(Duration::from_secs(30), async {
+    // the scan ends when the duration is over, only an error stops it earlier
+    let scan = timeout(duration, async {
         while let Some(event) = discover.next().await {
-            match event {
-                AdapterEvent::DeviceAdded(addr) => {
-                    let device = adapter.device(addr)?;
-                    let name = device.name().await.unwrap_or_default().unwrap_or_default();
-                    
-                    if device.is_paired().await? {
-                        if !paired_array.iter().any(|d| d == &addr) {
-                            let mut file_path = cache_path.clone();
-                            file_path.push(format!("{}.txt", addr));
-                            match File::create_new(&file_path) {
-                                Ok(_file) => {},
-                                Err(_) => {
-                                    continue;
-                                }
-                            }
-                        }
-                    }
-                    else if !addr.is_empty() && !name.is_empty() {
-
-                        let icons = build_icon_map();
-                        let device_icon: String = match device.icon().await {
-                            Ok(Some(icon)) => icon.to_string().to_lowercase(),
-                            Ok(None) => "unknown".to_string(),
-                            Err(_) => "unknown".to_string()
-                            
-                        };        
-                        let new_device_info = DeviceInfo 
-                        {
-                            address: addr.to_string(),
-                            device_name: device.name().await?.unwrap_or("Unknown".to_string()),
-                            device_type: icons
-                                .iter()
-                                .find(|(key, _)| device_icon.contains(*key))
-                                .map(|(_, icon)| *icon)
-                                .unwrap_or("")
-                                .to_string(),            
-                            trusted: if device.is_trusted().await? {
-                                "T".to_string()
-                            } else {
-                                " ".to_string()
-                            },
-                            paired: if device.is_connected().await? {"O".to_string()} else if device.is_paired().await? {"".to_string()} else {" ".to_string()},
-                            battery: if device.is_connected().await? {
-                                format!("{:?}%", device.battery_percentage().await?.unwrap())
-                            } else {" ".to_string()}
-                        };
-                        
-                        {
-                            let mut list = devices_list.lock().unwrap();
-                            list.push(new_device_info);
-                        }                                            
-                    }
+            if let AdapterEvent::DeviceAdded(addr) = event {
+                let device = backend.device_properties(addr).await?;
+                let name = device.name.clone().unwrap_or_default();
+
+                store.seen(addr);
+                if device.paired && !paired_array.iter().any(|d| d == &addr) && !store.remember(addr) {
+                    continue;
+                }
+
+                // BlueZ reports the devices it already knew whatever the filter
+                if (device.paired || (!addr.is_empty() && !name.is_empty() && filter.matches(addr, &device)))
+                    && !devices_list.lock().unwrap().iter().any(|d| d.address == addr)
+                {
+                    devices_list.lock().unwrap().push(DeviceInfo::from_properties(addr, device));
+                    watch_device(backend, addr, devices_list.clone(), store, log);
                 }
-                _ => {} 
             }
         }
-        Ok::<(), bluer::Error>(())
+        Ok(())
     }).await;
-    Ok(())
+    scan.unwrap_or(Ok(()))
 }
 
 /*
- * Power the default_adapter on or off, based on its current state
+ * Power the adapter on or off, based on its current state
 */
-pub async fn power_adapter (session: &Session) -> bluer::Result<()>
+pub async fn power_adapter<B: Backend> (backend: &B) -> Result<()>
 {
-    let adapter: Adapter = get_adapter(session).await?;
-    let switch: bool = adapter.is_powered().await.expect("cannot find any adapter");
-    let _ = adapter.set_powered(!switch).await;
-    //println!("{:?}",switch);
-    Ok(())
+    let powered = backend.is_powered().await?;
+    set_adapter_power(backend, !powered).await
 }
 
-pub async fn pair_device(session: &Session, address: String) -> bluer::Result<()>
+pub async fn set_adapter_power<B: Backend> (backend: &B, powered: bool) -> Result<()>
 {
-    let adapter: Adapter = get_adapter(session).await?;
-
-    let device_address = string_to_address(address);
-    let device = adapter.device(device_address)?;
+    Ok(backend.set_powered(powered).await?)
+}
 
-    device.pair().await?;
+pub async fn pair_device<B: Backend>(backend: &B, address: Address) -> Result<()>
+{
+    backend.pair(address).await?;
 
     Ok(())
 }
 
-pub async fn dis_connect_device(session: &Session, address: String) -> bluer::Result<()>
+/*
+ * Pairs first when the device isn't paired yet
+*/
+pub async fn connect_device<B: Backend>(backend: &B, address: Address) -> Result<()>
 {
-    let adapter: Adapter = get_adapter(session).await?;
-
-    let device_address = string_to_address(address.clone());
-    let device = adapter.device(device_address)?;
-
-    if !device.is_paired().await?
+    if !backend.device_properties(address).await?.paired
     {
-        match pair_device(&session, address).await
-        {
-            Err(_) => {},
-            Ok(_) => {
-                match device.connect().await
-            {
-                Err(_) => {},
-                Ok(_) => {},
-                }
+        pair_device(backend, address).await?;
+    }
+    Ok(backend.connect(address).await?)
+}
 
-            },
-        }
+pub async fn disconnect_device<B: Backend>(backend: &B, address: Address) -> Result<()>
+{
+    Ok(backend.disconnect(address).await?)
+}
 
-    }
-    
-    if !device.is_connected().await?
-    {
-        match device.connect().await
-        {
-            Err(_) => {},
-            Ok(_) => {},
+/*
+ * BlueZ goes on with an operation whose call was dropped, tell it to stop.
+ * Disconnecting ends a connection attempt, which may have started by pairing.
+*/
+pub async fn cancel_operation<B: Backend>(backend: &B, address: Address, kind: OperationKind) -> Result<()>
+{
+    match kind {
+        OperationKind::Pairing => Ok(backend.cancel_pairing(address).await?),
+        OperationKind::Connecting => {
+            let _ = backend.cancel_pairing(address).await;
+            Ok(backend.disconnect(address).await?)
         }
+        _ => Ok(()),
     }
-    else
-    {
-        device.disconnect().await?;
-    }
-    Ok(())
 }
 
-pub async fn forget_device (session: &Session, address: String, devices_list: Arc<Mutex<Vec<DeviceInfo>>>) -> bluer::Result<()>
+/*
+ * Connects `address` again, retrying with a growing delay until it is connected.
+ * Stops once auto-reconnect is turned off, the device leaves the list, the user disconnected it or blocked it.
+ * Attempts are skipped while the adapter is off, and wait while nothing was heard from the device for IN_RANGE:
+ * a scan seeing it or the device connecting by itself starts them over.
+*/
+pub fn auto_reconnect<B: Backend>(backend: &B, address: Address, devices_list: Arc<Mutex<Vec<DeviceInfo>>>, store: &DeviceStore, log: &ActivityLog)
 {
-    let adapter: Adapter = get_adapter(session).await?;
+    let wake = Arc::new(Notify::new());
+    let name = {
+        let mut list = devices_list.lock().unwrap();
+        let Some(entry) = list.iter_mut().find(|d| d.address == address) else {
+            return;
+        };
+        // already being reconnected
+        if entry.reconnect.is_some() {
+            return;
+        }
+        entry.reconnect = Some(Reconnect { attempt: 0, retry_at: None, out_of_range: false, wake: wake.clone() });
+        entry.display_name().to_string()
+    };
+
+    let backend = backend.clone();
+    let store = store.clone();
+    let log = log.clone();
+    let set_status = move |devices_list: &Mutex<Vec<DeviceInfo>>, status: Option<Reconnect>| {
+        if let Some(entry) = devices_list.lock().unwrap().iter_mut().find(|d| d.address == address) {
+            entry.reconnect = status;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut delay = RECONNECT_DELAY;
+        let mut attempt = 0;
+        let mut waiting = false;
+        loop {
+            let (wanted, in_range) = devices_list.lock().unwrap().iter()
+                .find(|d| d.address == address)
+                .map_or((false, false), |d| (
+                    d.state != ConnectionState::Connected && !d.user_disconnected && !d.blocked,
+                    d.seen_at.is_some_and(|seen| seen.elapsed() < IN_RANGE),
+                ));
+            if !wanted || !store.get(address).is_some_and(|d| d.auto_reconnect) {
+                set_status(&devices_list, None);
+                return;
+            }
 
-    let _ = adapter.remove_device(string_to_address(address.clone())).await?;
+            if !in_range {
+                if !waiting {
+                    log.info(format!("{}: out of range, reconnecting once it is seen again", name));
+                    waiting = true;
+                }
+                set_status(&devices_list, Some(Reconnect { attempt, retry_at: None, out_of_range: true, wake: wake.clone() }));
+                // still looks whether it is wanted now and then
+                let _ = timeout(RECONNECT_MAX_DELAY, wake.notified()).await;
+                delay = RECONNECT_DELAY;
+                continue;
+            }
+            waiting = false;
+
+            if backend.is_powered().await.unwrap_or(false) {
+                attempt += 1;
+                set_status(&devices_list, Some(Reconnect { attempt, retry_at: None, out_of_range: false, wake: wake.clone() }));
+                match connect_device(&backend, address).await {
+                    Ok(()) => {
+                        log.info(format!("{}: reconnected", name));
+                        set_status(&devices_list, None);
+                        return;
+                    }
+                    Err(err) => log.push(Level::Warning, format!("{}: reconnection attempt {} failed: {}", name, attempt, err)),
+                }
+            }
 
-    let mut list = devices_list.lock().unwrap();
-    let index = list.iter().position(|x| x.address == address).unwrap();
-    list.remove(index);
+            set_status(&devices_list, Some(Reconnect { attempt, retry_at: Some(Instant::now() + delay), out_of_range: false, wake: wake.clone() }));
+            // a scan seeing the device retries early, but not more often than RECONNECT_DELAY
+            let seen = async {
+                sleep(RECONNECT_DELAY).await;
+                wake.notified().await;
+            };
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = seen => {}
+            }
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        }
+    });
+}
+
+pub async fn forget_device<B: Backend> (backend: &B, address: Address, devices_list: Arc<Mutex<Vec<DeviceInfo>>>) -> Result<()>
+{
+    backend.remove_device(address).await?;
+
+    // the DeviceRemoved event may already have dropped it
+    devices_list.lock().unwrap().retain(|x| x.address != address);
 
     Ok(())
 }
 
 
-pub async fn un_trust_device(session: &Session, address: String) -> bluer::Result<()>
+/*
+ * Sets the name shown for the device, None goes back to the name the device reports
+*/
+pub async fn rename_device<B: Backend>(backend: &B, address: Address, alias: Option<&str>) -> Result<()>
 {
-    let adapter: Adapter = get_adapter(session).await?;
+    Ok(backend.set_alias(address, alias.unwrap_or_default().to_string()).await?)
+}
 
-    let device = adapter.device(string_to_address(address))?;
-    let switch: bool = !device.is_trusted().await.expect("Error occured");
-    let _ = device.set_trusted(switch).await;
-    Ok(())
+pub async fn set_trust<B: Backend>(backend: &B, address: Address, trusted: bool) -> Result<()>
+{
+    Ok(backend.set_trusted(address, trusted).await?)
 }
 
-pub fn string_to_address (string: String) -> Address
+pub async fn set_blocked<B: Backend>(backend: &B, address: Address, blocked: bool) -> Result<()>
 {
-    let new_address: Address = string[0..17].parse().unwrap();
-    return new_address;
+    Ok(backend.set_blocked(address, blocked).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ScanFilter;
+    use crate::fake::FakeBackend;
+
+    const SPEAKER: &str = "EE:00:00:00:00:02";
+
+    /*
+     * A list holding the device as the fake has it, and a store with auto-reconnect on for it
+    */
+    fn reconnecting(backend: &FakeBackend, address: Address) -> (Arc<Mutex<Vec<DeviceInfo>>>, DeviceStore) {
+        let store = DeviceStore::in_memory();
+        store.remember(address);
+        store.update(address, |device| device.auto_reconnect = true);
+        let device = DeviceInfo::from_properties(address, backend.device(address).unwrap());
+        (Arc::new(Mutex::new(vec![device])), store)
+    }
+
+    fn connects(backend: &FakeBackend) -> usize {
+        backend.calls().iter().filter(|call| call.starts_with("connect ")).count()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_backs_off_until_turned_off() {
+        let speaker: Address = SPEAKER.parse().unwrap();
+        let backend = FakeBackend::new("hci0").with_device(speaker, DeviceProperties { paired: true, rssi: Some(-60), ..Default::default() });
+        let (list, store) = reconnecting(&backend, speaker);
+
+        backend.fail_next("connect");
+        auto_reconnect(&backend, speaker, list.clone(), &store, &ActivityLog::default());
+        sleep(Duration::from_millis(1000)).await;
+        assert_eq!(connects(&backend), 1);
+
+        // 2s after the first attempt, then 4s after the second
+        backend.fail_next("connect");
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(connects(&backend), 2);
+        backend.fail_next("connect");
+        sleep(Duration::from_millis(3000)).await;
+        assert_eq!(connects(&backend), 2);
+        sleep(Duration::from_millis(1000)).await;
+        assert_eq!(connects(&backend), 3);
+
+        store.update(speaker, |device| device.auto_reconnect = false);
+        sleep(Duration::from_secs(600)).await;
+        assert_eq!(connects(&backend), 3);
+        assert!(list.lock().unwrap()[0].reconnect.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_waits_for_the_device_to_be_in_range() {
+        let speaker: Address = SPEAKER.parse().unwrap();
+        let backend = FakeBackend::new("hci0").with_device(speaker, DeviceProperties { paired: true, ..Default::default() });
+        let (list, store) = reconnecting(&backend, speaker);
+        let log = ActivityLog::default();
+        watch_device(&backend, speaker, list.clone(), &store, &log);
+        sleep(Duration::from_millis(10)).await;
+
+        auto_reconnect(&backend, speaker, list.clone(), &store, &log);
+        sleep(Duration::from_secs(600)).await;
+        assert_eq!(connects(&backend), 0);
+        assert!(list.lock().unwrap()[0].reconnect.as_ref().is_some_and(|reconnect| reconnect.out_of_range));
+
+        backend.set_property(speaker, DeviceProperty::Rssi(-60));
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(connects(&backend), 1);
+        let device = &list.lock().unwrap()[0];
+        assert_eq!(device.state, ConnectionState::Connected);
+        assert!(device.reconnect.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn repeated_advertisements_are_dropped_when_asked() {
+        let speaker: Address = SPEAKER.parse().unwrap();
+        let backend = FakeBackend::new("hci0").with_device(speaker, DeviceProperties { paired: true, ..Default::default() });
+        let (list, store) = reconnecting(&backend, speaker);
+        let log = ActivityLog::default();
+        watch_device(&backend, speaker, list.clone(), &store, &log);
+        sleep(Duration::from_millis(10)).await;
+        let advertised = |log: &ActivityLog| log.entries(Level::Debug).iter().filter(|entry| entry.text.contains("ManufacturerData")).count();
+
+        for _ in 0..3 {
+            backend.set_property(speaker, DeviceProperty::ManufacturerData([(0x004c, vec![1])].into()));
+        }
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(advertised(&log), 3, "BlueZ's default is to report them all");
+
+        store.set_discovery_filter(ScanFilter { duplicate_data: false, ..Default::default() });
+        for data in [1, 1, 2, 2] {
+            backend.set_property(speaker, DeviceProperty::ManufacturerData([(0x004c, vec![data])].into()));
+        }
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(advertised(&log), 4, "only the new data is");
+        assert_eq!(list.lock().unwrap()[0].properties.manufacturer_data[&0x004c], [2]);
+    }
 }
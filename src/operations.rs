@@ -0,0 +1,273 @@
+use crate::error::Result;
+use crate::status::StatusBar;
+use bluer::Address;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}
+};
+use tokio::{task::JoinHandle, time::Instant};
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperationKind
+{
+    Pairing,
+    Connecting,
+    Disconnecting,
+    Trusting,
+    Untrusting,
+    Blocking,
+    Unblocking,
+    Renaming,
+    Forgetting,
+    // listing the GATT services
+    Exploring,
+    // the adapter's properties
+    Reading,
+}
+
+impl OperationKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            OperationKind::Pairing => "Pairing…",
+            OperationKind::Connecting => "Connecting…",
+            OperationKind::Disconnecting => "Disconnecting…",
+            OperationKind::Trusting => "Trusting…",
+            OperationKind::Untrusting => "Untrusting…",
+            OperationKind::Blocking => "Blocking…",
+            OperationKind::Unblocking => "Unblocking…",
+            OperationKind::Renaming => "Renaming…",
+            OperationKind::Forgetting => "Forgetting…",
+            OperationKind::Exploring => "Exploring…",
+            OperationKind::Reading => "Reading…",
+        }
+    }
+
+    /*
+     * For the status bar, e.g. "Pairing Headphones"
+    */
+    pub fn describe(self, name: &str) -> String {
+        format!("{} {}", self.label().trim_end_matches('…'), name)
+    }
+}
+
+/*
+ * An operation as the list shows it
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct Running
+{
+    pub kind: OperationKind,
+    pub started_at: Instant,
+}
+
+impl Running {
+    /// Frame of the spinner drawn next to the device, turning every 100 ms.
+    pub fn spinner(&self) -> char {
+        SPINNER[(self.started_at.elapsed().as_millis() / 100) as usize % SPINNER.len()]
+    }
+}
+
+/*
+ * What an operation runs on, the adapter's own ones can't be mistaken for a device's
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OperationTarget
+{
+    /// The adapter of that name.
+    Adapter(String),
+    Device(Address),
+}
+
+impl From<Address> for OperationTarget {
+    fn from(address: Address) -> Self {
+        OperationTarget::Device(address)
+    }
+}
+
+struct Task
+{
+    id: u64,
+    running: Running,
+    handle: JoinHandle<()>,
+}
+
+/*
+ * Device and adapter operations running in the background, at most one per target
+ * but as many targets at once as wanted.
+*/
+#[derive(Clone, Default)]
+pub struct Operations
+{
+    tasks: Arc<Mutex<HashMap<OperationTarget, Task>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Operations {
+    /*
+     * Spawns `work` for the target, its failure ends up in the status bar, its start and end in the activity log.
+     * Returns what the target is already busy with instead when there is something.
+    */
+    pub fn start<F>(&self, target: impl Into<OperationTarget>, kind: OperationKind, name: String, status: &StatusBar, work: F) -> std::result::Result<(), OperationKind>
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let target = target.into();
+        // held until the task is registered, so a task ending at once cannot unregister before
+        let mut tasks = self.tasks.lock().unwrap();
+        if let Some(task) = tasks.get(&target) {
+            return Err(task.running.kind);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        status.log().info(kind.describe(&name));
+        let handle = {
+            let tasks = self.tasks.clone();
+            let target = target.clone();
+            let status = status.clone();
+            tokio::spawn(async move {
+                let result = work.await;
+                if status.report(&kind.describe(&name), result).is_some() {
+                    match kind {
+                        OperationKind::Pairing => status.info(format!("Paired with {}", name)),
+                        _ => status.log().info(format!("{}: done", kind.describe(&name))),
+                    }
+                }
+                let mut tasks = tasks.lock().unwrap();
+                if tasks.get(&target).is_some_and(|task| task.id == id) {
+                    tasks.remove(&target);
+                }
+            })
+        };
+        tasks.insert(target, Task { id, running: Running { kind, started_at: Instant::now() }, handle });
+        Ok(())
+    }
+
+    pub fn get(&self, target: impl Into<OperationTarget>) -> Option<Running> {
+        self.tasks.lock().unwrap().get(&target.into()).map(|task| task.running)
+    }
+
+    /*
+     * Stops waiting for the target's operation, returns what it was.
+     * BlueZ may carry on with it, see manager::cancel_operation.
+    */
+    pub fn cancel(&self, target: impl Into<OperationTarget>) -> Option<OperationKind> {
+        let task = self.tasks.lock().unwrap().remove(&target.into())?;
+        task.handle.abort();
+        Some(task.running.kind)
+    }
+
+    pub fn cancel_all(&self) {
+        for (_, task) in self.tasks.lock().unwrap().drain() {
+            task.handle.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{activity::Level, error::Error, status::Severity};
+    use tokio::{sync::oneshot, time::{Duration, sleep}};
+
+    const SPEAKER: &str = "EE:00:00:00:00:02";
+
+    fn speaker() -> Address {
+        SPEAKER.parse().unwrap()
+    }
+
+    /*
+     * Work that ends with what is sent through the returned sender
+    */
+    fn held() -> (oneshot::Sender<Result<()>>, impl Future<Output = Result<()>> + Send + 'static) {
+        let (finish, finished) = oneshot::channel();
+        (finish, async move { finished.await.unwrap_or(Ok(())) })
+    }
+
+    fn logged(status: &StatusBar) -> Vec<String> {
+        status.log().entries(Level::Debug).into_iter().map(|entry| entry.text).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_finished_operation_frees_the_device() {
+        let (operations, status) = (Operations::default(), StatusBar::default());
+        let (finish, work) = held();
+
+        operations.start(speaker(), OperationKind::Connecting, "Speaker".to_string(), &status, work).unwrap();
+        assert_eq!(operations.get(speaker()).map(|running| running.kind), Some(OperationKind::Connecting));
+        finish.send(Ok(())).unwrap();
+        sleep(Duration::from_millis(10)).await;
+
+        assert!(operations.get(speaker()).is_none());
+        assert_eq!(logged(&status), ["Connecting Speaker", "Connecting Speaker: done"]);
+        assert!(status.current().is_none(), "success is only logged");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pairing_tells_when_it_is_done() {
+        let (operations, status) = (Operations::default(), StatusBar::default());
+        operations.start(speaker(), OperationKind::Pairing, "Speaker".to_string(), &status, async { Ok(()) }).unwrap();
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(status.current().map(|message| message.text).as_deref(), Some("Paired with Speaker"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failure_ends_up_in_the_status_bar() {
+        let (operations, status) = (Operations::default(), StatusBar::default());
+        let failure = async { Err(Error::Authentication("Authentication Rejected".to_string())) };
+        operations.start(speaker(), OperationKind::Pairing, "Speaker".to_string(), &status, failure).unwrap();
+        sleep(Duration::from_millis(10)).await;
+
+        let message = status.current().unwrap();
+        assert_eq!(message.text, "Pairing Speaker: Authentication Rejected");
+        assert_eq!(message.severity, Severity::Error);
+        assert!(operations.get(speaker()).is_none());
+
+        let busy = async { Err(Error::Busy("In Progress".to_string())) };
+        operations.start(speaker(), OperationKind::Connecting, "Speaker".to_string(), &status, busy).unwrap();
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(status.current().unwrap().severity, Severity::Warning);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn one_operation_per_target() {
+        let (operations, status) = (Operations::default(), StatusBar::default());
+        let (_finish, work) = held();
+        operations.start(speaker(), OperationKind::Connecting, "Speaker".to_string(), &status, work).unwrap();
+
+        let refused = operations.start(speaker(), OperationKind::Forgetting, "Speaker".to_string(), &status, async { Ok(()) });
+        assert_eq!(refused, Err(OperationKind::Connecting));
+        assert_eq!(logged(&status), ["Connecting Speaker"], "a refused operation isn't logged");
+
+        // nothing a device could share with the adapter
+        let (_finish, work) = held();
+        operations.start(Address::any(), OperationKind::Connecting, "Nobody".to_string(), &status, work).unwrap();
+        let (_finish, work) = held();
+        operations.start(OperationTarget::Adapter("hci0".to_string()), OperationKind::Reading, "hci0".to_string(), &status, work).unwrap();
+        assert_eq!(operations.get(OperationTarget::Adapter("hci0".to_string())).map(|running| running.kind), Some(OperationKind::Reading));
+        assert!(operations.get(OperationTarget::Adapter("hci1".to_string())).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_cancelled_operation_never_reports() {
+        let (operations, status) = (Operations::default(), StatusBar::default());
+        let (finish, work) = held();
+        operations.start(speaker(), OperationKind::Connecting, "Speaker".to_string(), &status, work).unwrap();
+
+        assert_eq!(operations.cancel(speaker()), Some(OperationKind::Connecting));
+        assert_eq!(operations.cancel(speaker()), None);
+        sleep(Duration::from_millis(10)).await;
+        assert!(finish.send(Ok(())).is_err(), "the work was dropped");
+
+        // a new one can start right away and isn't unregistered by the old one
+        let (finish, work) = held();
+        operations.start(speaker(), OperationKind::Disconnecting, "Speaker".to_string(), &status, work).unwrap();
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(operations.get(speaker()).map(|running| running.kind), Some(OperationKind::Disconnecting));
+        finish.send(Ok(())).unwrap();
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(logged(&status), ["Connecting Speaker", "Disconnecting Speaker", "Disconnecting Speaker: done"]);
+    }
+}
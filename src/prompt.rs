@@ -0,0 +1,224 @@
+use btui::agent::AgentRequest;
+use ratatui::crossterm::event::KeyCode;
+
+/*
+ * Modal popup answering an agent request
+*/
+pub struct AgentPrompt
+{
+    pub request: AgentRequest,
+    pub device_name: String,
+    pub input: String,
+}
+
+impl AgentPrompt {
+    pub fn new(request: AgentRequest, device_name: String) -> Self {
+        Self { request, device_name, input: String::new() }
+    }
+
+    pub fn message(&self) -> String {
+        match &self.request {
+            AgentRequest::PinCode { .. } | AgentRequest::Passkey { .. } => format!("{}\n\n{}_", self.question(), self.input),
+            _ => self.question(),
+        }
+    }
+
+    /*
+     * What the user is asked, without the text typed so far
+    */
+    pub fn question(&self) -> String {
+        let device = format!("{} [{}]", self.device_name, self.request.device());
+        match &self.request {
+            AgentRequest::PinCode { .. } => format!("Enter the PIN code for {}", device),
+            AgentRequest::Passkey { .. } => format!("Enter the passkey shown on {}", device),
+            AgentRequest::DisplayPinCode { pincode, .. } => format!("Type this PIN code on {}\n\n{}", device, pincode),
+            AgentRequest::DisplayPasskey { passkey, entered, .. } => format!("Type this passkey on {}\n\n{:06}\n\n{} key(s) entered", device, passkey, entered),
+            AgentRequest::Confirmation { passkey, .. } => format!("Does {} show this passkey?\n\n{:06}", device, passkey),
+            AgentRequest::Authorization { .. } => format!("Allow {} to pair?", device),
+            AgentRequest::AuthorizeService { service, .. } => format!("Allow {} to use service\n{}?", device, service),
+        }
+    }
+
+    pub fn keys(&self) -> &'static str {
+        match &self.request {
+            AgentRequest::PinCode { .. } | AgentRequest::Passkey { .. } => "(Enter) Accept | (Esc) Reject",
+            AgentRequest::DisplayPinCode { .. } | AgentRequest::DisplayPasskey { .. } => "(Esc) Close",
+            _ => "(Y)es | (N)o",
+        }
+    }
+
+    /*
+     * Returns the prompt back while it still waits for an answer
+    */
+    pub fn handle_key(mut self, code: KeyCode) -> Option<Self> {
+        let text_input = matches!(self.request, AgentRequest::PinCode { .. } | AgentRequest::Passkey { .. });
+        let yes_no = matches!(self.request, AgentRequest::Confirmation { .. } | AgentRequest::Authorization { .. } | AgentRequest::AuthorizeService { .. });
+
+        match code {
+            KeyCode::Esc => self.answer(false),
+            KeyCode::Enter if text_input && self.input.is_empty() => return Some(self),
+            KeyCode::Enter if text_input || yes_no => self.answer(true),
+            KeyCode::Enter => self.answer(false),
+            KeyCode::Char('y') | KeyCode::Char('Y') if yes_no => self.answer(true),
+            KeyCode::Char('n') | KeyCode::Char('N') if yes_no => self.answer(false),
+            KeyCode::Backspace if text_input => {
+                self.input.pop();
+                return Some(self);
+            }
+            KeyCode::Char(c) if text_input => {
+                let (max_len, valid) = match self.request {
+                    AgentRequest::Passkey { .. } => (6, c.is_ascii_digit()),
+                    _ => (16, c.is_ascii_alphanumeric()),
+                };
+                if valid && self.input.len() < max_len {
+                    self.input.push(c);
+                }
+                return Some(self);
+            }
+            _ => return Some(self),
+        }
+        None
+    }
+
+    /*
+     * Answers with a line typed on the terminal, used outside the TUI
+    */
+    pub fn answer_line(mut self, line: &str) {
+        let accept = match self.request {
+            AgentRequest::PinCode { .. } | AgentRequest::Passkey { .. } => {
+                self.input = line.to_string();
+                !line.is_empty()
+            }
+            _ => line.eq_ignore_ascii_case("y") || line.eq_ignore_ascii_case("yes"),
+        };
+        self.answer(accept);
+    }
+
+    fn answer(self, accept: bool) {
+        match self.request {
+            AgentRequest::PinCode { reply, .. } => {
+                let _ = reply.send(accept.then_some(self.input));
+            }
+            AgentRequest::Passkey { reply, .. } => {
+                let _ = reply.send(self.input.parse().ok().filter(|_| accept));
+            }
+            AgentRequest::Confirmation { reply, .. }
+            | AgentRequest::Authorization { reply, .. }
+            | AgentRequest::AuthorizeService { reply, .. } => {
+                let _ = reply.send(accept);
+            }
+            // nothing to answer, BlueZ only wanted the code shown
+            AgentRequest::DisplayPinCode { .. } | AgentRequest::DisplayPasskey { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    fn prompt(request: AgentRequest) -> AgentPrompt {
+        AgentPrompt::new(request, "Headphones".to_string())
+    }
+
+    /*
+     * Presses the keys one after the other, None once the prompt closed
+    */
+    fn press(prompt: AgentPrompt, keys: &[KeyCode]) -> Option<AgentPrompt> {
+        keys.iter().try_fold(prompt, |prompt, key| prompt.handle_key(*key))
+    }
+
+    fn chars(text: &str) -> Vec<KeyCode> {
+        text.chars().map(KeyCode::Char).collect()
+    }
+
+    fn confirmation(keys: &[KeyCode]) -> (Option<AgentPrompt>, oneshot::Receiver<bool>) {
+        let (reply, answer) = oneshot::channel();
+        let request = AgentRequest::Confirmation { device: "EE:00:00:00:00:01".parse().unwrap(), passkey: 1234, reply };
+        (press(prompt(request), keys), answer)
+    }
+
+    #[test]
+    fn a_confirmation_is_answered_with_y_or_n() {
+        for (key, accepted) in [('y', true), ('Y', true), ('n', false), ('N', false)] {
+            let (prompt, mut answer) = confirmation(&[KeyCode::Char(key)]);
+            assert!(prompt.is_none());
+            assert_eq!(answer.try_recv(), Ok(accepted), "{}", key);
+        }
+
+        let (prompt, mut answer) = confirmation(&[KeyCode::Char('x'), KeyCode::Char('1')]);
+        assert!(prompt.is_some(), "other keys are ignored");
+        assert!(answer.try_recv().is_err());
+
+        let (_, mut answer) = confirmation(&[KeyCode::Enter]);
+        assert_eq!(answer.try_recv(), Ok(true));
+        let (_, mut answer) = confirmation(&[KeyCode::Esc]);
+        assert_eq!(answer.try_recv(), Ok(false));
+    }
+
+    #[test]
+    fn a_passkey_takes_six_digits() {
+        let (reply, mut answer) = oneshot::channel();
+        let request = AgentRequest::Passkey { device: "EE:00:00:00:00:01".parse().unwrap(), reply };
+        let prompt = press(prompt(request), &chars("12a3456789")).unwrap();
+        assert_eq!(prompt.input, "123456", "letters and a seventh digit are left out");
+
+        let prompt = press(prompt, &[KeyCode::Backspace, KeyCode::Backspace]).unwrap();
+        assert_eq!(prompt.message(), "Enter the passkey shown on Headphones [EE:00:00:00:00:01]\n\n1234_");
+        assert!(press(prompt, &[KeyCode::Char('0'), KeyCode::Enter]).is_none());
+        assert_eq!(answer.try_recv(), Ok(Some(12340)));
+    }
+
+    #[test]
+    fn a_pin_code_waits_for_some_text() {
+        let (reply, mut answer) = oneshot::channel();
+        let request = AgentRequest::PinCode { device: "EE:00:00:00:00:01".parse().unwrap(), reply };
+        let prompt = press(prompt(request), &[KeyCode::Enter]).unwrap();
+        assert!(answer.try_recv().is_err(), "an empty PIN isn't sent");
+
+        assert!(press(prompt, &[chars("0-0 0a").as_slice(), &[KeyCode::Enter]].concat()).is_none());
+        assert_eq!(answer.try_recv(), Ok(Some("000a".to_string())));
+    }
+
+    #[test]
+    fn escape_rejects_what_was_typed() {
+        let (reply, mut answer) = oneshot::channel();
+        let request = AgentRequest::PinCode { device: "EE:00:00:00:00:01".parse().unwrap(), reply };
+        assert!(press(prompt(request), &[chars("1234").as_slice(), &[KeyCode::Esc]].concat()).is_none());
+        assert_eq!(answer.try_recv(), Ok(None));
+
+        let (reply, mut answer) = oneshot::channel();
+        let request = AgentRequest::Passkey { device: "EE:00:00:00:00:01".parse().unwrap(), reply };
+        assert!(press(prompt(request), &[chars("1234").as_slice(), &[KeyCode::Esc]].concat()).is_none());
+        assert_eq!(answer.try_recv(), Ok(None));
+    }
+
+    #[test]
+    fn a_displayed_code_closes_on_escape() {
+        let (_cancel, cancelled) = oneshot::channel();
+        let request = AgentRequest::DisplayPasskey { device: "EE:00:00:00:00:01".parse().unwrap(), passkey: 42, entered: 2, cancel: cancelled };
+        let prompt = press(prompt(request), &chars("y1")).unwrap();
+        assert!(prompt.message().contains("000042\n\n2 key(s) entered"));
+        assert!(press(prompt, &[KeyCode::Esc]).is_none());
+    }
+
+    #[test]
+    fn a_line_answers_outside_the_tui() {
+        let (reply, mut answer) = oneshot::channel();
+        prompt(AgentRequest::Authorization { device: "EE:00:00:00:00:01".parse().unwrap(), reply }).answer_line("Yes");
+        assert_eq!(answer.try_recv(), Ok(true));
+
+        let (reply, mut answer) = oneshot::channel();
+        prompt(AgentRequest::Authorization { device: "EE:00:00:00:00:01".parse().unwrap(), reply }).answer_line("");
+        assert_eq!(answer.try_recv(), Ok(false));
+
+        let (reply, mut answer) = oneshot::channel();
+        prompt(AgentRequest::Passkey { device: "EE:00:00:00:00:01".parse().unwrap(), reply }).answer_line("not digits");
+        assert_eq!(answer.try_recv(), Ok(None));
+
+        let (reply, mut answer) = oneshot::channel();
+        prompt(AgentRequest::PinCode { device: "EE:00:00:00:00:01".parse().unwrap(), reply }).answer_line("0000");
+        assert_eq!(answer.try_recv(), Ok(Some("0000".to_string())));
+    }
+}
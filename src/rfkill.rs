@@ -0,0 +1,257 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf}
+};
+
+// struct rfkill_event from linux/rfkill.h
+const TYPE_BLUETOOTH: u8 = 2;
+const OP_CHANGE: u8 = 2;
+
+/*
+ * One rfkill switch, e.g. /sys/class/rfkill/rfkill3
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Switch
+{
+    pub index: u32,
+    /// The adapter name for the switch BlueZ registers, a driver name for platform switches.
+    pub name: String,
+    /// Turned off in software, can be undone.
+    pub soft: bool,
+    /// Turned off by a hardware switch, a key or the firmware.
+    pub hard: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Block
+{
+    #[default]
+    Unblocked,
+    Soft,
+    Hard,
+}
+
+impl Block {
+    /*
+     * A hard block wins, unblocking the soft ones wouldn't bring the radio back
+    */
+    pub fn of(switches: &[Switch]) -> Self {
+        if switches.iter().any(|switch| switch.hard) {
+            Block::Hard
+        } else if switches.iter().any(|switch| switch.soft) {
+            Block::Soft
+        } else {
+            Block::Unblocked
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Block::Unblocked => "unblocked",
+            Block::Soft => "soft-blocked",
+            Block::Hard => "hard-blocked",
+        }
+    }
+
+    /*
+     * What the user can do about it, in a sentence
+    */
+    pub fn explain(self, adapter: &str) -> String {
+        match self {
+            Block::Unblocked => format!("{} is not blocked by rfkill", adapter),
+            Block::Soft => format!("{} is soft-blocked by rfkill: the radio was turned off in software, `rfkill unblock bluetooth` turns it back on", adapter),
+            Block::Hard => format!("{} is hard-blocked by rfkill: a wireless switch, a function key or the firmware turned the radio off, only that can turn it back on", adapter),
+        }
+    }
+}
+
+/*
+ * The kernel's radio kill switches, read from sysfs and changed through /dev/rfkill
+*/
+#[derive(Clone, Debug)]
+pub struct Rfkill
+{
+    sysfs: PathBuf,
+    // sysfs is written to instead when missing or refused
+    device: Option<PathBuf>,
+}
+
+impl Default for Rfkill {
+    fn default() -> Self {
+        Self { sysfs: PathBuf::from("/sys/class/rfkill"), device: Some(PathBuf::from("/dev/rfkill")) }
+    }
+}
+
+impl Rfkill {
+    /*
+     * Reads and writes the switches below `sysfs` only, a stand-in for /sys/class/rfkill
+    */
+    pub fn at(sysfs: &Path) -> Self {
+        Self { sysfs: sysfs.to_path_buf(), device: None }
+    }
+
+    /*
+     * The Bluetooth switches holding `adapter` back: its own and the platform ones, which hold back every adapter.
+     * No rfkill support at all means no switches.
+    */
+    pub fn switches(&self, adapter: &str) -> io::Result<Vec<Switch>> {
+        let entries = match fs::read_dir(&self.sysfs) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+
+        let mut switches = vec![];
+        for entry in entries {
+            let dir = entry?.path();
+            let Some(index) = dir.file_name().and_then(|name| name.to_str()?.strip_prefix("rfkill")?.parse().ok()) else {
+                continue;
+            };
+            if read(&dir, "type")? != "bluetooth" {
+                continue;
+            }
+            let name = read(&dir, "name")?;
+            // the switches of the other adapters
+            if name.starts_with("hci") && name != adapter {
+                continue;
+            }
+            switches.push(Switch { index, name, soft: read(&dir, "soft")? == "1", hard: read(&dir, "hard")? == "1" });
+        }
+        switches.sort_by_key(|switch| switch.index);
+        Ok(switches)
+    }
+
+    /*
+     * Unreadable switches count as unblocked, BlueZ still reports what it can't do
+    */
+    pub fn block(&self, adapter: &str) -> Block {
+        self.switches(adapter).map_or(Block::Unblocked, |switches| Block::of(&switches))
+    }
+
+    /*
+     * Lifts the soft blocks on `adapter`, hard blocks stay
+    */
+    pub fn unblock(&self, adapter: &str) -> io::Result<()> {
+        for switch in self.switches(adapter)?.into_iter().filter(|switch| switch.soft) {
+            let changed = match &self.device {
+                Some(device) => change(device, switch.index),
+                None => Err(io::ErrorKind::Unsupported.into()),
+            };
+            // /dev/rfkill is usually open to the logged in user, sysfs only to root
+            if changed.is_err() {
+                fs::write(self.sysfs.join(format!("rfkill{}", switch.index)).join("soft"), "0")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read(dir: &Path, attribute: &str) -> io::Result<String>
+{
+    Ok(fs::read_to_string(dir.join(attribute))?.trim().to_string())
+}
+
+/*
+ * Clears the soft block of switch `index` with an RFKILL_OP_CHANGE event
+*/
+fn change(device: &Path, index: u32) -> io::Result<()>
+{
+    let mut event = [0; 8];
+    event[..4].copy_from_slice(&index.to_ne_bytes());
+    event[4] = TYPE_BLUETOOTH;
+    event[5] = OP_CHANGE;
+    // soft and hard stay 0, hard is ignored by the kernel
+    fs::OpenOptions::new().write(true).open(device)?.write_all(&event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * A /sys/class/rfkill of our own, removed when dropped
+    */
+    struct FakeSysfs
+    {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new(test: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("btui-rfkill-{}-{}", std::process::id(), test));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            Self { root }
+        }
+
+        fn switch(self, index: u32, kind: &str, name: &str, soft: bool, hard: bool) -> Self {
+            let dir = self.root.join(format!("rfkill{}", index));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("type"), format!("{}\n", kind)).unwrap();
+            fs::write(dir.join("name"), format!("{}\n", name)).unwrap();
+            fs::write(dir.join("soft"), format!("{}\n", u8::from(soft))).unwrap();
+            fs::write(dir.join("hard"), format!("{}\n", u8::from(hard))).unwrap();
+            self
+        }
+
+        fn soft(&self, index: u32) -> String {
+            fs::read_to_string(self.root.join(format!("rfkill{}/soft", index))).unwrap().trim().to_string()
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn reads_the_switches_of_the_adapter() {
+        let sysfs = FakeSysfs::new("reads")
+            .switch(0, "wlan", "phy0", true, false)
+            .switch(1, "bluetooth", "tpacpi_bluetooth_sw", false, false)
+            .switch(3, "bluetooth", "hci0", true, false)
+            .switch(4, "bluetooth", "hci1", false, true);
+        let rfkill = Rfkill::at(&sysfs.root);
+
+        assert_eq!(rfkill.switches("hci0").unwrap(), vec![
+            Switch { index: 1, name: "tpacpi_bluetooth_sw".to_string(), soft: false, hard: false },
+            Switch { index: 3, name: "hci0".to_string(), soft: true, hard: false },
+        ]);
+        assert_eq!(rfkill.block("hci0"), Block::Soft);
+        assert_eq!(rfkill.block("hci1"), Block::Hard);
+    }
+
+    #[test]
+    fn a_platform_switch_blocks_every_adapter() {
+        let sysfs = FakeSysfs::new("platform")
+            .switch(0, "bluetooth", "dell-bluetooth", false, true)
+            .switch(1, "bluetooth", "hci0", true, false);
+        let rfkill = Rfkill::at(&sysfs.root);
+
+        assert_eq!(rfkill.block("hci0"), Block::Hard);
+        assert_eq!(rfkill.block("hci1"), Block::Hard);
+    }
+
+    #[test]
+    fn no_rfkill_means_unblocked() {
+        let rfkill = Rfkill::at(&std::env::temp_dir().join("btui-rfkill-missing"));
+        assert_eq!(rfkill.switches("hci0").unwrap(), vec![]);
+        assert_eq!(rfkill.block("hci0"), Block::Unblocked);
+    }
+
+    #[test]
+    fn unblock_lifts_soft_blocks_only() {
+        let sysfs = FakeSysfs::new("unblock")
+            .switch(0, "bluetooth", "hci0", true, false)
+            .switch(1, "bluetooth", "hci1", true, false)
+            .switch(2, "bluetooth", "btusb_sw", false, true);
+        let rfkill = Rfkill::at(&sysfs.root);
+
+        rfkill.unblock("hci0").unwrap();
+        assert_eq!(sysfs.soft(0), "0");
+        assert_eq!(sysfs.soft(1), "1", "another adapter's switch is left alone");
+        assert_eq!(rfkill.block("hci0"), Block::Hard);
+    }
+}
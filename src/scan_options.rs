@@ -0,0 +1,273 @@
+use btui::backend::{self, ScanFilter, Transport};
+use crate::config::Action;
+use btui::store::DeviceStore;
+use ratatui::crossterm::event::KeyCode;
+
+/*
+ * The parts of the discovery filter, one line each in the dialog
+*/
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ScanOption
+{
+    Transport,
+    MinRssi,
+    MaxPathloss,
+    Uuids,
+    Pattern,
+    DuplicateData,
+}
+
+impl ScanOption {
+    pub const ALL: [ScanOption; 6] = [
+        ScanOption::Transport, ScanOption::MinRssi, ScanOption::MaxPathloss,
+        ScanOption::Uuids, ScanOption::Pattern, ScanOption::DuplicateData,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ScanOption::Transport => "Transport",
+            ScanOption::MinRssi => "Minimum RSSI",
+            ScanOption::MaxPathloss => "Maximum path loss",
+            ScanOption::Uuids => "Service UUIDs",
+            ScanOption::Pattern => "Name/address prefix",
+            ScanOption::DuplicateData => "Duplicate data",
+        }
+    }
+
+    pub fn value(self, filter: &ScanFilter) -> String {
+        let or_any = |value: Option<String>| value.unwrap_or("any".to_string());
+        match self {
+            ScanOption::Transport => match filter.transport {
+                Transport::Auto => "auto",
+                Transport::Le => "LE only",
+                Transport::BrEdr => "BR/EDR only",
+            }.to_string(),
+            ScanOption::MinRssi => or_any(filter.min_rssi.map(|rssi| format!("{} dBm", rssi))),
+            ScanOption::MaxPathloss => or_any(filter.max_pathloss.map(|pathloss| format!("{} dB", pathloss))),
+            ScanOption::Uuids if filter.uuids.is_empty() => "any".to_string(),
+            ScanOption::Uuids => filter.uuids.iter().map(|uuid| uuid.to_string()).collect::<Vec<_>>().join(", "),
+            ScanOption::Pattern => or_any(filter.pattern.clone()),
+            ScanOption::DuplicateData => if filter.duplicate_data { "reported" } else { "dropped" }.to_string(),
+        }
+    }
+
+    /*
+     * The text the input starts with
+    */
+    fn text(self, filter: &ScanFilter) -> String {
+        match self {
+            ScanOption::MinRssi => filter.min_rssi.map(|rssi| rssi.to_string()).unwrap_or_default(),
+            ScanOption::MaxPathloss => filter.max_pathloss.map(|pathloss| pathloss.to_string()).unwrap_or_default(),
+            ScanOption::Uuids => filter.uuids.iter().map(|uuid| uuid.to_string()).collect::<Vec<_>>().join(" "),
+            ScanOption::Pattern => filter.pattern.clone().unwrap_or_default(),
+            ScanOption::Transport | ScanOption::DuplicateData => String::new(),
+        }
+    }
+}
+
+pub struct ScanOptionInput
+{
+    pub option: ScanOption,
+    pub text: String,
+}
+
+/*
+ * Dialog editing the filter of the next scans, every change is saved in the adapter's store
+*/
+pub struct ScanOptions
+{
+    pub filter: ScanFilter,
+    pub selected_index: usize,
+    pub input: Option<ScanOptionInput>,
+    pub error: Option<String>,
+    store: DeviceStore,
+}
+
+impl ScanOptions {
+    pub fn new(store: &DeviceStore) -> Self {
+        Self {
+            filter: store.discovery_filter(),
+            selected_index: 0,
+            input: None,
+            error: None,
+            store: store.clone(),
+        }
+    }
+
+    pub fn keys(&self) -> &'static str {
+        match self.input.as_ref().map(|input| input.option) {
+            Some(ScanOption::Uuids) => "(Enter) Save, separated by spaces, e.g. 180d | (Esc) Cancel",
+            Some(_) => "(Enter) Save, empty for any | (Esc) Cancel",
+            None => "(Enter) Change | (Del) Reset all | (Esc) Back",
+        }
+    }
+
+    /*
+     * Returns false once the dialog is closed
+    */
+    pub fn handle_key(&mut self, code: KeyCode, action: Option<Action>) -> bool {
+        if let Some(input) = &mut self.input {
+            match code {
+                KeyCode::Esc => self.input = None,
+                KeyCode::Backspace => {
+                    input.text.pop();
+                }
+                KeyCode::Enter => {
+                    let input = self.input.take().unwrap();
+                    self.submit(input);
+                }
+                KeyCode::Char(c) => input.text.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        match (code, action) {
+            (KeyCode::Enter, _) | (KeyCode::Char(' '), _) => {
+                let option = ScanOption::ALL[self.selected_index];
+                let mut filter = self.filter.clone();
+                match option {
+                    ScanOption::Transport => {
+                        filter.transport = match filter.transport {
+                            Transport::Auto => Transport::Le,
+                            Transport::Le => Transport::BrEdr,
+                            Transport::BrEdr => Transport::Auto,
+                        };
+                    }
+                    ScanOption::DuplicateData => filter.duplicate_data = !filter.duplicate_data,
+                    _ => {
+                        self.input = Some(ScanOptionInput { option, text: option.text(&self.filter) });
+                        return true;
+                    }
+                }
+                self.save(filter);
+            }
+            (KeyCode::Delete, _) => self.save(ScanFilter::default()),
+            (_, Some(Action::Up)) => {
+                self.selected_index = (self.selected_index + ScanOption::ALL.len() - 1) % ScanOption::ALL.len();
+            }
+            (_, Some(Action::Down)) => {
+                self.selected_index = (self.selected_index + 1) % ScanOption::ALL.len();
+            }
+            (KeyCode::Esc, _) | (_, Some(Action::Quit)) => return false,
+            _ => {}
+        }
+        true
+    }
+
+    fn submit(&mut self, input: ScanOptionInput) {
+        let text = input.text.trim();
+        let mut filter = self.filter.clone();
+        // BlueZ takes either an RSSI or a path loss threshold, setting one drops the other
+        let parsed = match input.option {
+            ScanOption::MinRssi => text_or_none(text, |text| text.parse().ok()).map(|rssi| {
+                filter.min_rssi = rssi;
+                filter.max_pathloss = filter.max_pathloss.filter(|_| rssi.is_none());
+            }),
+            ScanOption::MaxPathloss => text_or_none(text, |text| text.parse().ok()).map(|pathloss| {
+                filter.max_pathloss = pathloss;
+                filter.min_rssi = filter.min_rssi.filter(|_| pathloss.is_none());
+            }),
+            ScanOption::Uuids => text.split([' ', ',']).filter(|uuid| !uuid.is_empty())
+                .map(backend::parse_uuid)
+                .collect::<Option<_>>()
+                .map(|uuids| filter.uuids = uuids),
+            ScanOption::Pattern => {
+                filter.pattern = Some(text.to_string()).filter(|text| !text.is_empty());
+                Some(())
+            }
+            ScanOption::Transport | ScanOption::DuplicateData => Some(()),
+        };
+
+        match parsed {
+            Some(()) => self.save(filter),
+            None => self.error = Some(format!("{}: \"{}\" is not valid", input.option.label(), text)),
+        }
+    }
+
+    fn save(&mut self, filter: ScanFilter) {
+        self.error = None;
+        self.store.set_discovery_filter(filter.clone());
+        self.filter = filter;
+    }
+}
+
+/*
+ * Empty text means no threshold, Some(None)
+*/
+fn text_or_none<T>(text: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<Option<T>>
+{
+    if text.is_empty() {
+        Some(None)
+    } else {
+        parse(text).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btui::backend::parse_uuid;
+
+    /*
+     * Opens the input of `option`, types `text` over what is there and presses Enter
+    */
+    fn enter(options: &mut ScanOptions, option: ScanOption, text: &str) {
+        options.selected_index = ScanOption::ALL.iter().position(|o| *o == option).unwrap();
+        assert!(options.handle_key(KeyCode::Enter, None));
+        options.input.as_mut().unwrap().text = text.to_string();
+        assert!(options.handle_key(KeyCode::Enter, None));
+    }
+
+    #[test]
+    fn a_threshold_replaces_the_other() {
+        let store = DeviceStore::in_memory();
+        let mut options = ScanOptions::new(&store);
+
+        enter(&mut options, ScanOption::MaxPathloss, "60");
+        assert_eq!(options.filter.max_pathloss, Some(60));
+        enter(&mut options, ScanOption::MinRssi, "-70");
+        assert_eq!((options.filter.min_rssi, options.filter.max_pathloss), (Some(-70), None));
+        enter(&mut options, ScanOption::MaxPathloss, "40");
+        assert_eq!((options.filter.min_rssi, options.filter.max_pathloss), (None, Some(40)));
+        enter(&mut options, ScanOption::MinRssi, "");
+        assert_eq!((options.filter.min_rssi, options.filter.max_pathloss), (None, Some(40)), "clearing one leaves the other");
+        assert_eq!(store.discovery_filter(), options.filter);
+    }
+
+    #[test]
+    fn invalid_values_are_refused_and_change_nothing() {
+        let store = DeviceStore::in_memory();
+        let mut options = ScanOptions::new(&store);
+
+        enter(&mut options, ScanOption::Uuids, "180d, 180f");
+        assert_eq!(options.filter.uuids, [parse_uuid("180d").unwrap(), parse_uuid("180f").unwrap()].into());
+        enter(&mut options, ScanOption::Uuids, "180d heart");
+        assert_eq!(options.error.as_deref(), Some("Service UUIDs: \"180d heart\" is not valid"));
+        assert_eq!(options.filter.uuids.len(), 2);
+        enter(&mut options, ScanOption::MinRssi, "loud");
+        assert!(options.error.is_some());
+        assert_eq!(options.filter.min_rssi, None);
+
+        enter(&mut options, ScanOption::Pattern, " Polar ");
+        assert_eq!(options.error, None);
+        assert_eq!(options.filter.pattern.as_deref(), Some("Polar"));
+    }
+
+    #[test]
+    fn toggles_and_reset() {
+        let store = DeviceStore::in_memory();
+        let mut options = ScanOptions::new(&store);
+
+        options.selected_index = ScanOption::ALL.iter().position(|o| *o == ScanOption::DuplicateData).unwrap();
+        options.handle_key(KeyCode::Char(' '), None);
+        assert!(!store.discovery_filter().duplicate_data);
+        options.selected_index = 0;
+        options.handle_key(KeyCode::Enter, None);
+        assert_eq!(options.filter.transport, Transport::Le);
+
+        options.handle_key(KeyCode::Delete, None);
+        assert!(store.discovery_filter().is_default());
+        assert!(!options.handle_key(KeyCode::Esc, None));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::manager::{ConnectionState, DeviceInfo};
+
+/*
+ * What the device list is narrowed to: the query typed after `/` and the quick toggles
+*/
+#[derive(Clone, Default)]
+pub struct ListFilter
+{
+    pub query: String,
+    pub connected_only: bool,
+    pub paired_only: bool,
+    pub trusted_only: bool,
+    pub blocked_only: bool,
+    pub hide_unnamed: bool,
+}
+
+impl ListFilter {
+    pub fn is_active(&self) -> bool {
+        !self.query.is_empty() || self.connected_only || self.paired_only || self.trusted_only || self.blocked_only || self.hide_unnamed
+    }
+
+    /*
+     * The query is looked for in the alias, the remote name and the address
+    */
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        if (self.connected_only && device.state != ConnectionState::Connected)
+            || (self.paired_only && device.state == ConnectionState::Discovered)
+            || (self.trusted_only && !device.trusted)
+            || (self.blocked_only && !device.blocked)
+            || (self.hide_unnamed && device.name.is_none())
+        {
+            return false;
+        }
+        self.query.is_empty() || [device.name.as_deref(), device.properties.name.as_deref(), Some(&device.address.to_string())]
+            .into_iter()
+            .flatten()
+            .any(|text| fuzzy_match(&self.query, text).is_some())
+    }
+
+    /*
+     * Short description for the list title, e.g. "connected, trusted, /head"
+    */
+    pub fn describe(&self) -> String {
+        let toggles = [
+            (self.connected_only, "connected"),
+            (self.paired_only, "paired"),
+            (self.trusted_only, "trusted"),
+            (self.blocked_only, "blocked"),
+            (self.hide_unnamed, "named"),
+        ];
+        let mut parts: Vec<String> = toggles.iter().filter(|(on, _)| *on).map(|(_, name)| name.to_string()).collect();
+        if !self.query.is_empty() {
+            parts.push(format!("/{}", self.query));
+        }
+        parts.join(", ")
+    }
+}
+
+/*
+ * Positions of the characters of `text` matching `query`, ignoring case.
+ * A contiguous match is preferred, otherwise the query characters only have to come in order.
+*/
+pub fn fuzzy_match(query: &str, text: &str) -> Option<Vec<usize>>
+{
+    let query: Vec<char> = query.chars().map(lowercase).collect();
+    let text: Vec<char> = text.chars().map(lowercase).collect();
+    if query.is_empty() {
+        return Some(vec![]);
+    }
+
+    if let Some(start) = text.windows(query.len()).position(|window| window == query.as_slice()) {
+        return Some((start..start + query.len()).collect());
+    }
+
+    let mut positions = Vec::with_capacity(query.len());
+    let mut wanted = query.iter().peekable();
+    for (i, c) in text.iter().enumerate() {
+        if wanted.peek() == Some(&c) {
+            positions.push(i);
+            wanted.next();
+        }
+    }
+    wanted.peek().is_none().then_some(positions)
+}
+
+// one character for one, the positions are those of `text`; İ lowercases to i and a combining dot
+fn lowercase(c: char) -> char
+{
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_contiguous_match_wins_over_an_earlier_scattered_one() {
+        assert_eq!(fuzzy_match("bud", "Big Speaker Buds"), Some(vec![12, 13, 14]));
+        assert_eq!(fuzzy_match("bgs", "Big Speaker Buds"), Some(vec![0, 2, 4]));
+        assert_eq!(fuzzy_match("sb", "Buds"), None, "the characters have to come in order");
+    }
+
+    #[test]
+    fn case_is_ignored_on_both_sides() {
+        assert_eq!(fuzzy_match("WH-1000", "wh-1000xm4"), Some(vec![0, 1, 2, 3, 4, 5, 6]));
+        assert_eq!(fuzzy_match("ee:00", "EE:00:00:00:00:01"), Some(vec![0, 1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn an_empty_query_matches_anything() {
+        assert_eq!(fuzzy_match("", "Headphones"), Some(vec![]));
+        assert_eq!(fuzzy_match("", ""), Some(vec![]));
+        assert_eq!(fuzzy_match("a", ""), None);
+    }
+
+    #[test]
+    fn positions_count_characters_not_bytes() {
+        assert_eq!(fuzzy_match("HÖR", "Kopfhörer"), Some(vec![4, 5, 6]));
+        assert_eq!(fuzzy_match("İp", "İphone"), Some(vec![0, 1]));
+        assert_eq!(fuzzy_match("ip", "İphone"), Some(vec![0, 1]));
+        assert_eq!(fuzzy_match("ñ", "Señal"), Some(vec![2]));
+    }
+}
@@ -0,0 +1,328 @@
+use btui::backend::{AdapterProperties, Backend};
+use crate::config::Action;
+use bluer::{AdapterEvent, AdapterProperty};
+use futures::StreamExt;
+use ratatui::crossterm::event::KeyCode;
+use std::sync::{Arc, Mutex};
+
+/*
+ * The adapter properties that can be changed from the panel
+*/
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Setting
+{
+    Alias,
+    Discoverable,
+    DiscoverableTimeout,
+    Pairable,
+    PairableTimeout,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 5] = [
+        Setting::Alias, Setting::Discoverable, Setting::DiscoverableTimeout, Setting::Pairable, Setting::PairableTimeout,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Setting::Alias => "Alias",
+            Setting::Discoverable => "Discoverable",
+            Setting::DiscoverableTimeout => "Discoverable timeout",
+            Setting::Pairable => "Pairable",
+            Setting::PairableTimeout => "Pairable timeout",
+        }
+    }
+
+    pub fn value(self, properties: &AdapterProperties) -> String {
+        let yes_no = |value: bool| if value { "yes" } else { "no" }.to_string();
+        // BlueZ takes 0 as "no timeout"
+        let timeout = |seconds: u32| if seconds == 0 { "never".to_string() } else { format!("{}s", seconds) };
+        match self {
+            Setting::Alias => properties.alias.clone().unwrap_or_default(),
+            Setting::Discoverable => yes_no(properties.discoverable),
+            Setting::DiscoverableTimeout => timeout(properties.discoverable_timeout),
+            Setting::Pairable => yes_no(properties.pairable),
+            Setting::PairableTimeout => timeout(properties.pairable_timeout),
+        }
+    }
+
+    fn is_toggle(self) -> bool {
+        matches!(self, Setting::Discoverable | Setting::Pairable)
+    }
+}
+
+/*
+ * Value being typed for the alias or a timeout
+*/
+pub struct SettingInput
+{
+    pub setting: Setting,
+    pub text: String,
+}
+
+/*
+ * Pane showing the current adapter, kept up to date with its property changes
+*/
+pub struct AdapterSettings
+{
+    pub properties: Arc<Mutex<AdapterProperties>>,
+    pub selected_index: usize,
+    pub input: Option<SettingInput>,
+    // why the last change was refused, cleared by the next one
+    pub error: Arc<Mutex<Option<String>>>,
+    events: tokio::task::JoinHandle<()>,
+}
+
+impl AdapterSettings {
+    pub fn new<B: Backend>(backend: &B, properties: AdapterProperties) -> Self {
+        let properties = Arc::new(Mutex::new(properties));
+        let events = {
+            let backend = backend.clone();
+            let properties = properties.clone();
+            tokio::spawn(async move {
+                let Ok(mut events) = backend.adapter_events().await else {
+                    return;
+                };
+                while let Some(event) = events.next().await {
+                    if let AdapterEvent::PropertyChanged(property) = event {
+                        properties.lock().unwrap().apply(property);
+                    }
+                }
+            })
+        };
+
+        Self {
+            properties,
+            selected_index: 0,
+            input: None,
+            error: Arc::new(Mutex::new(None)),
+            events,
+        }
+    }
+
+    pub fn selected(&self) -> Setting {
+        Setting::ALL[self.selected_index]
+    }
+
+    pub fn keys(&self) -> &'static str {
+        match self.input {
+            Some(SettingInput { setting: Setting::Alias, .. }) => "(Enter) Save, empty for the system name | (Esc) Cancel",
+            Some(_) => "(Enter) Save, in seconds, 0 for never | (Esc) Cancel",
+            None => "(Enter) Change | (Esc) Back",
+        }
+    }
+
+    /*
+     * Returns false once the pane is closed
+    */
+    pub fn handle_key<B: Backend>(&mut self, backend: &B, code: KeyCode, action: Option<Action>) -> bool {
+        if let Some(input) = &mut self.input {
+            match code {
+                KeyCode::Esc => self.input = None,
+                KeyCode::Backspace => {
+                    input.text.pop();
+                }
+                KeyCode::Enter => {
+                    let input = self.input.take().unwrap();
+                    self.submit(backend, input);
+                }
+                KeyCode::Char(c) if input.setting == Setting::Alias || c.is_ascii_digit() => input.text.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        match (code, action) {
+            (KeyCode::Enter, _) | (KeyCode::Char(' '), _) => {
+                let setting = self.selected();
+                if setting.is_toggle() {
+                    self.toggle(backend, setting);
+                } else {
+                    let text = match setting {
+                        Setting::Alias => self.properties.lock().unwrap().alias.clone().unwrap_or_default(),
+                        _ => String::new(),
+                    };
+                    self.input = Some(SettingInput { setting, text });
+                }
+            }
+            (_, Some(Action::Up)) => {
+                self.selected_index = (self.selected_index + Setting::ALL.len() - 1) % Setting::ALL.len();
+            }
+            (_, Some(Action::Down)) => {
+                self.selected_index = (self.selected_index + 1) % Setting::ALL.len();
+            }
+            (KeyCode::Esc, _) | (_, Some(Action::Quit)) => {
+                self.close();
+                return false;
+            }
+            _ => {}
+        }
+        true
+    }
+
+    pub fn close(&mut self) {
+        self.events.abort();
+    }
+
+    fn toggle<B: Backend>(&self, backend: &B, setting: Setting) {
+        let properties = self.properties.lock().unwrap().clone();
+        let property = match setting {
+            Setting::Discoverable => AdapterProperty::Discoverable(!properties.discoverable),
+            _ => AdapterProperty::Pairable(!properties.pairable),
+        };
+        self.change(backend, property);
+    }
+
+    fn submit<B: Backend>(&self, backend: &B, input: SettingInput) {
+        let property = match input.setting {
+            Setting::Alias => AdapterProperty::Alias(input.text.trim().to_string()),
+            setting => {
+                let Ok(seconds) = input.text.parse() else {
+                    *self.error.lock().unwrap() = Some(format!("\"{}\" is not a number of seconds", input.text));
+                    return;
+                };
+                match setting {
+                    Setting::DiscoverableTimeout => AdapterProperty::DiscoverableTimeout(seconds),
+                    _ => AdapterProperty::PairableTimeout(seconds),
+                }
+            }
+        };
+        self.change(backend, property);
+    }
+
+    /*
+     * The panel is updated by the PropertyChanged event that follows, not here
+    */
+    fn change<B: Backend>(&self, backend: &B, property: AdapterProperty) {
+        let backend = backend.clone();
+        let error = self.error.clone();
+        *error.lock().unwrap() = None;
+
+        tokio::spawn(async move {
+            let result = match property {
+                AdapterProperty::Alias(alias) => backend.set_adapter_alias(alias).await,
+                AdapterProperty::Discoverable(discoverable) => backend.set_discoverable(discoverable).await,
+                AdapterProperty::DiscoverableTimeout(seconds) => backend.set_discoverable_timeout(seconds).await,
+                AdapterProperty::Pairable(pairable) => backend.set_pairable(pairable).await,
+                AdapterProperty::PairableTimeout(seconds) => backend.set_pairable_timeout(seconds).await,
+                _ => Ok(()),
+            };
+            if let Err(err) = result {
+                *error.lock().unwrap() = Some(err.to_string());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btui::fake::FakeBackend;
+    use tokio::time::{Duration, sleep};
+
+    async fn open(backend: &FakeBackend) -> AdapterSettings {
+        let settings = AdapterSettings::new(backend, backend.adapter_properties().await.unwrap());
+        // lets the event task subscribe
+        sleep(Duration::from_millis(10)).await;
+        settings
+    }
+
+    fn select(settings: &mut AdapterSettings, setting: Setting) {
+        settings.selected_index = Setting::ALL.iter().position(|s| *s == setting).unwrap();
+    }
+
+    fn type_text<B: Backend>(settings: &mut AdapterSettings, backend: &B, text: &str) {
+        for c in text.chars() {
+            settings.handle_key(backend, KeyCode::Char(c), None);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn toggles_follow_the_adapter() {
+        let backend = FakeBackend::new("hci0");
+        let mut settings = open(&backend).await;
+        select(&mut settings, Setting::Discoverable);
+
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        sleep(Duration::from_millis(10)).await;
+        assert!(settings.properties.lock().unwrap().discoverable);
+        assert_eq!(Setting::Discoverable.value(&settings.properties.lock().unwrap()), "yes");
+
+        let pairable = settings.properties.lock().unwrap().pairable;
+        select(&mut settings, Setting::Pairable);
+        settings.handle_key(&backend, KeyCode::Char(' '), None);
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(settings.properties.lock().unwrap().pairable, !pairable);
+
+        backend.fail_next("set_pairable");
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(settings.properties.lock().unwrap().pairable, !pairable, "nothing changed");
+        assert!(settings.error.lock().unwrap().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_alias_is_typed_over_the_current_one() {
+        let backend = FakeBackend::new("hci0");
+        backend.set_adapter_alias("laptop".to_string()).await.unwrap();
+        let mut settings = open(&backend).await;
+
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        assert_eq!(settings.input.as_ref().unwrap().text, "laptop");
+        settings.handle_key(&backend, KeyCode::Backspace, None);
+        type_text(&mut settings, &backend, "s 2");
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        sleep(Duration::from_millis(10)).await;
+        assert!(settings.input.is_none());
+        assert_eq!(settings.properties.lock().unwrap().alias.as_deref(), Some("laptos 2"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeouts_take_digits_only() {
+        let backend = FakeBackend::new("hci0");
+        let mut settings = open(&backend).await;
+        select(&mut settings, Setting::DiscoverableTimeout);
+
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        type_text(&mut settings, &backend, "1m20");
+        assert_eq!(settings.input.as_ref().unwrap().text, "120", "letters are not typed");
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(settings.properties.lock().unwrap().discoverable_timeout, 120);
+        assert_eq!(Setting::DiscoverableTimeout.value(&settings.properties.lock().unwrap()), "120s");
+
+        select(&mut settings, Setting::PairableTimeout);
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        assert_eq!(settings.error.lock().unwrap().as_deref(), Some("\"\" is not a number of seconds"));
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        type_text(&mut settings, &backend, "99999999999");
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        assert_eq!(settings.error.lock().unwrap().as_deref(), Some("\"99999999999\" is not a number of seconds"));
+        sleep(Duration::from_millis(10)).await;
+        assert!(!backend.calls().iter().any(|call| call.starts_with("set_pairable_timeout")));
+
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        type_text(&mut settings, &backend, "0");
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        assert_eq!(*settings.error.lock().unwrap(), None, "cleared by the next change");
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(Setting::PairableTimeout.value(&settings.properties.lock().unwrap()), "never");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn closing_stops_following_the_adapter() {
+        let backend = FakeBackend::new("hci0");
+        let mut settings = open(&backend).await;
+
+        settings.handle_key(&backend, KeyCode::Enter, None);
+        assert!(settings.handle_key(&backend, KeyCode::Esc, None), "Esc leaves the input first");
+        assert!(!settings.handle_key(&backend, KeyCode::Esc, None));
+        sleep(Duration::from_millis(10)).await;
+        assert!(settings.events.is_finished());
+
+        backend.set_discoverable(true).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        assert!(!settings.properties.lock().unwrap().discoverable);
+    }
+}
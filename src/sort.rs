@@ -0,0 +1,174 @@
+use crate::manager::{ConnectionState, DeviceInfo};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+
+/*
+ * Order of the devices inside each group of the list, cycled with the sort key
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey
+{
+    #[default]
+    Name,
+    /// Strongest first.
+    Rssi,
+    /// Most recent first.
+    LastSeen,
+    /// Emptiest first, the ones that need charging.
+    Battery,
+    Type,
+}
+
+impl SortKey {
+    pub fn is_default(&self) -> bool {
+        *self == SortKey::default()
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SortKey::Name => SortKey::Rssi,
+            SortKey::Rssi => SortKey::LastSeen,
+            SortKey::LastSeen => SortKey::Battery,
+            SortKey::Battery => SortKey::Type,
+            SortKey::Type => SortKey::Name,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Rssi => "signal",
+            SortKey::LastSeen => "last seen",
+            SortKey::Battery => "battery",
+            SortKey::Type => "type",
+        }
+    }
+
+    /*
+     * Connected devices come first, then paired then discovered ones, each group sorted by the key.
+     * Devices missing the value go last, ties are broken by name.
+     * `last_seen` is what the store remembers, connected devices count as seen now.
+    */
+    pub fn compare(self, a: &DeviceInfo, b: &DeviceInfo, last_seen: impl Fn(&DeviceInfo) -> Option<u64>) -> Ordering {
+        let seen = |device: &DeviceInfo| match device.state {
+            ConnectionState::Connected => Some(u64::MAX),
+            _ => last_seen(device),
+        };
+        let by_key = match self {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Rssi => a.rssi.is_none().cmp(&b.rssi.is_none()).then(b.rssi.cmp(&a.rssi)),
+            SortKey::LastSeen => {
+                let (a, b) = (seen(a), seen(b));
+                a.is_none().cmp(&b.is_none()).then(b.cmp(&a))
+            }
+            SortKey::Battery => a.battery.is_none().cmp(&b.battery.is_none()).then(a.battery.cmp(&b.battery)),
+            SortKey::Type => a.icon.cmp(&b.icon),
+        };
+        Reverse(a.state).cmp(&Reverse(b.state))
+            .then(by_key)
+            .then_with(|| by_name(a, b))
+    }
+}
+
+/*
+ * Without case, the unnamed devices after the named ones by address
+*/
+fn by_name(a: &DeviceInfo, b: &DeviceInfo) -> Ordering
+{
+    let name = |device: &DeviceInfo| device.name.as_ref().map(|name| name.to_lowercase());
+    let (name_a, name_b) = (name(a), name(b));
+    name_a.is_none().cmp(&name_b.is_none())
+        .then(name_a.cmp(&name_b))
+        .then(a.address.cmp(&b.address))
+}
+
+/*
+ * Header of the section a device is listed under
+*/
+pub fn group_label(state: ConnectionState) -> &'static str
+{
+    match state {
+        ConnectionState::Connected => "Connected",
+        ConnectionState::Paired => "Paired",
+        ConnectionState::Discovered => "Discovered",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::DeviceProperties;
+
+    fn device(address: &str, name: Option<&str>, paired: bool) -> DeviceInfo {
+        let properties = DeviceProperties { alias: name.map(str::to_string), paired, ..Default::default() };
+        DeviceInfo::from_properties(address.parse().unwrap(), properties)
+    }
+
+    fn sorted(key: SortKey, devices: &[DeviceInfo], last_seen: impl Fn(&DeviceInfo) -> Option<u64>) -> Vec<String> {
+        let mut devices = devices.to_vec();
+        devices.sort_by(|a, b| key.compare(a, b, &last_seen));
+        devices.iter().map(|device| device.address.to_string()).collect()
+    }
+
+    #[test]
+    fn ties_are_broken_by_name_then_address() {
+        let mut devices = vec![
+            device("EE:00:00:00:00:04", None, false),
+            device("EE:00:00:00:00:03", None, false),
+            device("EE:00:00:00:00:02", Some("speaker"), false),
+            device("EE:00:00:00:00:01", Some("Headphones"), false),
+        ];
+        for device in &mut devices {
+            device.rssi = Some(-60);
+        }
+
+        assert_eq!(sorted(SortKey::Rssi, &devices, |_| None), [
+            "EE:00:00:00:00:01", "EE:00:00:00:00:02", "EE:00:00:00:00:03", "EE:00:00:00:00:04",
+        ]);
+    }
+
+    #[test]
+    fn the_groups_come_before_the_key() {
+        let mut paired = device("EE:00:00:00:00:01", Some("Paired"), true);
+        paired.rssi = Some(-90);
+        let mut discovered = device("EE:00:00:00:00:02", Some("Discovered"), false);
+        discovered.rssi = Some(-30);
+
+        assert_eq!(sorted(SortKey::Rssi, &[discovered, paired], |_| None), ["EE:00:00:00:00:01", "EE:00:00:00:00:02"]);
+    }
+
+    #[test]
+    fn missing_values_go_last() {
+        let mut devices = vec![
+            device("EE:00:00:00:00:01", Some("A"), false),
+            device("EE:00:00:00:00:02", Some("B"), false),
+            device("EE:00:00:00:00:03", Some("C"), false),
+        ];
+        devices[1].rssi = Some(-80);
+        devices[2].rssi = Some(-40);
+        devices[1].battery = Some(90);
+        devices[2].battery = Some(10);
+
+        assert_eq!(sorted(SortKey::Rssi, &devices, |_| None), ["EE:00:00:00:00:03", "EE:00:00:00:00:02", "EE:00:00:00:00:01"]);
+        assert_eq!(sorted(SortKey::Battery, &devices, |_| None), ["EE:00:00:00:00:03", "EE:00:00:00:00:02", "EE:00:00:00:00:01"]);
+        assert_eq!(
+            sorted(SortKey::LastSeen, &devices, |device| (device.address.0[5] != 3).then_some(u64::from(device.address.0[5]))),
+            ["EE:00:00:00:00:02", "EE:00:00:00:00:01", "EE:00:00:00:00:03"]
+        );
+    }
+
+    #[test]
+    fn swapping_the_devices_reverses_the_order() {
+        let mut a = device("EE:00:00:00:00:01", Some("A"), false);
+        let b = device("EE:00:00:00:00:02", Some("B"), false);
+        a.battery = Some(50);
+
+        for key in [SortKey::Name, SortKey::Rssi, SortKey::LastSeen, SortKey::Battery, SortKey::Type] {
+            let forward = key.compare(&a, &b, |_| None);
+            assert_ne!(forward, Ordering::Equal, "{:?}", key);
+            assert_eq!(key.compare(&b, &a, |_| None), forward.reverse(), "{:?}", key);
+        }
+        assert_eq!(SortKey::Name.compare(&a, &a, |_| None), Ordering::Equal);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::activity::{ActivityLog, Level};
+use crate::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity
+{
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn level(self) -> Level {
+        match self {
+            Severity::Info => Level::Info,
+            Severity::Warning => Level::Warning,
+            Severity::Error => Level::Error,
+        }
+    }
+
+    /*
+     * How long a message stays, errors longer so they are not missed
+    */
+    fn lifetime(self) -> Duration {
+        match self {
+            Severity::Info => Duration::from_secs(4),
+            Severity::Warning => Duration::from_secs(8),
+            Severity::Error => Duration::from_secs(15),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Message
+{
+    pub text: String,
+    pub severity: Severity,
+    expires_at: Instant,
+}
+
+/*
+ * Line under the device list, shared with the tasks running actions in the background.
+ * A new message replaces the previous one, every message is kept in the activity log.
+*/
+#[derive(Clone, Default)]
+pub struct StatusBar
+{
+    message: Arc<Mutex<Option<Message>>>,
+    log: ActivityLog,
+}
+
+impl StatusBar {
+    pub fn new(log: ActivityLog) -> Self {
+        Self { message: Arc::default(), log }
+    }
+
+    pub fn log(&self) -> &ActivityLog {
+        &self.log
+    }
+
+    pub fn show(&self, severity: Severity, text: impl Into<String>) {
+        let text = text.into();
+        self.log.push(severity.level(), text.clone());
+        *self.message.lock().unwrap() = Some(Message {
+            text,
+            severity,
+            expires_at: Instant::now() + severity.lifetime(),
+        });
+    }
+
+    pub fn info(&self, text: impl Into<String>) {
+        self.show(Severity::Info, text);
+    }
+
+    pub fn warn(&self, text: impl Into<String>) {
+        self.show(Severity::Warning, text);
+    }
+
+    /*
+     * Shows why `what` failed, nothing when it worked
+    */
+    pub fn report<T, E: Into<Error>>(&self, what: &str, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                let err = err.into();
+                // a busy device is worth a retry, not an alarm
+                let severity = match err {
+                    Error::Busy(_) => Severity::Warning,
+                    _ => Severity::Error,
+                };
+                self.show(severity, format!("{}: {}", what, err));
+                None
+            }
+        }
+    }
+
+    /*
+     * The message to draw, dropped once it expired
+    */
+    pub fn current(&self) -> Option<Message> {
+        let mut message = self.message.lock().unwrap();
+        if message.as_ref().is_some_and(|m| Instant::now() >= m.expires_at) {
+            *message = None;
+        }
+        message.clone()
+    }
+}
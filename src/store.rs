@@ -0,0 +1,539 @@
+use crate::backend::ScanFilter;
+use crate::sort::SortKey;
+use bluer::{Address, DeviceProperty};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+/*
+ * What btui remembers about the paired devices of one adapter, the last scan filter and list order,
+ * kept in $XDG_CACHE_HOME/bluetooi/<adapter>/devices.json, e.g.
+ *
+ *   {
+ *     "version": 1,
+ *     "devices": {
+ *       "AA:BB:CC:DD:EE:FF": {
+ *         "alias": "Headphones",
+ *         "first_seen": 1718000000,
+ *         "last_seen": 1718003600,
+ *         "last_connected": 1718003600,
+ *         "battery": 80,
+ *         "tags": ["work"],
+ *         "notes": "",
+ *         "auto_reconnect": true
+ *       }
+ *     },
+ *     "discovery_filter": {
+ *       "transport": "le",
+ *       "min_rssi": -70
+ *     },
+ *     "sort": "last_seen"
+ *   }
+*/
+
+// bumped whenever the layout changes, older files are migrated on load
+pub const SCHEMA_VERSION: u32 = 1;
+
+// RSSI updates and advertisements come several times a second while scanning
+const LAST_SEEN_GRANULARITY: u64 = 60;
+
+// numbers the temporary files of this process, see DeviceStore::save
+static NEXT_TEMPORARY: AtomicU64 = AtomicU64::new(0);
+
+/// Times are seconds since the Unix epoch.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoredDevice
+{
+    pub alias: Option<String>,
+    pub first_seen: Option<u64>,
+    pub last_seen: Option<u64>,
+    pub last_connected: Option<u64>,
+    /// Last battery level reported while connected.
+    pub battery: Option<u8>,
+    pub tags: Vec<String>,
+    pub notes: String,
+    /// Connect again whenever the device drops, see manager::auto_reconnect.
+    pub auto_reconnect: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct StoreFile
+{
+    // missing in files older than the field, read as version 0
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    devices: BTreeMap<Address, StoredDevice>,
+    #[serde(default, skip_serializing_if = "ScanFilter::is_default")]
+    discovery_filter: ScanFilter,
+    #[serde(default, skip_serializing_if = "SortKey::is_default")]
+    sort: SortKey,
+}
+
+/*
+ * Shared handle on the store, every change is written to disk right away
+*/
+#[derive(Clone)]
+pub struct DeviceStore
+{
+    // None when nothing may be written, see open_at
+    path: Option<PathBuf>,
+    file: Arc<Mutex<StoreFile>>,
+    // what was lost reading the file, for the user to know
+    problem: Option<String>,
+}
+
+impl DeviceStore {
+    /*
+     * The store of `adapter`, only kept in memory when there is no cache directory
+    */
+    pub fn open(adapter: &str) -> Self {
+        match dirs::cache_dir() {
+            Some(mut dir) => {
+                dir.push(format!("bluetooi/{}", adapter));
+                Self::open_at(&dir)
+            }
+            None => Self::in_memory(),
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        Self { path: None, file: Arc::new(Mutex::new(StoreFile::default())), problem: None }
+    }
+
+    /*
+     * Loads `dir`/devices.json, creating it from the <address>.txt marker files older versions left.
+     * A damaged file is set aside as devices.json.corrupt and what can still be read of it is kept,
+     * a file written by a newer btui or one that can't be read at all is used as is but never overwritten.
+    */
+    pub fn open_at(dir: &Path) -> Self {
+        let _ = fs::create_dir_all(dir);
+        let path = dir.join("devices.json");
+
+        let mut problem = None;
+        let (file, writable) = match fs::read(&path) {
+            Ok(bytes) => match parse(&bytes) {
+                Ok(file) if file.version > SCHEMA_VERSION => (file, false),
+                Ok(file) => (file, true),
+                Err(err) => {
+                    let corrupt = dir.join("devices.json.corrupt");
+                    let _ = fs::rename(&path, &corrupt);
+                    let (file, lost) = salvage(&String::from_utf8_lossy(&bytes));
+                    problem = Some(match lost {
+                        None => format!("{} is unreadable ({}), the remembered devices are lost, it was kept as {}", path.display(), err, corrupt.display()),
+                        Some(0) => format!("{} was damaged ({}), every device could be read back, it was kept as {}", path.display(), err, corrupt.display()),
+                        Some(lost) => format!("{} was damaged ({}), {} devices are lost, it was kept as {}", path.display(), err, lost, corrupt.display()),
+                    });
+                    (file, true)
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (StoreFile { devices: migrate_markers(dir), ..Default::default() }, true),
+            Err(err) => {
+                problem = Some(format!("cannot read {} ({}), the remembered devices are left out and the file is left alone", path.display(), err));
+                (StoreFile::default(), false)
+            }
+        };
+
+        let store = Self {
+            path: writable.then_some(path),
+            file: Arc::new(Mutex::new(file)),
+            problem,
+        };
+        // the markers are only dropped once the store holding them is on disk
+        if store.save(&store.file.lock().unwrap()) {
+            remove_markers(dir);
+        }
+        store
+    }
+
+    /// False when changes stay in memory, as with a file written by a newer btui.
+    pub fn is_saved(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// What could not be read back from the file, None when it was fine.
+    pub fn problem(&self) -> Option<&str> {
+        self.problem.as_deref()
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        self.file.lock().unwrap().devices.keys().copied().collect()
+    }
+
+    pub fn get(&self, address: Address) -> Option<StoredDevice> {
+        self.file.lock().unwrap().devices.get(&address).cloned()
+    }
+
+    /*
+     * Adds a newly paired device, returns false when it was already known
+    */
+    pub fn remember(&self, address: Address) -> bool {
+        let mut file = self.file.lock().unwrap();
+        if file.devices.contains_key(&address) {
+            return false;
+        }
+        let now = now();
+        file.devices.insert(address, StoredDevice { first_seen: Some(now), last_seen: Some(now), ..Default::default() });
+        self.save(&file);
+        true
+    }
+
+    pub fn forget(&self, address: Address) {
+        let mut file = self.file.lock().unwrap();
+        if file.devices.remove(&address).is_some() {
+            self.save(&file);
+        }
+    }
+
+    /*
+     * Changes the entry of a known device, the file is only rewritten when something changed
+    */
+    pub fn update(&self, address: Address, change: impl FnOnce(&mut StoredDevice)) {
+        let mut file = self.file.lock().unwrap();
+        let Some(device) = file.devices.get_mut(&address) else {
+            return;
+        };
+        let before = device.clone();
+        change(device);
+        if *device != before {
+            self.save(&file);
+        }
+    }
+
+    /// Filter of the last scan, the default one at first.
+    pub fn discovery_filter(&self) -> ScanFilter {
+        self.file.lock().unwrap().discovery_filter.clone()
+    }
+
+    pub fn set_discovery_filter(&self, filter: ScanFilter) {
+        let mut file = self.file.lock().unwrap();
+        if file.discovery_filter != filter {
+            file.discovery_filter = filter;
+            self.save(&file);
+        }
+    }
+
+    pub fn sort_key(&self) -> SortKey {
+        self.file.lock().unwrap().sort
+    }
+
+    pub fn set_sort_key(&self, sort: SortKey) {
+        let mut file = self.file.lock().unwrap();
+        if file.sort != sort {
+            file.sort = sort;
+            self.save(&file);
+        }
+    }
+
+    /*
+     * The device showed up in a scan
+    */
+    pub fn seen(&self, address: Address) {
+        let now = now();
+        self.update(address, |device| mark_seen(device, now));
+    }
+
+    /*
+     * Records what a property change says about the device
+    */
+    pub fn apply(&self, address: Address, property: &DeviceProperty) {
+        let now = now();
+        self.update(address, |device| match property {
+            DeviceProperty::Alias(alias) => device.alias = Some(alias.clone()),
+            DeviceProperty::Connected(true) => {
+                device.last_connected = Some(now);
+                device.last_seen = Some(now);
+            }
+            DeviceProperty::Rssi(_) => mark_seen(device, now),
+            DeviceProperty::BatteryPercentage(battery) => device.battery = Some(*battery),
+            _ => {}
+        });
+    }
+
+    /*
+     * Written next to the store then renamed over it, a crash never leaves half a file.
+     * Every write has a temporary file of its own, so two btui running at once can't mix theirs up.
+    */
+    fn save(&self, file: &StoreFile) -> bool {
+        let Some(path) = &self.path else {
+            return false;
+        };
+        let file = StoreFile { version: SCHEMA_VERSION, ..file.clone() };
+        let Ok(text) = serde_json::to_string_pretty(&file) else {
+            return false;
+        };
+
+        let temporary = path.with_extension(format!("json.{}-{}.tmp", std::process::id(), NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed)));
+        let written = fs::File::create(&temporary).and_then(|mut out| {
+            out.write_all(text.as_bytes())?;
+            out.sync_all()
+        });
+        let saved = written.and_then(|_| fs::rename(&temporary, path));
+        if saved.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        saved.is_ok()
+    }
+}
+
+pub fn now() -> u64
+{
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs()
+}
+
+pub fn to_time(seconds: u64) -> SystemTime
+{
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/*
+ * Only once the last one is LAST_SEEN_GRANULARITY old, a device in range isn't a file write per advertisement
+*/
+fn mark_seen(device: &mut StoredDevice, now: u64)
+{
+    if device.last_seen.is_none_or(|seen| now >= seen + LAST_SEEN_GRANULARITY) {
+        device.last_seen = Some(now);
+    }
+}
+
+/*
+ * Bytes that aren't UTF-8 make the file as damaged as bad JSON does
+*/
+fn parse(bytes: &[u8]) -> Result<StoreFile, String>
+{
+    let text = std::str::from_utf8(bytes).map_err(|err| err.to_string())?;
+    serde_json::from_str(text).map_err(|err| err.to_string())
+}
+
+/*
+ * The parts of a damaged file that still read, entry by entry.
+ * Returns how many devices were dropped, None when the text isn't JSON at all.
+*/
+fn salvage(text: &str) -> (StoreFile, Option<usize>)
+{
+    let Ok(serde_json::Value::Object(mut fields)) = serde_json::from_str(text) else {
+        return (StoreFile::default(), None);
+    };
+    let mut take = |name: &str| fields.remove(name).unwrap_or_default();
+
+    let entries: serde_json::Map<String, serde_json::Value> = serde_json::from_value(take("devices")).unwrap_or_default();
+    let count = entries.len();
+    let devices: BTreeMap<Address, StoredDevice> = entries.into_iter()
+        .filter_map(|(address, device)| Some((address.parse().ok()?, serde_json::from_value(device).ok()?)))
+        .collect();
+    let lost = count - devices.len();
+
+    let file = StoreFile {
+        version: SCHEMA_VERSION,
+        devices,
+        discovery_filter: serde_json::from_value(take("discovery_filter")).unwrap_or_default(),
+        sort: serde_json::from_value(take("sort")).unwrap_or_default(),
+    };
+    (file, Some(lost))
+}
+
+/*
+ * Version 0 kept an empty <address>.txt file per paired device,
+ * the file's age is the best guess of when the device was first seen
+*/
+fn migrate_markers(dir: &Path) -> BTreeMap<Address, StoredDevice>
+{
+    let Ok(entries) = fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let address: Address = entry.file_name().to_str()?.strip_suffix(".txt")?.parse().ok()?;
+            let first_seen = entry.metadata().and_then(|m| m.modified()).ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|age| age.as_secs());
+            Some((address, StoredDevice { first_seen, ..Default::default() }))
+        })
+        .collect()
+}
+
+fn remove_markers(dir: &Path)
+{
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_marker = entry.file_name().to_str()
+            .and_then(|name| name.strip_suffix(".txt"))
+            .is_some_and(|address| address.parse::<Address>().is_ok());
+        if is_marker {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * A cache directory of our own, removed when dropped
+    */
+    struct FakeCache
+    {
+        dir: PathBuf,
+    }
+
+    impl FakeCache {
+        fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("btui-store-{}-{}", std::process::id(), test));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+
+        fn write(&self, name: &str, text: &str) {
+            fs::write(self.dir.join(name), text).unwrap();
+        }
+
+        fn read(&self, name: &str) -> String {
+            fs::read_to_string(self.dir.join(name)).unwrap()
+        }
+    }
+
+    impl Drop for FakeCache {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn address(text: &str) -> Address {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn markers_become_devices() {
+        let cache = FakeCache::new("markers");
+        cache.write("AA:BB:CC:DD:EE:01.txt", "");
+        cache.write("AA:BB:CC:DD:EE:02.txt", "");
+        cache.write("notes.txt", "not a marker");
+        let store = DeviceStore::open_at(&cache.dir);
+
+        assert_eq!(store.addresses(), vec![address("AA:BB:CC:DD:EE:01"), address("AA:BB:CC:DD:EE:02")]);
+        assert!(store.get(address("AA:BB:CC:DD:EE:01")).unwrap().first_seen.is_some());
+        assert!(store.problem().is_none());
+        let mut left: Vec<String> = fs::read_dir(&cache.dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        left.sort();
+        assert_eq!(left, ["devices.json", "notes.txt"], "the markers are gone, no temporary file is left");
+    }
+
+    #[test]
+    fn a_file_without_a_version_is_read_and_upgraded() {
+        let cache = FakeCache::new("unversioned");
+        cache.write("devices.json", r#"{ "devices": { "AA:BB:CC:DD:EE:01": { "tags": ["work"] } } }"#);
+        let store = DeviceStore::open_at(&cache.dir);
+
+        assert_eq!(store.get(address("AA:BB:CC:DD:EE:01")).unwrap().tags, ["work"]);
+        assert!(cache.read("devices.json").contains(&format!("\"version\": {}", SCHEMA_VERSION)));
+    }
+
+    #[test]
+    fn a_newer_file_is_never_overwritten() {
+        let cache = FakeCache::new("newer");
+        let newer = r#"{ "version": 99, "devices": { "AA:BB:CC:DD:EE:01": { "alias": "Headphones", "colour": "red" } } }"#;
+        cache.write("devices.json", newer);
+        let store = DeviceStore::open_at(&cache.dir);
+
+        assert!(!store.is_saved());
+        assert_eq!(store.get(address("AA:BB:CC:DD:EE:01")).unwrap().alias.as_deref(), Some("Headphones"));
+        store.remember(address("AA:BB:CC:DD:EE:02"));
+        store.update(address("AA:BB:CC:DD:EE:01"), |device| device.notes = "changed".to_string());
+        assert_eq!(store.addresses().len(), 2, "changes are kept in memory");
+        assert_eq!(cache.read("devices.json"), newer);
+    }
+
+    #[test]
+    fn a_damaged_file_keeps_the_devices_still_readable() {
+        let cache = FakeCache::new("damaged");
+        let damaged = r#"{
+            "version": "one",
+            "devices": {
+                "AA:BB:CC:DD:EE:01": { "alias": "Headphones", "auto_reconnect": true },
+                "AA:BB:CC:DD:EE:02": { "battery": "full" },
+                "not an address": {}
+            },
+            "sort": "last_seen"
+        }"#;
+        cache.write("devices.json", damaged);
+        let store = DeviceStore::open_at(&cache.dir);
+
+        assert_eq!(store.addresses(), vec![address("AA:BB:CC:DD:EE:01")]);
+        assert_eq!(store.get(address("AA:BB:CC:DD:EE:01")).unwrap().alias.as_deref(), Some("Headphones"));
+        assert_eq!(store.sort_key(), SortKey::LastSeen);
+        assert!(store.problem().unwrap().contains("2 devices are lost"), "{:?}", store.problem());
+        assert_eq!(cache.read("devices.json.corrupt"), damaged);
+        // written back whole
+        assert!(serde_json::from_str::<StoreFile>(&cache.read("devices.json")).is_ok());
+    }
+
+    #[test]
+    fn a_file_that_is_not_json_is_reported_lost() {
+        let cache = FakeCache::new("garbage");
+        cache.write("devices.json", "{\"version\": 1, \"devices\": {\"AA:BB:CC");
+        let store = DeviceStore::open_at(&cache.dir);
+
+        assert!(store.addresses().is_empty());
+        assert!(store.problem().unwrap().contains("the remembered devices are lost"), "{:?}", store.problem());
+        assert_eq!(cache.read("devices.json.corrupt"), "{\"version\": 1, \"devices\": {\"AA:BB:CC");
+    }
+
+    #[test]
+    fn a_file_that_is_not_utf8_is_set_aside() {
+        let cache = FakeCache::new("latin1");
+        let latin1 = b"{ \"version\": 1, \"devices\": { \"AA:BB:CC:DD:EE:01\": { \"alias\": \"Kopfh\xf6rer\" } } }";
+        fs::write(cache.dir.join("devices.json"), latin1).unwrap();
+        let store = DeviceStore::open_at(&cache.dir);
+
+        assert!(store.problem().unwrap().contains("was damaged"), "{:?}", store.problem());
+        assert_eq!(fs::read(cache.dir.join("devices.json.corrupt")).unwrap(), latin1);
+        assert_eq!(store.addresses(), vec![address("AA:BB:CC:DD:EE:01")]);
+    }
+
+    #[test]
+    fn a_file_that_cannot_be_read_is_left_alone() {
+        let cache = FakeCache::new("unreadable");
+        cache.write("AA:BB:CC:DD:EE:01.txt", "");
+        // reading a directory fails, as a file without read permission would
+        fs::create_dir(cache.dir.join("devices.json")).unwrap();
+        let store = DeviceStore::open_at(&cache.dir);
+
+        assert!(!store.is_saved());
+        assert!(store.problem().unwrap().starts_with("cannot read"), "{:?}", store.problem());
+        assert!(store.addresses().is_empty());
+        store.remember(address("AA:BB:CC:DD:EE:02"));
+        assert!(cache.dir.join("devices.json").is_dir());
+        assert!(cache.dir.join("AA:BB:CC:DD:EE:01.txt").exists(), "the markers are kept");
+    }
+
+    #[test]
+    fn last_seen_moves_a_minute_at_a_time() {
+        let cache = FakeCache::new("seen");
+        let store = DeviceStore::open_at(&cache.dir);
+        let headphones = address("AA:BB:CC:DD:EE:01");
+        store.remember(headphones);
+
+        let recently = now() - 30;
+        store.update(headphones, |device| device.last_seen = Some(recently));
+        store.seen(headphones);
+        store.apply(headphones, &DeviceProperty::Rssi(-60));
+        assert_eq!(store.get(headphones).unwrap().last_seen, Some(recently));
+
+        store.update(headphones, |device| device.last_seen = Some(recently - 60));
+        store.seen(headphones);
+        assert!(store.get(headphones).unwrap().last_seen.unwrap() >= recently + 30);
+    }
+}
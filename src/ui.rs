@@ -0,0 +1,1087 @@
+use btui::activity::{self, Entry, Level};
+use crate::config::{self, Action, Config};
+use btui::details;
+use crate::gatt::GattExplorer;
+use btui::manager::{self, ConnectionState, DeviceInfo, IconKind};
+use btui::operations::Running;
+use btui::rfkill;
+use crate::scan_options::{ScanOption, ScanOptions};
+use btui::search::{self, ListFilter};
+use crate::settings::{AdapterSettings, Setting};
+use btui::sort::{self, SortKey};
+use btui::status::{Message, Severity};
+use btui::store::{self, StoredDevice};
+use crate::prompt::AgentPrompt;
+use crate::{AdapterPicker, LogPane, RenameInput};
+use ratatui::{
+    Frame, widgets::{Block, Borders, List, ListItem, ListState, Paragraph}
+};
+use std::collections::HashMap;
+
+/*
+ * Everything a frame shows, taken from the app before drawing.
+ * Drawing depends on nothing else, not even the time.
+*/
+#[derive(Default)]
+pub struct Screen<'a>
+{
+    pub adapter_name: &'a str,
+    pub powered: bool,
+    pub blocked: rfkill::Block,
+    pub scanning: bool,
+    /// The devices the filter lets through, in the order they are listed.
+    pub rows: Vec<DeviceRow>,
+    /// How many devices there are before filtering.
+    pub total: usize,
+    /// Position in `rows`.
+    pub selected: usize,
+    pub filter: ListFilter,
+    pub searching: bool,
+    pub sort: SortKey,
+    pub detail: bool,
+    /// What the store remembers of the selected device, for the detail panel.
+    pub stored: Option<StoredDevice>,
+    pub rename: Option<&'a RenameInput>,
+    pub picker: Option<&'a AdapterPicker>,
+    pub settings: Option<&'a AdapterSettings>,
+    pub scan_options: Option<&'a ScanOptions>,
+    pub gatt: Option<&'a GattExplorer>,
+    pub prompt: Option<&'a AgentPrompt>,
+    pub log_pane: Option<&'a LogPane>,
+    /// The entries the log pane shows, at its level and above.
+    pub log: Vec<Entry>,
+    pub status: Option<Message>,
+    /// When the frame was taken, in seconds since the epoch as the store keeps times.
+    pub now: u64,
+}
+
+/*
+ * A device as its line in the list shows it
+*/
+pub struct DeviceRow
+{
+    pub device: DeviceInfo,
+    pub auto_reconnect: bool,
+    // spinner frame and label of the running operation, empty when there is none
+    pub operation: String,
+    pub reconnect: String,
+}
+
+impl DeviceRow {
+    /*
+     * Turns the spinner and the reconnection countdown into text as of now
+    */
+    pub fn new(device: DeviceInfo, auto_reconnect: bool, running: Option<Running>) -> Self {
+        let operation = running.map(|running| format!("{} {} ", running.spinner(), running.kind.label())).unwrap_or_default();
+        let reconnect = reconnect_label(&device);
+        Self { device, auto_reconnect, operation, reconnect }
+    }
+}
+
+pub fn render(frame: &mut Frame, screen: &Screen, config: &Config) {
+    use ratatui::prelude::*;
+
+    let theme = &config.theme;
+
+    let query = &screen.filter.query;
+    let device_items: Vec<ListItem> = screen.rows
+        .iter()
+        .map(|row| {
+            let d = &row.device;
+            let connected = d.state == ConnectionState::Connected;
+            let signal = match d.rssi {
+                Some(rssi) => Span::styled(format!("{:>8}", rssi_label(rssi)), signal_color(rssi, theme)),
+                None => Span::raw(format!("{:>8}", "")),
+            };
+            let mut spans = vec![
+                Span::raw(format!("{}{}", if d.trusted { "T" } else { " " }, if row.auto_reconnect { "R" } else { " " })),
+                Span::styled(if d.blocked { "B" } else { " " }, theme.error),
+                Span::raw(format!(" | {} ", state_glyph(d.state))),
+                signal,
+                Span::raw(format!("    {}    [", icon_glyph(d, &config.icons))),
+            ];
+            spans.extend(highlighted(&d.address.to_string(), query, theme));
+            spans.push(Span::raw("] "));
+            spans.extend(highlighted(d.display_name(), query, theme));
+            spans.push(Span::raw(format!(" {} ", battery_label(d))));
+            if !row.operation.is_empty() {
+                spans.push(Span::styled(row.operation.clone(), theme.scanning));
+            }
+            spans.push(Span::styled(row.reconnect.clone(), theme.scanning));
+            ListItem::new(Line::from(spans))
+                .add_modifier(
+                    if connected {
+                        Modifier::BOLD
+                    } else {
+                        Modifier::empty()
+                    }
+                )
+                .style(
+                    if connected && screen.powered {
+                        theme.connected
+                    } else if !screen.powered {
+                        theme.powered_off
+                    } else {
+                        theme.text
+                    }
+
+                )
+        })
+        .collect();
+
+    // a header above each group, the selection has to step over them
+    let rows = &screen.rows;
+    let mut items = vec![];
+    let mut selected_item = None;
+    for (index, item) in device_items.into_iter().enumerate() {
+        let state = rows[index].device.state;
+        if index == 0 || rows[index - 1].device.state != state {
+            let count = rows.iter().filter(|row| row.device.state == state).count();
+            items.push(ListItem::new(format!("{} ({})", sort::group_label(state), count))
+                .style(Style::default().fg(theme.text).add_modifier(Modifier::BOLD | Modifier::UNDERLINED)));
+        }
+        if index == screen.selected {
+            selected_item = Some(items.len());
+        }
+        items.push(item);
+    }
+
+    let mut list_state = ListState::default();
+    list_state.select(selected_item);
+
+    let list = List::new(items)
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title(if screen.filter.is_active() {
+                format!("Devices on {} ({} of {}: {})", screen.adapter_name, rows.len(), screen.total, screen.filter.describe())
+            } else {
+                format!("Devices on {}", screen.adapter_name)
+            })
+            .title(Line::from(format!("sorted by {}", screen.sort.label())).right_aligned())
+            .title_bottom(filter_hints(&screen.filter, config).centered())
+            .style(Style::default().fg(
+                if screen.scanning {
+                    theme.scanning
+                } else if screen.powered {
+                    theme.text
+                } else {
+                    theme.powered_off
+                }
+            )))
+        .highlight_style(Style::default().bg(theme.highlight).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> ");
+
+    let commands: Vec<String> = [Action::Power, Action::Scan, Action::Connect, Action::Pair, Action::Trust, Action::Block, Action::Reconnect, Action::Rename, Action::Forget, Action::Cancel, Action::Gatt, Action::Info, Action::Log, Action::Search, Action::Sort, Action::Adapter, Action::Settings, Action::ScanFilter, Action::Quit]
+        .into_iter()
+        .map(|action| config.keys.hint(action))
+        .collect();
+    let commands = wrap_hints(&commands, frame.area().width as usize);
+
+    // the commands take their title line and as many as they wrap to, but leave the list three lines
+    let height = (commands.len() as u16 + 1).min(frame.area().height.saturating_sub(4)).max(2);
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Length(height), Constraint::Min(0), Constraint::Length(1)])
+        .split(frame.area());
+
+    frame.render_widget(
+        Paragraph::new(commands.into_iter().map(Line::from).collect::<Vec<_>>())
+        .alignment(Alignment::Center)
+        .block(render_blocked(screen.blocked, config, Block::new().borders(Borders::NONE).title("Commands").title_alignment(Alignment::Center))),
+        layout[0],
+    );
+
+    let main_area = match screen.log_pane {
+        Some(pane) => {
+            let [main_area, log_area] = Layout::vertical([Constraint::Min(0), Constraint::Percentage(35)]).areas(layout[1]);
+            render_log(frame, pane, &screen.log, log_area, config);
+            main_area
+        }
+        None => layout[1],
+    };
+
+    match (screen.gatt, screen.settings) {
+        (Some(explorer), _) => render_gatt(frame, explorer, main_area, config),
+        (None, Some(settings)) => render_settings(frame, settings, screen.adapter_name, main_area, theme),
+        (None, None) if screen.detail => {
+            let [list_area, detail_area] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(main_area);
+            let list_area = render_rename(frame, screen.rename, list_area, theme);
+            let list_area = render_search(frame, screen, list_area, theme);
+            frame.render_stateful_widget(list, list_area, &mut list_state);
+            let device = rows.get(screen.selected).map(|row| &row.device);
+            render_detail(frame, device, screen.stored.clone(), store::to_time(screen.now), detail_area, theme);
+        }
+        (None, None) => {
+            let list_area = render_rename(frame, screen.rename, main_area, theme);
+            let list_area = render_search(frame, screen, list_area, theme);
+            frame.render_stateful_widget(list, list_area, &mut list_state);
+        }
+    }
+
+    if let Some(message) = &screen.status {
+        let color = match message.severity {
+            Severity::Info => theme.info,
+            Severity::Warning => theme.warning,
+            Severity::Error => theme.error,
+        };
+        frame.render_widget(Paragraph::new(message.text.as_str()).style(Style::default().fg(color)), layout[2]);
+    }
+
+    if let Some(picker) = screen.picker {
+        render_picker(frame, picker, screen.adapter_name, theme);
+    }
+    if let Some(options) = screen.scan_options {
+        render_scan_options(frame, options, theme);
+    }
+    if let Some(prompt) = screen.prompt {
+        render_prompt(frame, prompt, theme);
+    }
+}
+
+/*
+ * The rfkill state on the right of the header, a soft block with the key lifting it
+*/
+fn render_blocked<'a>(blocked: rfkill::Block, config: &Config, block: Block<'a>) -> Block<'a> {
+    use ratatui::prelude::*;
+
+    let (text, color) = match blocked {
+        rfkill::Block::Unblocked => return block,
+        rfkill::Block::Soft => (format!("Soft-blocked, {}", config.keys.hint(Action::Unblock)), config.theme.warning),
+        rfkill::Block::Hard => ("Hard-blocked by a switch".to_string(), config.theme.error),
+    };
+    block.title(Line::styled(text, Style::default().fg(color).add_modifier(Modifier::BOLD)).right_aligned())
+}
+
+/*
+ * Newest entries at the bottom unless scrolled back, like the GATT values
+*/
+fn render_log(frame: &mut Frame, pane: &LogPane, entries: &[Entry], area: ratatui::layout::Rect, config: &Config) {
+    use ratatui::prelude::*;
+
+    let theme = &config.theme;
+    let height = area.height.saturating_sub(2) as usize;
+    let end = entries.len().saturating_sub(pane.scroll);
+    let lines: Vec<Line> = entries[end.saturating_sub(height)..end]
+        .iter()
+        .map(|entry| {
+            let color = match entry.level {
+                Level::Debug => theme.powered_off,
+                Level::Info => theme.text,
+                Level::Warning => theme.warning,
+                Level::Error => theme.error,
+            };
+            Line::from(vec![
+                Span::raw(format!("{} ", activity::clock(entry.time))),
+                Span::styled(format!("{:<5} ", entry.level), Style::default().fg(color)),
+                Span::raw(entry.text.clone()),
+            ])
+        })
+        .collect();
+
+    let title = match pane.scroll {
+        0 => format!("Activity, UTC ({} and above)", pane.level),
+        scroll => format!("Activity, UTC ({} and above, {} lines back)", pane.level, scroll),
+    };
+    let hints = [Action::LogLevel, Action::SaveLog].map(|action| config.keys.hint(action)).join(" | ");
+    frame.render_widget(
+        Paragraph::new(lines)
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title(title)
+            .title_bottom(Line::from(format!("{} | {} scroll", hints, config.keys.keys(&[Action::ScrollUp, Action::ScrollDown]))).centered())
+            .style(Style::default().fg(theme.text))),
+        area,
+    );
+}
+
+fn render_detail(frame: &mut Frame, device: Option<&DeviceInfo>, stored: Option<StoredDevice>, now: std::time::SystemTime, area: ratatui::layout::Rect, theme: &config::Theme) {
+    use ratatui::prelude::*;
+    use ratatui::widgets::Sparkline;
+
+    let block = Block::new()
+        .borders(Borders::ALL)
+        .title("Details")
+        .style(Style::default().fg(theme.text));
+    let Some(device) = device else {
+        frame.render_widget(block, area);
+        return;
+    };
+
+    let properties = &device.properties;
+    let yes_no = |value: bool| if value { "yes" } else { "no" }.to_string();
+    let field = |label: &str, value: String| Line::from(format!("{:<18}{}", label, value));
+    let or_dash = |value: Option<String>| value.unwrap_or("-".to_string());
+
+    let mut lines = vec![
+        Line::from(device.display_name().to_string()).bold(),
+        Line::from(device.address.to_string()),
+        Line::from(""),
+        field("Remote name", or_dash(properties.name.clone())),
+        field("Alias", or_dash(properties.alias.clone())),
+        field("Address type", or_dash(properties.address_type.map(|t| t.to_string()))),
+        field("Class", or_dash(properties.class.map(details::describe_class))),
+        field("Appearance", or_dash(properties.appearance.map(details::describe_appearance))),
+        field("Modalias", or_dash(properties.modalias.as_ref().map(|m| format!("{}:v{:04X}p{:04X}d{:04X}", m.source, m.vendor, m.product, m.device)))),
+        field("Paired", yes_no(properties.paired)),
+        field("Legacy pairing", yes_no(properties.legacy_pairing)),
+        field("Trusted", yes_no(properties.trusted)),
+        field("Blocked", yes_no(properties.blocked)),
+        field("Connected", match (properties.connected, device.connected_at) {
+            (true, Some(time)) => format!("yes, {}", details::ago(time, now)),
+            (true, None) => "yes, since before btui started".to_string(),
+            (false, _) => "no".to_string(),
+        }),
+        field("Disconnected", or_dash(device.disconnected_at.map(|time| details::ago(time, now)))),
+        field("Services resolved", yes_no(properties.services_resolved)),
+        field("Battery", or_dash(properties.battery.map(|b| format!("{}%", b)))),
+        match properties.rssi {
+            Some(rssi) => Line::from(vec![Span::raw(format!("{:<18}", "RSSI")), Span::styled(rssi_label(rssi), signal_color(rssi, theme))]),
+            None => field("RSSI", "-".to_string()),
+        },
+        field("TX power", or_dash(properties.tx_power.map(|tx| format!("{} dBm", tx)))),
+    ];
+    // free-space path loss, only a rough hint of how close the device is
+    if let (Some(rssi), Some(tx_power)) = (properties.rssi, properties.tx_power) {
+        let distance = 10f64.powf((tx_power - rssi) as f64 / 20.0);
+        lines.push(field("Distance", format!("~{:.1} m", distance)));
+    }
+
+    // what btui remembers from earlier runs
+    if let Some(stored) = stored {
+        let ago = |time: Option<u64>| or_dash(time.map(|time| details::ago(store::to_time(time), now)));
+        lines.push(Line::from(""));
+        lines.push(Line::from("History").bold());
+        lines.push(field("First seen", ago(stored.first_seen)));
+        lines.push(field("Last seen", ago(stored.last_seen)));
+        lines.push(field("Last connected", ago(stored.last_connected)));
+        lines.push(field("Last battery", or_dash(stored.battery.map(|b| format!("{}%", b)))));
+        if !stored.tags.is_empty() {
+            lines.push(field("Tags", stored.tags.join(", ")));
+        }
+        if !stored.notes.is_empty() {
+            lines.push(field("Notes", stored.notes));
+        }
+    }
+
+    if !properties.uuids.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from("Services").bold());
+        for uuid in &properties.uuids {
+            lines.push(Line::from(format!("  {} {}", uuid, details::uuid_name(*uuid).unwrap_or_default())));
+        }
+    }
+    if !properties.manufacturer_data.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from("Manufacturer data").bold());
+        for (id, data) in &properties.manufacturer_data {
+            let name = details::manufacturer_name(*id).unwrap_or("Unknown".to_string());
+            lines.push(Line::from(format!("  {} (0x{:04x}): {}", name, id, details::hex(data))));
+        }
+    }
+    if !properties.service_data.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from("Service data").bold());
+        for (uuid, data) in &properties.service_data {
+            let name = details::uuid_name(*uuid).unwrap_or(uuid.to_string());
+            lines.push(Line::from(format!("  {}: {}", name, details::hex(data))));
+        }
+    }
+
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let sparkline_height = if device.rssi_history.is_empty() { 0 } else { 8 };
+    let [text_area, sparkline_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(sparkline_height)]).areas(inner);
+    frame.render_widget(Paragraph::new(lines).wrap(ratatui::widgets::Wrap { trim: false }), text_area);
+
+    // -100 dBm and below sit at the bottom of the chart
+    let samples: Vec<u64> = device.rssi_history.iter().map(|rssi| (rssi + 100).max(0) as u64).collect();
+    let newest = samples.len().saturating_sub(sparkline_area.width as usize);
+    frame.render_widget(
+        Sparkline::default()
+        .block(Block::new().borders(Borders::TOP).title("Signal"))
+        .data(&samples[newest..])
+        .max(100)
+        .style(Style::default().fg(properties.rssi.map_or(theme.text, |rssi| signal_color(rssi, theme)))),
+        sparkline_area,
+    );
+}
+
+fn state_glyph(state: ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Connected => "",
+        ConnectionState::Paired => "",
+        ConnectionState::Discovered => " ",
+    }
+}
+
+/*
+ * Icons from the config file win over the built-in ones,
+ * the most specific name matching the BlueZ icon is used, "headphone" over "phone"
+*/
+fn icon_glyph(device: &DeviceInfo, overrides: &HashMap<String, String>) -> String {
+    let icon = device.properties.icon.clone().unwrap_or("unknown".to_string()).to_lowercase();
+    if let Some((_, glyph)) = overrides.iter().filter(|(key, _)| icon.contains(key.as_str())).max_by_key(|(key, _)| key.len()) {
+        return glyph.clone();
+    }
+    match device.icon {
+        IconKind::Headset => "",
+        IconKind::Headphones => "",
+        IconKind::Speaker => "󰜟",
+        IconKind::Mouse => "",
+        IconKind::Gamepad => "󰊴",
+        IconKind::Laptop => "󰌢",
+        IconKind::Phone => "",
+        IconKind::Card => "󰢮",
+        IconKind::Tv => "",
+        IconKind::Unknown => "",
+    }.to_string()
+}
+
+/*
+ * Battery level with its gauge, only known while connected
+*/
+fn battery_label(device: &DeviceInfo) -> String {
+    if device.state != ConnectionState::Connected {
+        return " ".to_string();
+    }
+    let percentage = device.battery.unwrap_or(0);
+    let gauge = if percentage>75 {
+        "󰁹"
+    } else if percentage>50 {
+        "󰂀"
+    } else if percentage>25 {
+        "󰁾"
+    } else if percentage>1 {
+        "󰁻"
+    } else { " " };
+    format!("{:?}% {}", percentage, gauge)
+}
+
+fn reconnect_label(device: &DeviceInfo) -> String {
+    match &device.reconnect {
+        Some(manager::Reconnect { out_of_range: true, .. }) => "reconnecting once in range".to_string(),
+        Some(manager::Reconnect { retry_at: None, .. }) => "reconnecting...".to_string(),
+        Some(manager::Reconnect { attempt, retry_at: Some(time), .. }) => format!(
+            "reconnecting in {}s (attempt {})",
+            time.saturating_duration_since(tokio::time::Instant::now()).as_secs(),
+            attempt + 1
+        ),
+        None => String::new(),
+    }
+}
+
+fn rssi_label(rssi: i16) -> String {
+    format!("{} dBm", rssi)
+}
+
+/*
+ * Colour bucket of a signal strength, in dBm
+*/
+fn signal_color(rssi: i16, theme: &config::Theme) -> ratatui::style::Color {
+    match rssi {
+        -60.. => theme.signal_good,
+        -75..=-61 => theme.signal_fair,
+        _ => theme.signal_weak,
+    }
+}
+
+/*
+ * Draws the rename input under the list, returns what is left for the list
+*/
+fn render_rename(frame: &mut Frame, rename: Option<&RenameInput>, area: ratatui::layout::Rect, theme: &config::Theme) -> ratatui::layout::Rect {
+    use ratatui::prelude::*;
+
+    let Some(input) = rename else {
+        return area;
+    };
+    let [list_area, input_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(area);
+    let reset = format!("(Tab) Reset to \"{}\"", input.remote_name.as_deref().unwrap_or("the address"));
+    frame.render_widget(
+        Paragraph::new(format!("{}_", input.text))
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title(format!("Rename [{}]", input.address))
+            .title_bottom(Line::from(format!("(Enter) Rename | {} | (Esc) Cancel", reset)).centered())
+            .style(Style::default().fg(theme.popup))),
+        input_area,
+    );
+    list_area
+}
+
+fn render_search(frame: &mut Frame, screen: &Screen, area: ratatui::layout::Rect, theme: &config::Theme) -> ratatui::layout::Rect {
+    use ratatui::prelude::*;
+
+    if !screen.searching {
+        return area;
+    }
+    let [list_area, input_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(area);
+    frame.render_widget(
+        Paragraph::new(format!("/{}_", screen.filter.query))
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title("Search names and addresses")
+            .title_bottom(Line::from("(Up/Down) Move | (Enter) Keep | (Esc) Clear").centered())
+            .style(Style::default().fg(theme.popup))),
+        input_area,
+    );
+    list_area
+}
+
+/*
+ * `text` cut into spans, the characters matching the search underlined
+*/
+fn highlighted<'a>(text: &str, query: &str, theme: &config::Theme) -> Vec<ratatui::text::Span<'a>> {
+    use ratatui::prelude::*;
+
+    let positions = if query.is_empty() { vec![] } else { search::fuzzy_match(query, text).unwrap_or_default() };
+    let mut spans: Vec<(bool, String)> = vec![];
+    for (i, c) in text.chars().enumerate() {
+        let matched = positions.contains(&i);
+        match spans.last_mut() {
+            Some((last, run)) if *last == matched => run.push(c),
+            _ => spans.push((matched, c.to_string())),
+        }
+    }
+    spans
+        .into_iter()
+        .map(|(matched, run)| if matched {
+            Span::styled(run, Style::default().fg(theme.popup).add_modifier(Modifier::UNDERLINED))
+        } else {
+            Span::raw(run)
+        })
+        .collect()
+}
+
+/*
+ * Hints joined by " | " into lines of at most `width`, broken between hints only
+*/
+fn wrap_hints(hints: &[String], width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for hint in hints {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 3 + hint.chars().count() <= width => {
+                line.push_str(" | ");
+                line.push_str(hint);
+            }
+            _ => lines.push(hint.clone()),
+        }
+    }
+    lines
+}
+
+/*
+ * The quick filter keys, the ones turned on shown reversed
+*/
+fn filter_hints<'a>(filter: &ListFilter, config: &Config) -> ratatui::text::Line<'a> {
+    use ratatui::prelude::*;
+
+    let toggles = [
+        (Action::FilterConnected, filter.connected_only),
+        (Action::FilterPaired, filter.paired_only),
+        (Action::FilterTrusted, filter.trusted_only),
+        (Action::HideUnnamed, filter.hide_unnamed),
+        (Action::FilterBlocked, filter.blocked_only),
+    ];
+    let mut spans = vec![];
+    for (i, (action, on)) in toggles.into_iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::raw(" | "));
+        }
+        let hint = Span::raw(config.keys.hint(action));
+        spans.push(if on { hint.reversed() } else { hint });
+    }
+    Line::from(spans)
+}
+
+fn render_picker(frame: &mut Frame, picker: &AdapterPicker, current: &str, theme: &config::Theme) {
+    use ratatui::prelude::*;
+    use ratatui::widgets::Clear;
+
+    let height = picker.names.len() as u16 + 2;
+    let [area] = Layout::vertical([Constraint::Length(height)]).flex(layout::Flex::Center).areas(frame.area());
+    let [area] = Layout::horizontal([Constraint::Length(30)]).flex(layout::Flex::Center).areas(area);
+
+    let items: Vec<ListItem> = picker.names
+        .iter()
+        .map(|name| ListItem::new(if name == current { format!("{} (current)", name) } else { name.clone() }))
+        .collect();
+
+    let mut list_state = ListState::default();
+    list_state.select(Some(picker.selected_index));
+
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(
+        List::new(items)
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title("Adapters")
+            .style(Style::default().fg(theme.popup)))
+        .highlight_style(Style::default().bg(theme.highlight).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> "),
+        area,
+        &mut list_state,
+    );
+}
+
+fn render_scan_options(frame: &mut Frame, options: &ScanOptions, theme: &config::Theme) {
+    use ratatui::prelude::*;
+    use ratatui::widgets::Clear;
+
+    let height = ScanOption::ALL.len() as u16 + 2 + if options.input.is_some() { 3 } else { 0 };
+    let [area] = Layout::vertical([Constraint::Length(height)]).flex(layout::Flex::Center).areas(frame.area());
+    let [area] = Layout::horizontal([Constraint::Percentage(60)]).flex(layout::Flex::Center).areas(area);
+    let [list_area, input_area] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(if options.input.is_some() { 3 } else { 0 }),
+    ]).areas(area);
+
+    let items: Vec<ListItem> = ScanOption::ALL
+        .iter()
+        .map(|option| ListItem::new(format!("{:<22}{}", option.label(), option.value(&options.filter))))
+        .collect();
+    let mut list_state = ListState::default();
+    list_state.select(Some(options.selected_index));
+
+    let mut block = Block::new()
+        .borders(Borders::ALL)
+        .title("Discovery filter")
+        .title_bottom(Line::from(options.keys()).centered())
+        .style(Style::default().fg(theme.popup));
+    if let Some(error) = &options.error {
+        block = block.title_bottom(Line::from(error.as_str()).fg(theme.powered_off).left_aligned());
+    }
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(
+        List::new(items)
+        .block(block)
+        .highlight_style(Style::default().bg(theme.highlight).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> "),
+        list_area,
+        &mut list_state,
+    );
+
+    if let Some(input) = &options.input {
+        frame.render_widget(
+            Paragraph::new(format!("{}_", input.text))
+            .block(Block::new()
+                .borders(Borders::ALL)
+                .title(input.option.label())
+                .style(Style::default().fg(theme.popup))),
+            input_area,
+        );
+    }
+}
+
+fn render_gatt(frame: &mut Frame, explorer: &GattExplorer, area: ratatui::layout::Rect, config: &Config) {
+    use ratatui::prelude::*;
+
+    let theme = &config.theme;
+    let [tree_area, log_area] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(area);
+    let [tree_area, input_area] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(if explorer.input.is_some() { 3 } else { 0 }),
+    ]).areas(tree_area);
+
+    let items: Vec<ListItem> = explorer.rows
+        .iter()
+        .map(|row| {
+            let notifying = row.handle.is_some_and(|handle| explorer.is_subscribed(&handle));
+            let line = format!("{}{} {} [{}]{}",
+                "  ".repeat(row.depth),
+                row.name.as_deref().unwrap_or("Unknown"),
+                row.uuid,
+                row.flags.join(", "),
+                if notifying { " (notifying)" } else { "" });
+            let item = ListItem::new(line);
+            if row.depth == 0 { item.add_modifier(Modifier::BOLD) } else { item }
+        })
+        .collect();
+
+    let mut list_state = ListState::default();
+    list_state.select(Some(explorer.selected_index));
+
+    frame.render_stateful_widget(
+        List::new(items)
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title(format!("GATT on {} [{}]", explorer.device_name, explorer.address))
+            .title_bottom(Line::from(explorer.keys(&config.keys)).centered())
+            .style(Style::default().fg(theme.text)))
+        .highlight_style(Style::default().bg(theme.highlight).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> "),
+        tree_area,
+        &mut list_state,
+    );
+
+    if let Some(input) = &explorer.input {
+        frame.render_widget(
+            Paragraph::new(format!("{}_", input.text))
+            .block(Block::new()
+                .borders(Borders::ALL)
+                .title(if input.hex { "Write (hex)" } else { "Write (UTF-8)" })
+                .style(Style::default().fg(theme.popup))),
+            input_area,
+        );
+    }
+
+    // the newest values stay at the bottom unless the log is scrolled back
+    let log = explorer.log.lock().unwrap();
+    let height = log_area.height.saturating_sub(2) as usize;
+    let end = log.len().saturating_sub(explorer.log_scroll);
+    let lines: Vec<Line> = log[end.saturating_sub(height)..end].iter().map(|line| Line::from(line.as_str())).collect();
+
+    frame.render_widget(
+        Paragraph::new(lines)
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title("Values")
+            .style(Style::default().fg(theme.text))),
+        log_area,
+    );
+}
+
+fn render_settings(frame: &mut Frame, settings: &AdapterSettings, adapter_name: &str, area: ratatui::layout::Rect, theme: &config::Theme) {
+    use ratatui::prelude::*;
+
+    let properties = settings.properties.lock().unwrap().clone();
+    let [info_area, settings_area] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(area);
+    let [settings_area, input_area] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(if settings.input.is_some() { 3 } else { 0 }),
+    ]).areas(settings_area);
+
+    let yes_no = |value: bool| if value { "yes" } else { "no" }.to_string();
+    let field = |label: &str, value: String| Line::from(format!("{:<14}{}", label, value));
+    let or_dash = |value: Option<String>| value.unwrap_or("-".to_string());
+
+    let mut lines = vec![
+        field("Address", or_dash(properties.address.map(|a| a.to_string()))),
+        field("Address type", or_dash(properties.address_type.map(|t| t.to_string()))),
+        field("Name", or_dash(properties.system_name.clone())),
+        field("Alias", or_dash(properties.alias.clone())),
+        field("Class", or_dash(properties.class.map(details::describe_class))),
+        field("Modalias", or_dash(properties.modalias.as_ref().map(|m| format!("{}:v{:04X}p{:04X}d{:04X}", m.source, m.vendor, m.product, m.device)))),
+        field("Roles", if properties.roles.is_empty() { "-".to_string() } else { properties.roles.join(", ") }),
+        field("Powered", yes_no(properties.powered)),
+        field("Discovering", yes_no(properties.discovering)),
+    ];
+    if !properties.uuids.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from("Services").bold());
+        for uuid in &properties.uuids {
+            lines.push(Line::from(format!("  {} {}", uuid, details::uuid_name(*uuid).unwrap_or_default())));
+        }
+    }
+    frame.render_widget(
+        Paragraph::new(lines)
+        .wrap(ratatui::widgets::Wrap { trim: false })
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title(format!("Adapter {}", adapter_name))
+            .style(Style::default().fg(theme.text))),
+        info_area,
+    );
+
+    let items: Vec<ListItem> = Setting::ALL
+        .iter()
+        .map(|setting| ListItem::new(format!("{:<22}{}", setting.label(), setting.value(&properties))))
+        .collect();
+    let mut list_state = ListState::default();
+    list_state.select(Some(settings.selected_index));
+
+    let error = settings.error.lock().unwrap().clone();
+    let mut block = Block::new()
+        .borders(Borders::ALL)
+        .title("Settings")
+        .title_bottom(Line::from(settings.keys()).centered())
+        .style(Style::default().fg(theme.text));
+    if let Some(error) = error {
+        block = block.title_bottom(Line::from(error).fg(theme.powered_off).left_aligned());
+    }
+    frame.render_stateful_widget(
+        List::new(items)
+        .block(block)
+        .highlight_style(Style::default().bg(theme.highlight).add_modifier(Modifier::BOLD))
+        .highlight_symbol(">> "),
+        settings_area,
+        &mut list_state,
+    );
+
+    if let Some(input) = &settings.input {
+        frame.render_widget(
+            Paragraph::new(format!("{}_", input.text))
+            .block(Block::new()
+                .borders(Borders::ALL)
+                .title(input.setting.label())
+                .style(Style::default().fg(theme.popup))),
+            input_area,
+        );
+    }
+}
+
+fn render_prompt(frame: &mut Frame, prompt: &AgentPrompt, theme: &config::Theme) {
+    use ratatui::prelude::*;
+    use ratatui::widgets::{Clear, Wrap};
+
+    let [area] = Layout::vertical([Constraint::Length(11)]).flex(layout::Flex::Center).areas(frame.area());
+    let [area] = Layout::horizontal([Constraint::Percentage(50)]).flex(layout::Flex::Center).areas(area);
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(prompt.message())
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false })
+        .block(Block::new()
+            .borders(Borders::ALL)
+            .title("Pairing")
+            .title_bottom(Line::from(prompt.keys()).centered())
+            .style(Style::default().fg(theme.popup))),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btui::backend::DeviceProperties;
+    use ratatui::{Terminal, backend::TestBackend, buffer::Buffer, style::{Color, Modifier}};
+
+    fn device(address: &str, name: &str, properties: DeviceProperties) -> DeviceRow {
+        let properties = DeviceProperties { name: Some(name.to_string()), ..properties };
+        DeviceRow::new(DeviceInfo::from_properties(address.parse().unwrap(), properties), false, None)
+    }
+
+    fn headphones() -> DeviceRow {
+        device("EE:00:00:00:00:01", "Headphones", DeviceProperties {
+            icon: Some("audio-headphones".to_string()), paired: true, connected: true, trusted: true, battery: Some(80), ..Default::default()
+        })
+    }
+
+    fn speaker() -> DeviceRow {
+        device("EE:00:00:00:00:02", "Speaker", DeviceProperties { icon: Some("audio-card".to_string()), paired: true, ..Default::default() })
+    }
+
+    fn phone() -> DeviceRow {
+        device("EE:00:00:00:00:03", "Phone", DeviceProperties { icon: Some("phone".to_string()), rssi: Some(-58), ..Default::default() })
+    }
+
+    fn screen(rows: Vec<DeviceRow>) -> Screen<'static> {
+        Screen { adapter_name: "hci0", powered: true, total: rows.len(), rows, ..Default::default() }
+    }
+
+    fn draw(screen: &Screen, width: u16, height: u16) -> Buffer {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| render(frame, screen, &Config::default())).unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    /*
+     * The text of each line, trailing blanks cut so the expectations stay readable
+    */
+    fn lines(buffer: &Buffer) -> Vec<String> {
+        let area = buffer.area;
+        (0..area.height)
+            .map(|y| (0..area.width).map(|x| buffer[(x, y)].symbol()).collect::<String>().trim_end().to_string())
+            .collect()
+    }
+
+    fn assert_lines(buffer: &Buffer, expected: &[&str]) {
+        assert_eq!(lines(buffer), expected);
+    }
+
+    #[test]
+    fn groups_each_connection_state() {
+        let buffer = draw(&screen(vec![headphones(), speaker(), phone()]), 80, 13);
+        assert_lines(&buffer, &[
+            "                                    Commands",
+            "    (O)n/off | (S)can | (C)onnect | (P)air | (T)rust | (B)lock | (R)econnect",
+            "    [n] Rename | (F)orget | [x] Cancel | (G)att | (I)nfo | (L)og | [/] Search",
+            "        [v] Sort | (A)dapter | [e] Settings | (D)iscovery filter | (Q)uit",
+            "┌Devices on hci0─────────────────────────────────────────────────sorted by name┐",
+            "│   Connected (1)                                                              │",
+            "│>> T   |                  [EE:00:00:00:00:01] Headphones 80% 󰁹              │",
+            "│   Paired (1)                                                                 │",
+            "│       |              󰢮    [EE:00:00:00:00:02] Speaker                       │",
+            "│   Discovered (1)                                                             │",
+            "│       |    -58 dBm        [EE:00:00:00:00:03] Phone                         │",
+            "└──────[1] Connected | [2] Paired | [3] Trusted | [4] Named | [5] Blocked──────┘",
+            "",
+        ]);
+        // connected rows stand out, the others keep the text colour
+        assert_eq!(buffer[(10, 6)].fg, Color::LightGreen);
+        assert!(buffer[(10, 6)].modifier.contains(Modifier::BOLD));
+        assert_eq!(buffer[(10, 8)].fg, Color::White);
+        assert_eq!(buffer[(10, 10)].fg, Color::White);
+        assert!(!buffer[(10, 10)].modifier.contains(Modifier::BOLD));
+    }
+
+    #[test]
+    fn detail_times_count_back_from_the_frame() {
+        const NOW: u64 = 1_718_000_000;
+        let mut headphones = headphones();
+        headphones.device.connected_at = Some(store::to_time(NOW - 90));
+        let mut screen = screen(vec![headphones]);
+        screen.detail = true;
+        screen.now = NOW;
+        screen.stored = Some(StoredDevice {
+            first_seen: Some(NOW - 3 * 86400),
+            last_seen: Some(NOW - 42),
+            last_connected: Some(NOW - 7200),
+            ..Default::default()
+        });
+
+        let lines = lines(&draw(&screen, 120, 40));
+        for expected in ["Connected         yes, 1m ago", "First seen        3d ago", "Last seen         42s ago", "Last connected    2h ago"] {
+            assert!(lines.iter().any(|line| line.contains(expected)), "{} missing from\n{}", expected, lines.join("\n"));
+        }
+    }
+
+    #[test]
+    fn blocked_devices_are_flagged() {
+        let blocked = device("EE:00:00:00:00:05", "Neighbour", DeviceProperties { paired: true, blocked: true, ..Default::default() });
+        let buffer = draw(&screen(vec![speaker(), blocked]), 80, 10);
+        assert_eq!(lines(&buffer)[6], "│>>     | \u{f00c}             \u{f08ae}    [EE:00:00:00:00:02] Speaker                       │");
+        assert_eq!(lines(&buffer)[7], "│     B | \u{f00c}             \u{eb32}    [EE:00:00:00:00:05] Neighbour                     │");
+        assert_eq!(buffer[(6, 7)].fg, Config::default().theme.error);
+    }
+
+    #[test]
+    fn powered_off_adapter_greys_the_list() {
+        let mut screen = screen(vec![headphones(), speaker()]);
+        screen.powered = false;
+        let buffer = draw(&screen, 80, 10);
+        assert_lines(&buffer, &[
+            "                                    Commands",
+            "    (O)n/off | (S)can | (C)onnect | (P)air | (T)rust | (B)lock | (R)econnect",
+            "    [n] Rename | (F)orget | [x] Cancel | (G)att | (I)nfo | (L)og | [/] Search",
+            "        [v] Sort | (A)dapter | [e] Settings | (D)iscovery filter | (Q)uit",
+            "┌Devices on hci0─────────────────────────────────────────────────sorted by name┐",
+            "│   Connected (1)                                                              │",
+            "│>> T   |                  [EE:00:00:00:00:01] Headphones 80% 󰁹              │",
+            "│   Paired (1)                                                                 │",
+            "└──────[1] Connected | [2] Paired | [3] Trusted | [4] Named | [5] Blocked──────┘",
+            "",
+        ]);
+        let powered_off = Config::default().theme.powered_off;
+        assert_eq!(buffer[(0, 4)].fg, powered_off);
+        assert_eq!(buffer[(10, 6)].fg, powered_off);
+    }
+
+    #[test]
+    fn scanning_colours_the_border() {
+        let mut screen = screen(vec![phone()]);
+        screen.scanning = true;
+        let buffer = draw(&screen, 80, 8);
+        assert_lines(&buffer, &[
+            "                                    Commands",
+            "    (O)n/off | (S)can | (C)onnect | (P)air | (T)rust | (B)lock | (R)econnect",
+            "    [n] Rename | (F)orget | [x] Cancel | (G)att | (I)nfo | (L)og | [/] Search",
+            "        [v] Sort | (A)dapter | [e] Settings | (D)iscovery filter | (Q)uit",
+            "┌Devices on hci0─────────────────────────────────────────────────sorted by name┐",
+            "│>>     |    -58 dBm        [EE:00:00:00:00:03] Phone                         │",
+            "└──────[1] Connected | [2] Paired | [3] Trusted | [4] Named | [5] Blocked──────┘",
+            "",
+        ]);
+        assert_eq!(buffer[(0, 4)].fg, Config::default().theme.scanning);
+        assert_eq!(buffer[(79, 6)].fg, Config::default().theme.scanning);
+    }
+
+    #[test]
+    fn header_shows_rfkill_blocks() {
+        let mut screen = screen(vec![]);
+        screen.powered = false;
+        screen.blocked = rfkill::Block::Soft;
+        let buffer = draw(&screen, 80, 5);
+        assert_eq!(lines(&buffer)[0], "                                    Commands             Soft-blocked, (U)nblock");
+        assert_eq!(buffer[(79, 0)].fg, Config::default().theme.warning);
+
+        screen.blocked = rfkill::Block::Hard;
+        let buffer = draw(&screen, 80, 5);
+        assert_eq!(lines(&buffer)[0], "                                    Commands            Hard-blocked by a switch");
+        assert_eq!(buffer[(79, 0)].fg, Config::default().theme.error);
+    }
+
+    #[test]
+    fn empty_list_keeps_its_frame() {
+        assert_lines(&draw(&screen(vec![]), 60, 10), &[
+            "                          Commands",
+            " (O)n/off | (S)can | (C)onnect | (P)air | (T)rust | (B)lock",
+            "  (R)econnect | [n] Rename | (F)orget | [x] Cancel | (G)att",
+            "     (I)nfo | (L)og | [/] Search | [v] Sort | (A)dapter",
+            "         [e] Settings | (D)iscovery filter | (Q)uit",
+            "┌Devices on hci0─────────────────────────────sorted by name┐",
+            "│                                                          │",
+            "│                                                          │",
+            "└Connected | [2] Paired | [3] Trusted | [4] Named | [5] Blo┘",
+            "",
+        ]);
+    }
+
+    #[test]
+    fn long_names_stop_at_the_border() {
+        let long = device("EE:00:00:00:00:04", &"Very Long Name ".repeat(6), DeviceProperties::default());
+        assert_lines(&draw(&screen(vec![long]), 70, 9), &[
+            "                               Commands",
+            "      (O)n/off | (S)can | (C)onnect | (P)air | (T)rust | (B)lock",
+            "  (R)econnect | [n] Rename | (F)orget | [x] Cancel | (G)att | (I)nfo",
+            "       (L)og | [/] Search | [v] Sort | (A)dapter | [e] Settings",
+            "                      (D)iscovery filter | (Q)uit",
+            "┌Devices on hci0───────────────────────────────────────sorted by name┐",
+            "│>>     |                   [EE:00:00:00:00:04] Very Long Name Very │",
+            "└─[1] Connected | [2] Paired | [3] Trusted | [4] Named | [5] Blocked─┘",
+            "",
+        ]);
+    }
+
+    #[test]
+    fn narrow_terminal_cuts_rows_and_titles() {
+        assert_lines(&draw(&screen(vec![headphones(), phone()]), 36, 15), &[
+            "              Commands",
+            "    (O)n/off | (S)can | (C)onnect",
+            "     (P)air | (T)rust | (B)lock",
+            " (R)econnect | [n] Rename | (F)orget",
+            "[x] Cancel | (G)att | (I)nfo | (L)og",
+            "  [/] Search | [v] Sort | (A)dapter",
+            "  [e] Settings | (D)iscovery filter",
+            "               (Q)uit",
+            "┌Devices on hci0─────sorted by name┐",
+            "│   Connected (1)                  │",
+            "│>> T   |                  [EE:00│",
+            "│   Discovered (1)                 │",
+            "│       |    -58 dBm        [EE:00│",
+            "└[2] Paired | [3] Trusted | [4] Nam┘",
+            "",
+        ]);
+    }
+
+    #[test]
+    fn log_pane_says_its_times_are_utc() {
+        let pane = LogPane { level: Level::Info, scroll: 0 };
+        let mut screen = screen(vec![]);
+        screen.log_pane = Some(&pane);
+        screen.log = vec![Entry {
+            time: std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_718_028_239),
+            level: Level::Info,
+            text: "Scan finished".to_string(),
+        }];
+        let lines = lines(&draw(&screen, 80, 16));
+        assert_eq!(lines[11], "┌Activity, UTC (INFO and above)────────────────────────────────────────────────┐");
+        assert_eq!(lines[12], "│14:03:59 INFO  Scan finished                                                  │");
+    }
+
+    #[test]
+    fn short_terminal_keeps_the_header_above_the_list() {
+        let mut screen = screen(vec![headphones(), speaker(), phone()]);
+        let status = btui::status::StatusBar::default();
+        status.info("Paired with Headphones");
+        screen.status = status.current();
+        assert_lines(&draw(&screen, 80, 6), &[
+            "                                    Commands",
+            "    (O)n/off | (S)can | (C)onnect | (P)air | (T)rust | (B)lock | (R)econnect",
+            "┌Devices on hci0─────────────────────────────────────────────────sorted by name┐",
+            "│>> T   |                  [EE:00:00:00:00:01] Headphones 80% 󰁹              │",
+            "└──────[1] Connected | [2] Paired | [3] Trusted | [4] Named | [5] Blocked──────┘",
+            "Paired with Headphones",
+        ]);
+    }
+}
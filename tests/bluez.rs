@@ -0,0 +1,266 @@
+mod fake_bluez;
+
+use btui::activity::{ActivityLog, Level};
+use btui::backend::Backend;
+use btui::error::Error;
+use btui::manager::{self, ConnectionState, DeviceInfo};
+use bluer::Address;
+use fake_bluez::{Failure, FakeBluez};
+use std::{
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    time::Duration
+};
+use tokio::time::{Instant, sleep};
+
+const HEADPHONES: &str = include_str!("scenarios/headphones.toml");
+const PAIRING_REJECTED: &str = include_str!("scenarios/pairing_rejected.toml");
+
+type DeviceList = Arc<Mutex<Vec<DeviceInfo>>>;
+
+fn address(text: &str) -> Address {
+    text.parse().unwrap()
+}
+
+fn device(list: &DeviceList, address: Address) -> Option<DeviceInfo> {
+    list.lock().unwrap().iter().find(|d| d.address == address).cloned()
+}
+
+/*
+ * Waits for the watchers to catch up with the fake, fails after two seconds
+*/
+async fn eventually(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/*
+ * Waits until a watcher logs the changes of `address`, so nothing sent after this is missed.
+ * Every change before went through as well, the watcher handles them in order.
+*/
+async fn device_watched(bluez: &FakeBluez, log: &ActivityLog, address: &str, rssi: i16) {
+    let seen = format!("Rssi({})", rssi);
+    eventually("the device watcher", || {
+        bluez.set_device_property(address, "RSSI", rssi);
+        log.entries(Level::Debug).iter().any(|entry| entry.text.ends_with(&seen))
+    }).await;
+}
+
+async fn adapter_watched(bluez: &FakeBluez, log: &ActivityLog) {
+    eventually("the adapter watcher", || {
+        bluez.set_adapter_property("Alias", "probe".to_string());
+        log.entries(Level::Debug).iter().any(|entry| entry.text.contains("Alias(\"probe\")"))
+    }).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn initiate_lists_what_bluez_has_paired() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let headphones = address("AA:BB:CC:DD:EE:01");
+    let store = bluez.store();
+    // the headphones were paired outside btui, BlueZ forgot this one meanwhile
+    store.remember(address("AA:BB:CC:DD:EE:09"));
+
+    let backend = bluez.backend().await;
+    let mut paired = vec![];
+    let store = manager::initiate(&backend, &mut paired).await.unwrap();
+    assert_eq!(paired, vec![headphones]);
+    assert_eq!(store.addresses(), vec![headphones]);
+
+    let list = DeviceList::default();
+    manager::load_device_list(&backend, &paired, list.clone(), &store, &ActivityLog::default());
+    eventually("the headphones' properties", || device(&list, headphones).is_some_and(|d| d.battery.is_some())).await;
+    let headphones = device(&list, headphones).unwrap();
+    assert_eq!(headphones.name.as_deref(), Some("WH-1000XM4"));
+    assert_eq!(headphones.state, ConnectionState::Connected);
+    assert_eq!(headphones.battery, Some(80));
+    assert!(headphones.trusted);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scan_lists_the_devices_in_range() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let backend = bluez.backend().await;
+    let mut paired = vec![];
+    let store = manager::initiate(&backend, &mut paired).await.unwrap();
+
+    let list = DeviceList::default();
+    manager::scan_devices(&backend, &mut paired, &store, list.clone(), Duration::from_millis(300), &ActivityLog::default()).await.unwrap();
+
+    let speaker = device(&list, address("AA:BB:CC:DD:EE:02")).expect("the speaker was not found");
+    assert_eq!(speaker.state, ConnectionState::Discovered);
+    assert_eq!(speaker.rssi, Some(-58));
+    // paired devices are listed and remembered whatever the scan finds
+    assert!(device(&list, address("AA:BB:CC:DD:EE:01")).is_some());
+    assert_eq!(store.addresses(), vec![address("AA:BB:CC:DD:EE:01")]);
+    assert!(bluez.calls().contains(&"StartDiscovery btuitest0".to_string()));
+    eventually("the discovery to stop", || bluez.calls().contains(&"StopDiscovery btuitest0".to_string())).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pair_connect_and_disconnect_update_the_list() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let phone = address("AA:BB:CC:DD:EE:03");
+    let backend = bluez.backend().await;
+    let mut paired = vec![];
+    let store = manager::initiate(&backend, &mut paired).await.unwrap();
+    let list = DeviceList::new(Mutex::new(vec![DeviceInfo::from_properties(phone, backend.device_properties(phone).await.unwrap())]));
+    manager::watch_device(&backend, phone, list.clone(), &store, &ActivityLog::default());
+    eventually("the phone's properties", || device(&list, phone).is_some_and(|d| d.name.is_some())).await;
+
+    manager::pair_device(&backend, phone).await.unwrap();
+    eventually("the phone to be paired", || device(&list, phone).is_some_and(|d| d.state == ConnectionState::Paired)).await;
+    assert_eq!(bluez.device_property::<bool>("AA:BB:CC:DD:EE:03", "Paired"), Some(true));
+    assert!(store.get(phone).is_some(), "a paired device is remembered");
+
+    manager::connect_device(&backend, phone).await.unwrap();
+    eventually("the phone to connect", || device(&list, phone).is_some_and(|d| d.state == ConnectionState::Connected)).await;
+
+    manager::disconnect_device(&backend, phone).await.unwrap();
+    eventually("the phone to disconnect", || device(&list, phone).is_some_and(|d| d.state == ConnectionState::Paired)).await;
+    assert_eq!(bluez.calls().iter().filter(|call| call.ends_with("AA:BB:CC:DD:EE:03")).cloned().collect::<Vec<_>>(), ["Pair AA:BB:CC:DD:EE:03", "Connect AA:BB:CC:DD:EE:03", "Disconnect AA:BB:CC:DD:EE:03"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_device_dropping_the_connection_shows_up() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let headphones = address("AA:BB:CC:DD:EE:01");
+    let backend = bluez.backend().await;
+    let store = bluez.store();
+    let list = DeviceList::new(Mutex::new(vec![DeviceInfo::from_properties(headphones, backend.device_properties(headphones).await.unwrap())]));
+    let log = ActivityLog::default();
+    manager::watch_device(&backend, headphones, list.clone(), &store, &log);
+    device_watched(&bluez, &log, "AA:BB:CC:DD:EE:01", -40).await;
+
+    bluez.set_device_property("AA:BB:CC:DD:EE:01", "Connected", false);
+    eventually("the headphones to disconnect", || device(&list, headphones).is_some_and(|d| d.state == ConnectionState::Paired)).await;
+    assert!(device(&list, headphones).unwrap().disconnected_at.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_blocked_device_is_not_reconnected() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let headphones = address("AA:BB:CC:DD:EE:01");
+    let backend = bluez.backend().await;
+    let store = bluez.store();
+    store.remember(headphones);
+    store.update(headphones, |d| d.auto_reconnect = true);
+    let list = DeviceList::new(Mutex::new(vec![DeviceInfo::from_properties(headphones, backend.device_properties(headphones).await.unwrap())]));
+    let log = ActivityLog::default();
+    manager::watch_device(&backend, headphones, list.clone(), &store, &log);
+    device_watched(&bluez, &log, "AA:BB:CC:DD:EE:01", -40).await;
+
+    manager::set_blocked(&backend, headphones, true).await.unwrap();
+    eventually("the headphones to be blocked", || device(&list, headphones).is_some_and(|d| d.blocked)).await;
+    bluez.set_device_property("AA:BB:CC:DD:EE:01", "Connected", false);
+    eventually("the headphones to disconnect", || device(&list, headphones).is_some_and(|d| d.state == ConnectionState::Paired)).await;
+    // the disconnection is handled by now, a reconnection would have been registered with it
+    device_watched(&bluez, &log, "AA:BB:CC:DD:EE:01", -41).await;
+
+    assert!(device(&list, headphones).unwrap().reconnect.is_none());
+    assert_eq!(bluez.calls().iter().filter(|call| call.ends_with("AA:BB:CC:DD:EE:01")).cloned().collect::<Vec<_>>(), ["SetBlocked AA:BB:CC:DD:EE:01"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_rejected_pairing_is_an_authentication_error() {
+    let bluez = FakeBluez::start(PAIRING_REJECTED).await;
+    let keyboard = address("AA:BB:CC:DD:EE:04");
+    let backend = bluez.backend().await;
+
+    let err = manager::pair_device(&backend, keyboard).await.unwrap_err();
+    assert!(matches!(err, Error::Authentication(_)), "{:?}", err);
+    assert_eq!(bluez.device_property::<bool>("AA:BB:CC:DD:EE:04", "Paired"), Some(false));
+
+    // only the first attempt was scripted to fail
+    manager::pair_device(&backend, keyboard).await.unwrap();
+    assert_eq!(bluez.device_property::<bool>("AA:BB:CC:DD:EE:04", "Paired"), Some(true));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn errors_keep_their_kind() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let backend = bluez.backend().await;
+
+    let err = manager::connect_device(&backend, address("AA:BB:CC:DD:EE:01")).await.unwrap_err();
+    assert!(matches!(err, Error::Busy(_)), "{:?}", err);
+    bluez.fail_next(Failure { method: "Connect".to_string(), device: None, error: "Failed".to_string() });
+    let err = manager::connect_device(&backend, address("AA:BB:CC:DD:EE:03")).await;
+    assert!(matches!(err, Err(Error::Failed(_))), "{:?}", err);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forget_removes_the_device() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let headphones = address("AA:BB:CC:DD:EE:01");
+    let backend = bluez.backend().await;
+    let list = DeviceList::new(Mutex::new(vec![DeviceInfo::from_properties(headphones, backend.device_properties(headphones).await.unwrap())]));
+    manager::watch_adapter(&backend, Arc::new(AtomicBool::new(true)), list.clone(), &ActivityLog::default());
+
+    manager::forget_device(&backend, headphones, list.clone()).await.unwrap();
+    assert!(!bluez.has_device("AA:BB:CC:DD:EE:01"));
+    assert!(device(&list, headphones).is_none());
+    assert!(backend.device_properties(headphones).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn power_changes_reach_the_adapter_watcher() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let backend = bluez.backend().await;
+    let powered = Arc::new(AtomicBool::new(true));
+    let log = ActivityLog::default();
+    manager::watch_adapter(&backend, powered.clone(), DeviceList::default(), &log);
+    adapter_watched(&bluez, &log).await;
+
+    manager::power_adapter(&backend).await.unwrap();
+    eventually("the adapter to power off", || !powered.load(Ordering::Relaxed)).await;
+    assert!(bluez.calls().contains(&"SetPowered btuitest0".to_string()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn the_agent_and_cancel_pairing_reach_bluez() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+    let session = bluer::Session::new().await.unwrap();
+    let (requests, _) = tokio::sync::mpsc::unbounded_channel();
+    let agent = session.register_agent(btui::agent::build_agent(requests)).await.unwrap();
+    assert_eq!(bluez.agents().len(), 1);
+    drop(agent);
+    eventually("the agent to be unregistered", || bluez.agents().is_empty()).await;
+
+    let backend = bluez.backend().await;
+    backend.cancel_pairing(address("AA:BB:CC:DD:EE:03")).await.unwrap();
+    assert!(bluez.calls().contains(&"CancelPairing AA:BB:CC:DD:EE:03".to_string()));
+}
+
+/*
+ * Runs the btui binary with `args` against the fake, returns its exit code and what it printed
+*/
+async fn btui(bluez: &FakeBluez, args: &[&str]) -> (i32, String, String) {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_btui"));
+    // FakeBluez::start pointed the system bus and the cache at the fake, the child inherits them
+    command.arg("--adapter").arg(bluez.adapter_name()).args(args)
+        .env("XDG_CONFIG_HOME", std::env::temp_dir().join(format!("btui-config-{}", std::process::id())));
+    let output = tokio::task::spawn_blocking(move || command.output().expect("cannot run btui")).await.unwrap();
+    (output.status.code().unwrap_or(-1), String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn subcommands_run_against_bluez() {
+    let bluez = FakeBluez::start(HEADPHONES).await;
+
+    let (code, listed, _) = btui(&bluez, &["list"]).await;
+    assert_eq!(code, 0);
+    assert!(listed.contains("AA:BB:CC:DD:EE:01  WH-1000XM4"), "{}", listed);
+
+    let (code, _, _) = btui(&bluez, &["disconnect", "wh-1000xm4"]).await;
+    assert_eq!(code, 0);
+    assert!(bluez.calls().contains(&"Disconnect AA:BB:CC:DD:EE:01".to_string()));
+
+    let (code, _, error) = btui(&bluez, &["connect", "Keyboard"]).await;
+    assert_eq!((code, error.trim()), (3, "btui: no device named \"Keyboard\""));
+
+    bluez.set_adapter_property("Powered", false);
+    let (code, _, error) = btui(&bluez, &["list"]).await;
+    assert_eq!((code, error.trim()), (4, "btui: adapter btuitest0 is powered off"));
+}
@@ -0,0 +1,593 @@
+use btui::backend::BluerBackend;
+use btui::store::DeviceStore;
+use dbus::{
+    Message, Path,
+    arg::{PropMap, RefArg, Variant},
+    blocking::{SyncConnection, stdintf::org_freedesktop_dbus::{ObjectManagerInterfacesAdded, ObjectManagerInterfacesRemoved, PropertiesPropertiesChanged}},
+    channel::{Channel, MatchingReceiver, Sender},
+    message::{MatchRule, SignalArgs},
+    strings::ErrorName
+};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::CString,
+    fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex, Once, atomic::{AtomicBool, Ordering}},
+    thread,
+    time::Duration
+};
+use tokio::sync::MutexGuard;
+
+const ADAPTER: &str = "org.bluez.Adapter1";
+const DEVICE: &str = "org.bluez.Device1";
+const BATTERY: &str = "org.bluez.Battery1";
+const AGENT_MANAGER: &str = "org.bluez.AgentManager1";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+// one org.bluez per bus, the tests using it take turns
+static BUS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+static ENVIRONMENT: Once = Once::new();
+
+/*
+ * What the fake BlueZ starts with, read from a TOML file, e.g.
+ *
+ *   [adapter]
+ *   name = "btuitest0"
+ *   address = "00:1A:7D:DA:71:13"
+ *
+ *   [[device]]
+ *   address = "AA:BB:CC:DD:EE:01"
+ *   name = "Headphones"
+ *   paired = true
+ *   battery = 80
+ *
+ *   [[failure]]
+ *   method = "Pair"
+ *   device = "AA:BB:CC:DD:EE:01"
+ *   error = "AuthenticationFailed"
+ *
+ * The adapter name doubles as the store directory, below a cache directory of the tests' own.
+*/
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario
+{
+    adapter: ScenarioAdapter,
+    #[serde(default, rename = "device")]
+    devices: Vec<ScenarioDevice>,
+    #[serde(default, rename = "failure")]
+    failures: Vec<Failure>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioAdapter
+{
+    name: String,
+    address: String,
+    #[serde(default = "yes")]
+    powered: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioDevice
+{
+    address: String,
+    name: Option<String>,
+    icon: Option<String>,
+    class: Option<u32>,
+    #[serde(default)]
+    uuids: Vec<String>,
+    #[serde(default)]
+    paired: bool,
+    #[serde(default)]
+    trusted: bool,
+    #[serde(default)]
+    connected: bool,
+    #[serde(default)]
+    blocked: bool,
+    battery: Option<u8>,
+    rssi: Option<i16>,
+    /// Unknown to BlueZ until discovery starts.
+    #[serde(default)]
+    in_range: bool,
+}
+
+/*
+ * The next call to `method` on the device, or on any device, answers org.bluez.Error.<error>
+*/
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Failure
+{
+    pub method: String,
+    pub device: Option<String>,
+    pub error: String,
+}
+
+fn yes() -> bool
+{
+    true
+}
+
+type Interfaces = HashMap<String, PropMap>;
+
+#[derive(Default)]
+struct BusState
+{
+    adapter: String,
+    objects: BTreeMap<String, Interfaces>,
+    in_range: Vec<(String, Interfaces)>,
+    failures: Vec<Failure>,
+    agents: Vec<String>,
+    calls: Vec<String>,
+}
+
+/*
+ * A private dbus-daemon serving a minimal org.bluez: Adapter1, Device1, Battery1 and AgentManager1.
+ * BluerBackend reaches it as the system bus, no Bluetooth hardware or bluetoothd involved.
+ * Everything is torn down when dropped.
+*/
+pub struct FakeBluez
+{
+    daemon: Child,
+    // socket and configuration of the daemon
+    dir: PathBuf,
+    connection: Arc<SyncConnection>,
+    state: Arc<Mutex<BusState>>,
+    stop: Arc<AtomicBool>,
+    service: Option<thread::JoinHandle<()>>,
+    _turn: MutexGuard<'static, ()>,
+}
+
+impl FakeBluez {
+    /*
+     * Serves `scenario`, fails the test when there is no dbus-daemon to run
+    */
+    pub async fn start(scenario: &str) -> Self {
+        let scenario: Scenario = toml::from_str(scenario).expect("invalid scenario");
+        let turn = BUS.lock().await;
+
+        let dir = bus_dir();
+        let socket = dir.join("system_bus_socket");
+        let address = format!("unix:path={}", socket.display());
+        isolate();
+        let _ = fs::remove_dir_all(store_dir(&scenario.adapter.name));
+        let _ = fs::create_dir_all(&dir);
+        let _ = fs::remove_file(&socket);
+        let config = dir.join("bus.conf");
+        fs::write(&config, format!(
+            "<busconfig>\
+               <type>system</type>\
+               <listen>unix:path={}</listen>\
+               <auth>EXTERNAL</auth>\
+               <policy context=\"default\"><allow send_destination=\"*\"/><allow own=\"*\"/><allow eavesdrop=\"true\"/></policy>\
+             </busconfig>",
+            socket.display(),
+        )).expect("cannot write the bus configuration");
+
+        let mut daemon = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--nopidfile", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap_or_else(|err| panic!("the BlueZ tests need dbus-daemon, install dbus to run them: {}", err));
+        // the address is printed once it listens
+        let mut ready = String::new();
+        BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut ready).expect("dbus-daemon did not start");
+
+        let mut channel = Channel::open_private(&address).expect("cannot connect to the private bus");
+        channel.register().expect("cannot register on the private bus");
+        let connection = Arc::new(SyncConnection::from(channel));
+        connection.request_name("org.bluez", false, true, true).expect("org.bluez is taken");
+
+        let state = Arc::new(Mutex::new(BusState::new(scenario)));
+        {
+            let state = state.clone();
+            connection.start_receive(MatchRule::new_method_call(), Box::new(move |msg, connection| {
+                let (reply, signals) = state.lock().unwrap().handle(&msg);
+                let _ = connection.send(reply);
+                for signal in signals {
+                    let _ = connection.send(signal);
+                }
+                true
+            }));
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let service = {
+            let (connection, stop) = (connection.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let _ = connection.process(Duration::from_millis(20));
+                }
+            })
+        };
+
+        Self { daemon, dir, connection, state, stop, service: Some(service), _turn: turn }
+    }
+
+    pub fn adapter_name(&self) -> String {
+        self.state.lock().unwrap().adapter.clone()
+    }
+
+    /// The store manager::initiate opens for the adapter, inside the fake's own cache directory.
+    pub fn store(&self) -> DeviceStore {
+        DeviceStore::open_at(&store_dir(&self.adapter_name()))
+    }
+
+    /*
+     * A backend on a session of its own, as btui opens it
+    */
+    pub async fn backend(&self) -> BluerBackend {
+        let session = bluer::Session::new().await.expect("cannot open a session");
+        BluerBackend::new(&session, Some(&self.adapter_name())).await.expect("cannot open the adapter")
+    }
+
+    /// Changes a device property as the device would, e.g. "Connected" when it powers off.
+    pub fn set_device_property(&self, address: &str, name: &str, value: impl RefArg + 'static) {
+        let path = self.state.lock().unwrap().device_path(address);
+        let signal = self.state.lock().unwrap().set(&path, DEVICE, name, Variant(Box::new(value)));
+        let _ = self.connection.send(signal);
+    }
+
+    pub fn set_adapter_property(&self, name: &str, value: impl RefArg + 'static) {
+        let path = format!("/org/bluez/{}", self.adapter_name());
+        let signal = self.state.lock().unwrap().set(&path, ADAPTER, name, Variant(Box::new(value)));
+        let _ = self.connection.send(signal);
+    }
+
+    pub fn fail_next(&self, failure: Failure) {
+        self.state.lock().unwrap().failures.push(failure);
+    }
+
+    /// Whether BlueZ still knows the device.
+    pub fn has_device(&self, address: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.objects.contains_key(&state.device_path(address))
+    }
+
+    pub fn device_property<T: Copy + 'static>(&self, address: &str, name: &str) -> Option<T> {
+        let state = self.state.lock().unwrap();
+        let device = state.objects.get(&state.device_path(address))?;
+        device.get(DEVICE)?.get(name)?.0.as_any().downcast_ref::<T>().copied()
+    }
+
+    /// Method calls received so far, as "Method target" with the device address or adapter name.
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Object paths of the agents registered and not unregistered yet.
+    pub fn agents(&self) -> Vec<String> {
+        self.state.lock().unwrap().agents.clone()
+    }
+}
+
+impl Drop for FakeBluez {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(service) = self.service.take() {
+            let _ = service.join();
+        }
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+        let store = store_dir(&self.adapter_name());
+        let _ = fs::remove_dir_all(&store);
+        // only once the other tests' stores are gone as well
+        let _ = fs::remove_dir(store.parent().unwrap());
+        let _ = fs::remove_dir(cache_dir());
+    }
+}
+
+/*
+ * Points the system bus and $XDG_CACHE_HOME at directories of the tests, the real ones are never touched.
+ * Call it before anything opens a DeviceStore, FakeBluez::start does.
+*/
+pub fn isolate()
+{
+    ENVIRONMENT.call_once(|| {
+        // SAFETY: set once, before the tests open a connection to the system bus or a store
+        unsafe {
+            std::env::set_var("DBUS_SYSTEM_BUS_ADDRESS", format!("unix:path={}", bus_dir().join("system_bus_socket").display()));
+            std::env::set_var("XDG_CACHE_HOME", cache_dir());
+        }
+    });
+}
+
+/*
+ * Where DeviceStore::open keeps the devices of `adapter` once isolated
+*/
+pub fn store_dir(adapter: &str) -> PathBuf
+{
+    cache_dir().join("bluetooi").join(adapter)
+}
+
+fn bus_dir() -> PathBuf
+{
+    std::env::temp_dir().join(format!("btui-bus-{}", std::process::id()))
+}
+
+/*
+ * $XDG_CACHE_HOME of the tests, DeviceStore::open keeps the adapter's devices below it
+*/
+fn cache_dir() -> PathBuf
+{
+    std::env::temp_dir().join(format!("btui-cache-{}", std::process::id()))
+}
+
+fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>>
+{
+    Variant(Box::new(value))
+}
+
+fn clone_props(props: &PropMap) -> PropMap
+{
+    props.iter().map(|(name, value)| (name.clone(), Variant(value.0.box_clone()))).collect()
+}
+
+fn clone_interfaces(interfaces: &Interfaces) -> Interfaces
+{
+    interfaces.iter().map(|(name, props)| (name.clone(), clone_props(props))).collect()
+}
+
+fn error(msg: &Message, name: &str, text: &str) -> Message
+{
+    let name = ErrorName::new(format!("org.bluez.Error.{}", name)).unwrap();
+    msg.error(&name, &CString::new(text).unwrap())
+}
+
+fn dbus_error(msg: &Message, name: &str, text: &str) -> Message
+{
+    msg.error(&ErrorName::new(format!("org.freedesktop.DBus.Error.{}", name)).unwrap(), &CString::new(text).unwrap())
+}
+
+fn object_path(path: &str) -> Path<'static>
+{
+    Path::new(path.to_string()).unwrap()
+}
+
+impl BusState {
+    fn new(scenario: Scenario) -> Self {
+        let adapter = scenario.adapter;
+        let adapter_path = format!("/org/bluez/{}", adapter.name);
+        let mut state = Self { adapter: adapter.name.clone(), failures: scenario.failures, ..Default::default() };
+
+        let props = PropMap::from([
+            ("Address".to_string(), variant(adapter.address)),
+            ("AddressType".to_string(), variant("public".to_string())),
+            ("Name".to_string(), variant(adapter.name.clone())),
+            ("Alias".to_string(), variant(adapter.name.clone())),
+            ("Class".to_string(), variant(0u32)),
+            ("Powered".to_string(), variant(adapter.powered)),
+            ("Discoverable".to_string(), variant(false)),
+            ("DiscoverableTimeout".to_string(), variant(180u32)),
+            ("Pairable".to_string(), variant(true)),
+            ("PairableTimeout".to_string(), variant(0u32)),
+            ("Discovering".to_string(), variant(false)),
+        ]);
+        state.objects.insert(adapter_path.clone(), Interfaces::from([(ADAPTER.to_string(), props)]));
+        state.objects.insert("/org/bluez".to_string(), Interfaces::from([(AGENT_MANAGER.to_string(), PropMap::new())]));
+
+        for device in scenario.devices {
+            let path = state.device_path(&device.address);
+            let mut props = PropMap::from([
+                ("Address".to_string(), variant(device.address.clone())),
+                ("AddressType".to_string(), variant("public".to_string())),
+                ("Alias".to_string(), variant(device.name.clone().unwrap_or(device.address.replace(':', "-")))),
+                ("Adapter".to_string(), variant(object_path(&adapter_path))),
+                ("Paired".to_string(), variant(device.paired)),
+                ("Trusted".to_string(), variant(device.trusted)),
+                ("Blocked".to_string(), variant(device.blocked)),
+                ("Connected".to_string(), variant(device.connected)),
+                ("ServicesResolved".to_string(), variant(device.connected)),
+                ("LegacyPairing".to_string(), variant(false)),
+                ("UUIDs".to_string(), variant(device.uuids)),
+            ]);
+            if let Some(name) = device.name {
+                props.insert("Name".to_string(), variant(name));
+            }
+            if let Some(icon) = device.icon {
+                props.insert("Icon".to_string(), variant(icon));
+            }
+            if let Some(class) = device.class {
+                props.insert("Class".to_string(), variant(class));
+            }
+            if let Some(rssi) = device.rssi {
+                props.insert("RSSI".to_string(), variant(rssi));
+            }
+            let mut interfaces = Interfaces::from([(DEVICE.to_string(), props)]);
+            if let Some(battery) = device.battery {
+                interfaces.insert(BATTERY.to_string(), PropMap::from([("Percentage".to_string(), variant(battery))]));
+            }
+
+            if device.in_range {
+                state.in_range.push((path, interfaces));
+            } else {
+                state.objects.insert(path, interfaces);
+            }
+        }
+        state
+    }
+
+    fn device_path(&self, address: &str) -> String {
+        format!("/org/bluez/{}/dev_{}", self.adapter, address.replace(':', "_"))
+    }
+
+    /*
+     * Device address or adapter name, whichever the path is
+    */
+    fn target(&self, path: &str) -> String {
+        match path.rsplit_once("/dev_") {
+            Some((_, address)) => address.replace('_', ":"),
+            None => path.rsplit('/').next().unwrap_or_default().to_string(),
+        }
+    }
+
+    fn property(&self, path: &str, interface: &str, name: &str) -> Option<&Variant<Box<dyn RefArg>>> {
+        self.objects.get(path)?.get(interface)?.get(name)
+    }
+
+    fn is(&self, path: &str, interface: &str, name: &str) -> bool {
+        self.property(path, interface, name).and_then(|value| value.0.as_u64()).is_some_and(|value| value != 0)
+    }
+
+    /*
+     * Stores the value and returns the PropertiesChanged signal telling about it
+    */
+    fn set(&mut self, path: &str, interface: &str, name: &str, value: Variant<Box<dyn RefArg>>) -> Message {
+        let changed = PropMap::from([(name.to_string(), Variant(value.0.box_clone()))]);
+        if let Some(props) = self.objects.get_mut(path).and_then(|object| object.get_mut(interface)) {
+            props.insert(name.to_string(), value);
+        }
+        PropertiesPropertiesChanged {
+            interface_name: interface.to_string(),
+            changed_properties: changed,
+            invalidated_properties: vec![],
+        }.to_emit_message(&object_path(path))
+    }
+
+    fn take_failure(&mut self, method: &str, path: &str) -> Option<Failure> {
+        let target = self.target(path);
+        let index = self.failures.iter().position(|failure| {
+            failure.method == method && failure.device.as_ref().is_none_or(|device| device.eq_ignore_ascii_case(&target))
+        })?;
+        Some(self.failures.remove(index))
+    }
+
+    /*
+     * The reply to a method call, and the signals to send after it
+    */
+    fn handle(&mut self, msg: &Message) -> (Message, Vec<Message>) {
+        let (Some(path), Some(interface), Some(member)) = (msg.path(), msg.interface(), msg.member()) else {
+            return (dbus_error(msg, "UnknownMethod", "no method"), vec![]);
+        };
+        let (path, interface, member) = (path.to_string(), interface.to_string(), member.to_string());
+
+        if interface != PROPERTIES && !interface.starts_with("org.freedesktop.DBus") {
+            self.calls.push(format!("{} {}", member, self.target(&path)));
+            if let Some(failure) = self.take_failure(&member, &path) {
+                return (error(msg, &failure.error, &format!("{} failed", member)), vec![]);
+            }
+        }
+
+        match (interface.as_str(), member.as_str()) {
+            ("org.freedesktop.DBus.ObjectManager", "GetManagedObjects") => {
+                let objects: HashMap<Path<'static>, Interfaces> = self.objects.iter()
+                    .map(|(path, interfaces)| (object_path(path), clone_interfaces(interfaces)))
+                    .collect();
+                (msg.method_return().append1(objects), vec![])
+            }
+            (PROPERTIES, "Get") => {
+                let Ok((interface, name)) = msg.read2::<&str, &str>() else {
+                    return (dbus_error(msg, "InvalidArgs", "expected interface and name"), vec![]);
+                };
+                if !self.objects.contains_key(&path) {
+                    return (dbus_error(msg, "UnknownObject", &format!("no object {}", path)), vec![]);
+                }
+                match self.property(&path, interface, name) {
+                    Some(value) => (msg.method_return().append1(Variant(value.0.box_clone())), vec![]),
+                    None => (dbus_error(msg, "InvalidArgs", &format!("no property {}", name)), vec![]),
+                }
+            }
+            (PROPERTIES, "GetAll") => {
+                let Ok(interface) = msg.read1::<&str>() else {
+                    return (dbus_error(msg, "InvalidArgs", "expected interface"), vec![]);
+                };
+                match self.objects.get(&path) {
+                    Some(object) => (msg.method_return().append1(object.get(interface).map(clone_props).unwrap_or_default()), vec![]),
+                    None => (dbus_error(msg, "UnknownObject", &format!("no object {}", path)), vec![]),
+                }
+            }
+            (PROPERTIES, "Set") => {
+                let Ok((interface, name, value)) = msg.read3::<String, String, Variant<Box<dyn RefArg>>>() else {
+                    return (dbus_error(msg, "InvalidArgs", "expected interface, name and value"), vec![]);
+                };
+                if self.property(&path, &interface, &name).is_none() {
+                    return (dbus_error(msg, "InvalidArgs", &format!("no property {}", name)), vec![]);
+                }
+                self.calls.push(format!("Set{} {}", name, self.target(&path)));
+                let signal = self.set(&path, &interface, &name, value);
+                (msg.method_return(), vec![signal])
+            }
+            (ADAPTER, "SetDiscoveryFilter") => (msg.method_return(), vec![]),
+            (ADAPTER, "StartDiscovery") => {
+                if !self.is(&path, ADAPTER, "Powered") {
+                    return (error(msg, "NotReady", "Resource Not Ready"), vec![]);
+                }
+                let mut signals = vec![self.set(&path, ADAPTER, "Discovering", variant(true))];
+                for (device, interfaces) in std::mem::take(&mut self.in_range) {
+                    signals.push(ObjectManagerInterfacesAdded { object: object_path(&device), interfaces: clone_interfaces(&interfaces) }.to_emit_message(&object_path("/")));
+                    self.objects.insert(device, interfaces);
+                }
+                (msg.method_return(), signals)
+            }
+            (ADAPTER, "StopDiscovery") => {
+                let signal = self.set(&path, ADAPTER, "Discovering", variant(false));
+                (msg.method_return(), vec![signal])
+            }
+            (ADAPTER, "RemoveDevice") => {
+                let Ok(device) = msg.read1::<Path>() else {
+                    return (dbus_error(msg, "InvalidArgs", "expected a device"), vec![]);
+                };
+                match self.objects.remove(&*device) {
+                    Some(interfaces) => {
+                        let removed = ObjectManagerInterfacesRemoved { object: device.into_static(), interfaces: interfaces.into_keys().collect() };
+                        (msg.method_return(), vec![removed.to_emit_message(&object_path("/"))])
+                    }
+                    None => (error(msg, "DoesNotExist", "Does Not Exist"), vec![]),
+                }
+            }
+            (DEVICE, _) if !self.objects.contains_key(&path) => (error(msg, "DoesNotExist", "Does Not Exist"), vec![]),
+            (DEVICE, "Pair") => {
+                if self.is(&path, DEVICE, "Paired") {
+                    return (error(msg, "AlreadyExists", "Already Exists"), vec![]);
+                }
+                let signal = self.set(&path, DEVICE, "Paired", variant(true));
+                (msg.method_return(), vec![signal])
+            }
+            (DEVICE, "CancelPairing") => (msg.method_return(), vec![]),
+            (DEVICE, "Connect") => {
+                if self.is(&path, DEVICE, "Connected") {
+                    return (error(msg, "AlreadyConnected", "Already Connected"), vec![]);
+                }
+                let signals = vec![
+                    self.set(&path, DEVICE, "Connected", variant(true)),
+                    self.set(&path, DEVICE, "ServicesResolved", variant(true)),
+                ];
+                (msg.method_return(), signals)
+            }
+            (DEVICE, "Disconnect") => {
+                if !self.is(&path, DEVICE, "Connected") {
+                    return (error(msg, "NotConnected", "Not Connected"), vec![]);
+                }
+                let signals = vec![
+                    self.set(&path, DEVICE, "ServicesResolved", variant(false)),
+                    self.set(&path, DEVICE, "Connected", variant(false)),
+                ];
+                (msg.method_return(), signals)
+            }
+            (AGENT_MANAGER, "RegisterAgent") => {
+                if let Ok(agent) = msg.read1::<Path>() {
+                    self.agents.push(agent.to_string());
+                }
+                (msg.method_return(), vec![])
+            }
+            (AGENT_MANAGER, "RequestDefaultAgent") => (msg.method_return(), vec![]),
+            (AGENT_MANAGER, "UnregisterAgent") => {
+                if let Ok(agent) = msg.read1::<Path>() {
+                    self.agents.retain(|a| **a != *agent);
+                }
+                (msg.method_return(), vec![])
+            }
+            _ => (dbus_error(msg, "UnknownMethod", &format!("no method {}.{}", interface, member)), vec![]),
+        }
+    }
+}